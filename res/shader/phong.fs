@@ -3,8 +3,11 @@
 uniform vec3 u_Ka;
 uniform vec3 u_Kd;
 uniform float u_d;
+uniform int u_illum;
 uniform sampler2D u_map_Ka;
 uniform sampler2D u_map_Kd;
+uniform bool u_map_Ka_clamp;
+uniform bool u_map_Kd_clamp;
 
 uniform vec4 u_light_ambient;
 uniform vec4 u_light_diffuse;
@@ -14,9 +17,14 @@ in vec3 t_pos;
 in vec2 t_uv_Ka;
 in vec2 t_uv_Kd;
 in vec3 t_normal;
+in vec3 t_color;
+in vec3 t_tangent;
+in vec3 t_bitangent;
 
 out vec4 out_col;
 
+#include "texture.glsl"
+
 void main() {
 	// l points from the surface to the light
 	vec3 l = u_light_pos - t_pos;
@@ -24,7 +32,20 @@ void main() {
 	float diffuse_brightness = dot(t_normal, l) / (length(l) * length(t_normal));
 	diffuse_brightness = clamp(diffuse_brightness, 0.0, 1.0);
 
-	vec4 ambient = vec4(u_Ka, u_d) * texture2D(u_map_Ka, t_uv_Ka) * u_light_ambient;
-	vec4 diffuse = vec4(u_Kd, u_d) * texture2D(u_map_Kd, t_uv_Kd) * u_light_diffuse * diffuse_brightness;
+	vec4 map_Ka = sample_map(u_map_Ka, t_uv_Ka, u_map_Ka_clamp);
+	vec4 map_Kd = sample_map(u_map_Kd, t_uv_Kd, u_map_Kd_clamp);
+
+	// Vertex colours tint the material's colours
+	vec3 Ka = u_Ka * t_color;
+	vec3 Kd = u_Kd * t_color;
+
+	// Illumination model 0 is a constant colour
+	if (u_illum == 0) {
+		out_col = vec4(Kd, u_d) * map_Kd;
+		return;
+	}
+
+	vec4 ambient = vec4(Ka, u_d) * map_Ka * u_light_ambient;
+	vec4 diffuse = vec4(Kd, u_d) * map_Kd * u_light_diffuse * diffuse_brightness;
 	out_col = ambient + diffuse;
 }
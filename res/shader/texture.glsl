@@ -0,0 +1,4 @@
+// Samples a texture map. If `clamp_uv` is true, the texture doesn't repeat.
+vec4 sample_map(sampler2D map, vec2 uv, bool clamp_uv) {
+	return texture2D(map, clamp_uv ? clamp(uv, 0.0, 1.0) : uv);
+}
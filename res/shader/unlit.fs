@@ -3,13 +3,17 @@
 uniform vec3 u_color;
 uniform float u_d;
 uniform sampler2D u_map;
+uniform bool u_map_clamp;
 
 in vec3 t_pos;
 in vec2 t_uv;
 in vec3 t_normal;
+in vec3 t_color;
 
 out vec4 out_col;
 
+#include "texture.glsl"
+
 void main() {
-	out_col = vec4(u_color, u_d) * texture2D(u_map, t_uv);
+	out_col = vec4(u_color * t_color, u_d) * sample_map(u_map, t_uv, u_map_clamp);
 }
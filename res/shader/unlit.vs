@@ -1,28 +1,47 @@
 #version 440
 
+#ifdef INSTANCED
+uniform mat4 u_view_projection;
+// Each instance has its own model matrix
+in mat4 i_model_mat;
+#else
 uniform mat4 u_mvp;
 uniform mat4 u_model_mat;
+#endif
 
 uniform vec2 u_map_uv_scale;
+uniform vec2 u_map_uv_offset;
 
 in vec3 pos;
 in vec2 uv;
 in vec3 normal;
+in vec3 color;
 
 out vec3 t_pos;
 out vec2 t_uv;
 out vec3 t_normal;
+out vec3 t_color;
 
 void main() {
+#ifdef INSTANCED
+	mat4 model_mat = i_model_mat;
+	mat4 mvp = u_view_projection * i_model_mat;
+#else
+	mat4 model_mat = u_model_mat;
+	mat4 mvp = u_mvp;
+#endif
+
 	// Transform position into world space
-	t_pos = vec3(u_model_mat * vec4(pos, 1.0));
+	t_pos = vec3(model_mat * vec4(pos, 1.0));
 
 	// Calculate uvs
-	t_uv = uv * u_map_uv_scale;
+	t_uv = uv * u_map_uv_scale + u_map_uv_offset;
 
 	// Transform normals into world space
-	t_normal = vec3(u_model_mat * vec4(normal, 0.0));
+	t_normal = vec3(model_mat * vec4(normal, 0.0));
+
+	t_color = color;
 
 	// Set actual position
-	gl_Position = u_mvp * vec4(pos, 1.0);
+	gl_Position = mvp * vec4(pos, 1.0);
 }
@@ -0,0 +1,16 @@
+//! Checks the assets in `res/` for problems, and prints a report.
+//!
+//! Exits with a non-zero code if there are any errors, so that it can be used in scripts.
+extern crate Portal2;
+
+use std::process::exit;
+
+use Portal2::lint;
+
+pub fn main() {
+	let report = lint::lint();
+	print!("{}", report);
+	if report.errors() > 0 {
+		exit(1);
+	}
+}
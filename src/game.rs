@@ -132,6 +132,9 @@ impl Game {
 			// Tick world
 			self.world.tick(dt);
 			
+			// Upload the meshes & textures that have loaded in the background
+			self.ren.upload_loaded();
+			
 			// Clear frame
 			let mut frame = self.win.draw();
 			frame.clear_all((0.0, 0.0, 0.0, 1.0), 1.0, 0);
@@ -189,16 +192,14 @@ impl Game {
 					}
 				},
 				ReloadMeshes => {
+					// The meshes are loaded in the background, and are logged as they finish
 					info!(" === Reloading Meshes === ");
-					let t = Instant::now();
 					self.ren.reload_meshes();
-					info!(" === Reloaded Meshes ({}ms) === ", duration_to_millis(t.elapsed()));
 				},
 				ReloadTextures => {
+					// The textures are loaded in the background, and are logged as they finish
 					info!(" === Reloading Textures === ");
-					let t = Instant::now();
 					self.ren.reload_textures();
-					info!(" === Reloaded Textures ({}ms) === ", duration_to_millis(t.elapsed()));
 				}
 				ReloadShaders => {
 					info!(" === Reloading Shaders === ");
@@ -0,0 +1,19 @@
+#![allow(non_snake_case)]
+#![feature(type_ascription, slice_patterns)]
+extern crate cgmath as cg;
+#[macro_use]
+extern crate glium;
+pub use glium::glutin as glutin;
+extern crate png;
+#[macro_use]
+extern crate log;
+
+pub mod prelude;
+pub mod key;
+pub mod event;
+pub mod game;
+pub mod lint;
+pub mod render;
+pub mod result;
+pub mod vfs;
+pub mod world;
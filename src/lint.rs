@@ -0,0 +1,344 @@
+//! Checks the game's assets for problems, so that broken assets are found without having to run the game.
+//!
+//! This is used by the `asset_lint` binary, which checks everything in `res/mesh`, `res/tex` & `res/shader`.
+use prelude::*;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use render::{self, Material, TextureID, MESH_DIR, TEX_DIR, SHADER_DIR};
+use render::parse::{self, GltfFile, ObjFile, ParseError, ParseMode, Preprocessor};
+use vfs;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+	Warning,
+	Error,
+}
+
+/// A problem found with an asset
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+	pub severity: Severity,
+	/// Path of the asset, relative to the executable
+	pub path: PathBuf,
+	pub message: String,
+	/// The error that the asset failed to parse with, which has the line the problem is on
+	pub parse_error: Option<ParseError>,
+}
+impl fmt::Display for Diagnostic {
+	/// Displays the diagnostic in the same format as `ParseError`s
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		let severity = match self.severity {
+			Severity::Warning => "warning",
+			Severity::Error => "error",
+		};
+		writeln!(f, "{}: {}", severity, self.message)?;
+		match self.parse_error {
+			Some(ref e) => e.fmt_location(f),
+			None => write!(f, "  --> {}", self.path.display()),
+		}
+	}
+}
+
+/// The problems found with the assets
+#[derive(Debug, Default)]
+pub struct Report {
+	pub diagnostics: Vec<Diagnostic>,
+	/// Number of files that were checked
+	pub files: usize,
+}
+impl Report {
+	pub fn errors(&self) -> usize {
+		self.diagnostics.iter().filter(|d| d.severity == Severity::Error).count()
+	}
+
+	pub fn warnings(&self) -> usize {
+		self.diagnostics.iter().filter(|d| d.severity == Severity::Warning).count()
+	}
+
+	fn push<P: AsRef<Path>>(&mut self, severity: Severity, path: P, message: String) {
+		self.diagnostics.push(Diagnostic {
+			severity: severity,
+			path: path.as_ref().to_path_buf(),
+			message: message,
+			parse_error: None,
+		});
+	}
+
+	fn error<P: AsRef<Path>>(&mut self, path: P, message: String) {
+		self.push(Severity::Error, path, message);
+	}
+
+	fn warning<P: AsRef<Path>>(&mut self, path: P, message: String) {
+		self.push(Severity::Warning, path, message);
+	}
+
+	fn parse_error(&mut self, e: ParseError) {
+		self.diagnostics.push(Diagnostic {
+			severity: Severity::Error,
+			path: e.path.clone(),
+			message: e.kind.to_string(),
+			parse_error: Some(e),
+		});
+	}
+}
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		for d in self.diagnostics.iter() {
+			writeln!(f, "{}\n", d)?;
+		}
+		writeln!(f, "{} files checked: {} errors, {} warnings", self.files, self.errors(), self.warnings())
+	}
+}
+
+/// Checks all of the meshes, materials, textures & shaders
+pub fn lint() -> Report {
+	let mut l = Linter::default();
+
+	let meshes = l.list_dir(MESH_DIR);
+	for rel_path in meshes.iter() {
+		if let Some(ext) = render::mesh_extension(rel_path) {
+			l.lint_mesh(rel_path, ext);
+		}
+	}
+	// Material libraries that are used have already been checked along with their meshes
+	for rel_path in meshes.iter().filter(|p| p.ends_with(".mtl")) {
+		if !l.mtllibs.contains(Path::new(rel_path)) {
+			l.lint_mtl(rel_path);
+		}
+	}
+
+	for rel_path in l.list_dir(TEX_DIR).iter().filter(|p| p.ends_with(".png")) {
+		l.report.files += 1;
+		let (key, _) = l.check_texture(rel_path);
+		if !l.used_textures.contains(&key) {
+			l.report.warning(rel_path, "texture is not used by any material".into());
+		}
+	}
+
+	let shaders = l.list_dir(SHADER_DIR);
+	for rel_path in shaders.iter() {
+		if let Some(ext) = Path::new(rel_path).extension().and_then(|e| e.to_str()) {
+			if SHADER_EXTENSIONS.contains(&ext) {
+				l.lint_shader(rel_path, ext);
+			}
+		}
+	}
+	// Other files in the shader directory should be included by a shader
+	for rel_path in shaders.iter().filter(|p| p.ends_with(".glsl")) {
+		if !l.includes.contains(Path::new(rel_path)) {
+			l.report.files += 1;
+			l.report.warning(rel_path, "shader file is not included by any shader".into());
+		}
+	}
+	l.report
+}
+
+/// Extensions of the shaders in `res/shader`. Programs are made of the `.vs`, `.tcs`, `.tes`, `.gs` & `.fs` files with the same name.
+const SHADER_EXTENSIONS: &'static [&'static str] = &["vs", "tcs", "tes", "gs", "fs", "comp"];
+
+#[derive(Default)]
+struct Linter {
+	report: Report,
+	/// Textures that have been loaded (by absolute path), and whether that worked
+	textures: HashMap<PathBuf, Result<(), String>>,
+	/// Absolute paths of the textures used by materials
+	used_textures: HashSet<PathBuf>,
+	/// Material libraries used by meshes, relative to the executable
+	mtllibs: HashSet<PathBuf>,
+	/// Files included by shaders, relative to the executable
+	includes: HashSet<PathBuf>,
+}
+impl Linter {
+	/// Lists the files in a directory relative to the executable, sorted by name
+	fn list_dir(&mut self, rel_dir: &str) -> Vec<String> {
+		let mut ret: Vec<String> = match fs::read_dir(vfs::canonicalize_exe(rel_dir)) {
+			Ok(it) => it
+				.filter_map(|f| f.ok())
+				.filter(|f| f.file_type().map(|t| t.is_file()).unwrap_or(false))
+				.map(|f| rel_dir.to_string() + &f.file_name().to_string_lossy())
+				.collect(),
+			Err(e) => {
+				self.report.error(rel_dir, format!("could not read directory: {}", e));
+				vec![]
+			}
+		};
+		ret.sort();
+		ret
+	}
+
+	fn lint_mesh(&mut self, rel_path: &str, ext: &str) {
+		self.report.files += 1;
+		let file = match ext {
+			"gltf" | "glb" => {
+				match GltfFile::new(rel_path.to_string()) {
+					Ok(f) => self.lint_gltf(&f),
+					Err(e) => self.report.parse_error(e),
+				}
+				return;
+			},
+			"stl" => ObjFile::load_stl(rel_path.to_string()),
+			"ply" => ObjFile::load_ply(rel_path.to_string()),
+			_ => ObjFile::new(rel_path.to_string(), ParseMode::Strict),
+		};
+		let f = match file {
+			Ok(f) => f,
+			Err(e) => return self.report.parse_error(e),
+		};
+		for mtllib in f.mtllibs.iter() {
+			if self.mtllibs.insert(mtllib.clone()) {
+				self.report.files += 1;
+			}
+		}
+
+		let tris: Vec<[usize; 3]> = f.faces.iter()
+			.map(|face| [face.x.vert as usize, face.y.vert as usize, face.z.vert as usize])
+			.collect();
+		self.check_triangles(Path::new(rel_path), &f.vertices, &tris);
+
+		let no_material: usize = f.ranges.iter().filter(|r| r.material.is_none()).map(|r| r.len).sum();
+		if no_material > 0 {
+			self.report.warning(rel_path, format!("{} faces have no material", no_material));
+		}
+		let mut names: Vec<&String> = f.materials.keys().collect();
+		names.sort();
+		for name in names {
+			self.check_material(Path::new(rel_path), name, &f.materials[name]);
+		}
+	}
+
+	fn lint_gltf(&mut self, f: &GltfFile) {
+		let rel_path = Path::new(&f.rel_path);
+		for p in f.primitives.iter() {
+			let tris: Vec<[usize; 3]> = p.indices.chunks(3)
+				.map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+				.collect();
+			self.check_triangles(rel_path, &p.positions, &tris);
+			if p.material.is_none() {
+				let name = p.name.as_ref().map(|s| &s[..]).unwrap_or("unnamed");
+				self.report.warning(rel_path, format!("primitive `{}` has no material", name));
+			}
+		}
+		// Embedded images aren't files, so they are checked before the materials that use them
+		for &(ref id, ref png) in f.images.iter() {
+			let key = vfs::canonicalize_exe(render::normalize_id(id.clone()));
+			self.textures.insert(key, render::decode_png(id, png).map(|_| ()));
+		}
+		for (i, m) in f.materials.iter().enumerate() {
+			self.check_material(rel_path, &i.to_string(), m);
+		}
+	}
+
+	/// Checks a material library that isn't used by any mesh
+	fn lint_mtl(&mut self, rel_path: &str) {
+		self.report.files += 1;
+		let rel_path = Path::new(rel_path);
+		match parse::load_mtl(rel_path, ParseMode::Strict) {
+			Ok(materials) => {
+				let mut names: Vec<&String> = materials.keys().collect();
+				names.sort();
+				for name in names {
+					self.check_material(rel_path, name, &materials[name]);
+				}
+				self.report.warning(rel_path, "material library is not used by any mesh".into());
+			},
+			Err(e) => self.report.parse_error(e),
+		}
+	}
+
+	/// Checks that a shader can be preprocessed, and that the other shaders that its program needs exist
+	fn lint_shader(&mut self, rel_path: &str, ext: &str) {
+		self.report.files += 1;
+		let mut pp = Preprocessor::new(&[]);
+		let mut read = |p: &Path| {
+			let mut s = String::new();
+			File::open(vfs::canonicalize_exe(p)).and_then(|mut f| f.read_to_string(&mut s)).map(|_| s)
+		};
+		if let Err(e) = pp.process(Path::new(rel_path), &mut read) {
+			self.report.error(rel_path, e);
+		}
+		for p in pp.files.into_iter().skip(1) {
+			if self.includes.insert(p) {
+				self.report.files += 1;
+			}
+		}
+		let required: &[(&str, &str)] = match ext {
+			"comp" => &[],
+			"tcs" => &[("vs", "vertex"), ("tes", "tessellation evaluation"), ("fs", "fragment")],
+			_ => &[("vs", "vertex"), ("fs", "fragment")],
+		};
+		for &(other, kind) in required.iter().filter(|&&(other, _)| other != ext) {
+			let other = Path::new(rel_path).with_extension(other);
+			if !vfs::canonicalize_exe(&other).is_file() {
+				self.report.error(rel_path, format!("{} shader is missing ({})", kind, other.display()));
+			}
+		}
+	}
+
+	/// Checks that all of the textures that a material uses can be loaded
+	fn check_material(&mut self, rel_path: &Path, name: &str, m: &Material) {
+		for id in m.textures() {
+			let (key, res) = self.check_texture(id);
+			self.used_textures.insert(key);
+			if let Err(e) = res {
+				self.report.error(rel_path, format!("texture `{}` used by material `{}` could not be loaded: {}", id, name, e));
+			}
+		}
+	}
+
+	/// Loads a texture, if it hasn't been already. Returns the absolute path of the texture, and whether it loaded.
+	fn check_texture(&mut self, id: &TextureID) -> (PathBuf, Result<(), String>) {
+		let key = vfs::canonicalize_exe(render::normalize_id(id.clone()));
+		let res = self.textures.entry(key.clone())
+			.or_insert_with(|| render::load_png(id).map(|_| ()))
+			.clone();
+		(key, res)
+	}
+
+	/// Checks the triangles of a mesh for degenerate triangles, and vertices that aren't used by any triangle
+	fn check_triangles(&mut self, rel_path: &Path, positions: &[Vec3], tris: &[[usize; 3]]) {
+		let mut used = vec![false; positions.len()];
+		let mut degenerate = 0;
+		for t in tris.iter() {
+			for &i in t.iter() {
+				used[i] = true;
+			}
+			let (e1, e2) = (positions[t[1]] - positions[t[0]], positions[t[2]] - positions[t[0]]);
+			// Triangles with no area, or with an angle too small to be seen
+			if e1.cross(e2).magnitude2() <= 1e-12 * e1.magnitude2() * e2.magnitude2() {
+				degenerate += 1;
+			}
+		}
+		if degenerate > 0 {
+			self.report.warning(rel_path, format!("{} degenerate triangles", degenerate));
+		}
+		let unused = used.iter().filter(|&&u| !u).count();
+		if unused > 0 {
+			self.report.warning(rel_path, format!("{} vertices are not used by any face", unused));
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	use std::path::Path;
+
+	use super::{Linter, Severity};
+
+	#[test]
+	fn test_check_triangles() {
+		let mut l = Linter::default();
+		let ps = [vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(2.0, 0.0, 0.0), vec3(5.0, 5.0, 5.0)];
+		l.check_triangles(Path::new("test.obj"), &ps, &[[0, 1, 2], [0, 1, 3], [2, 2, 0]]);
+		let messages: Vec<&str> = l.report.diagnostics.iter().map(|d| &d.message[..]).collect();
+		assert_eq!(messages, vec!["2 degenerate triangles", "1 vertices are not used by any face"]);
+		assert!(l.report.diagnostics.iter().all(|d| d.severity == Severity::Warning));
+		assert_eq!(l.report.errors(), 0);
+	}
+}
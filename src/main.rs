@@ -1,29 +1,15 @@
-#![allow(non_snake_case)]
-#![feature(type_ascription, slice_patterns)]
-extern crate cgmath as cg;
-#[macro_use]
-extern crate glium;
-pub use glium::glutin as glutin;
-extern crate png;
+extern crate Portal2;
 #[macro_use]
 extern crate log;
 extern crate simplelog;
 
-pub mod prelude;
-pub mod key;
-pub mod event;
-pub mod game;
-pub mod render;
-pub mod result;
-pub mod vfs;
-pub mod world;
-
-use prelude::*;
+use Portal2::prelude::*;
 
 use std::io::{self, Write};
 use std::process::exit;
 
-use game::Game;
+use Portal2::game::Game;
+use Portal2::render;
 
 pub fn parse_log_level() -> Option<simplelog::LogLevelFilter> {
 	use std::ascii::AsciiExt;
@@ -62,6 +48,19 @@ pub fn main() {
 }
 
 pub fn run() -> GameResult<()> {
-	let mut game = Game::new()?;
-	game.run()
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	match args.get(0).map(|s| &s[..]) {
+		Some("--bake-meshes") => {
+			// Bake the mesh caches, instead of running the game
+			let dir = args.get(1).map(|s| &s[..]).unwrap_or(render::MESH_DIR);
+			let n = render::mesh_cache::bake_dir(dir)?;
+			info!("{} meshes baked.", n);
+			Ok(())
+		},
+		Some(arg) => Err(format!("Unknown argument: {}", arg)),
+		None => {
+			let mut game = Game::new()?;
+			game.run()
+		}
+	}
 }
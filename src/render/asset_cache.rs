@@ -0,0 +1,323 @@
+use prelude::*;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// How many frames an asset is kept for after it was last used, even if the cache is over its budget
+pub const KEEP_FRAMES: u64 = 3;
+
+/// How a cache deals with assets that failed to load
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RetryPolicy {
+	/// How many times an asset is loaded again after failing, before its error is kept until the cache is cleared
+	pub max_retries: u32,
+	/// How long to wait before the first retry. The wait doubles after each failure.
+	pub delay: Duration,
+}
+impl Default for RetryPolicy {
+	fn default() -> RetryPolicy {
+		RetryPolicy {
+			max_retries: 3,
+			delay: Duration::from_secs(1),
+		}
+	}
+}
+
+/// Counts of what a cache has done, e.g. for a debug overlay
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+	/// Lookups of assets that had loaded, or failed to
+	pub hits: u64,
+	/// Lookups of assets that weren't in the cache, or whose error was due to be retried
+	pub misses: u64,
+	/// Assets that were dropped to keep within the budget
+	pub evictions: u64,
+	/// Estimated GPU memory used by the assets in the cache
+	pub bytes_resident: usize,
+	/// Number of assets in the cache, including failed ones
+	pub entries: usize,
+}
+impl ::std::ops::Add for CacheStats {
+	type Output = CacheStats;
+
+	fn add(self, rhs: CacheStats) -> CacheStats {
+		CacheStats {
+			hits: self.hits + rhs.hits,
+			misses: self.misses + rhs.misses,
+			evictions: self.evictions + rhs.evictions,
+			bytes_resident: self.bytes_resident + rhs.bytes_resident,
+			entries: self.entries + rhs.entries,
+		}
+	}
+}
+
+enum Entry<T> {
+	Loaded {
+		value: Rc<T>,
+		size: usize,
+		/// Value of the cache's clock when the asset was last looked up
+		last_used: u64,
+		/// Frame that the asset was last looked up in
+		last_frame: u64,
+	},
+	Failed {
+		error: String,
+		/// How many times in a row the asset has failed to load
+		failures: u32,
+		/// When the asset should be loaded again, or None if it has run out of retries
+		retry_at: Option<Instant>,
+	},
+}
+
+/// Loaded assets of a bank, kept within a memory budget.
+///
+/// When the assets take up more than the budget, the least recently used ones are dropped. Assets that have been used in
+/// the last `KEEP_FRAMES` frames, or that something else holds an `Rc` to, are never dropped, so the budget can be
+/// exceeded if they don't fit.
+pub struct AssetCache<T> {
+	entries: HashMap<String, Entry<T>>,
+	budget: usize,
+	retry: RetryPolicy,
+	stats: CacheStats,
+	/// Counts lookups, to order the assets by when they were last used
+	clock: u64,
+	frame: u64,
+}
+impl<T> AssetCache<T> {
+	/// Creates an empty cache with a budget in bytes
+	pub fn new(budget: usize) -> AssetCache<T> {
+		AssetCache {
+			entries: HashMap::new(),
+			budget: budget,
+			retry: RetryPolicy::default(),
+			stats: CacheStats::default(),
+			clock: 0,
+			frame: 0,
+		}
+	}
+
+	/// Looks up an asset.
+	///
+	/// Returns None if the asset isn't in the cache, or if it failed to load and is due to be retried. In both cases
+	/// it should be loaded again.
+	pub fn get(&mut self, id: &str) -> Option<GameResult<Rc<T>>> {
+		self.clock += 1;
+		let res = match self.entries.get_mut(id) {
+			Some(&mut Entry::Loaded { ref value, ref mut last_used, ref mut last_frame, .. }) => {
+				*last_used = self.clock;
+				*last_frame = self.frame;
+				Some(Ok(value.clone()))
+			},
+			Some(&mut Entry::Failed { ref error, retry_at, .. }) => match retry_at {
+				Some(t) if Instant::now() >= t => None,
+				_ => Some(Err(error.clone())),
+			},
+			None => None,
+		};
+		if res.is_some() {
+			self.stats.hits += 1;
+		} else {
+			self.stats.misses += 1;
+		}
+		res
+	}
+
+	/// Checks if an asset is in the cache, whether it loaded or not
+	pub fn contains(&self, id: &str) -> bool {
+		self.entries.contains_key(id)
+	}
+
+	/// Adds an asset that has loaded (or failed to), along with how many bytes it takes up, and drops unused assets
+	/// if the cache is over budget.
+	///
+	/// The asset counts as used in the current frame, so an asset that is bigger than the whole budget is still kept.
+	pub fn insert(&mut self, id: String, res: GameResult<Rc<T>>, size: usize) {
+		// Failures are only counted in a row, so that an asset that is fixed & broken again gets all of its retries
+		let failures = match self.entries.get(&id) {
+			Some(&Entry::Failed { failures, .. }) => failures,
+			_ => 0,
+		};
+		self.remove(&id);
+		self.clock += 1;
+		let entry = match res {
+			Ok(value) => {
+				if size > self.budget {
+					warn!("{} takes up {} bytes, which is more than the cache's budget of {} bytes", id, size, self.budget);
+				}
+				self.stats.bytes_resident += size;
+				Entry::Loaded { value: value, size: size, last_used: self.clock, last_frame: self.frame }
+			},
+			Err(error) => {
+				let retry_at = if failures < self.retry.max_retries {
+					Some(Instant::now() + self.retry.delay * 2u32.pow(failures.min(16)))
+				} else {
+					None
+				};
+				Entry::Failed { error: error, failures: failures + 1, retry_at: retry_at }
+			},
+		};
+		self.entries.insert(id, entry);
+		self.stats.entries = self.entries.len();
+		self.evict();
+	}
+
+	/// Removes an asset from the cache
+	fn remove(&mut self, id: &str) {
+		if let Some(Entry::Loaded { size, .. }) = self.entries.remove(id) {
+			self.stats.bytes_resident -= size;
+		}
+		self.stats.entries = self.entries.len();
+	}
+
+	/// Starts a new frame. Assets that were used in the last `KEEP_FRAMES` frames aren't dropped.
+	pub fn next_frame(&mut self) {
+		self.frame += 1;
+	}
+
+	/// Drops the least recently used assets that aren't in use until the cache is within its budget
+	pub fn evict(&mut self) {
+		let frame = self.frame;
+		while self.stats.bytes_resident > self.budget {
+			let lru = self.entries.iter()
+				.filter_map(|(id, e)| match e {
+					&Entry::Loaded { ref value, last_used, last_frame, .. }
+						if frame - last_frame >= KEEP_FRAMES && Rc::strong_count(value) == 1 => Some((last_used, id)),
+					_ => None,
+				})
+				.min()
+				.map(|(_, id)| id.clone());
+			match lru {
+				Some(id) => {
+					debug!("Evicting {} from the cache", id);
+					self.remove(&id);
+					self.stats.evictions += 1;
+				},
+				None => break, // Everything left is in use, or was used recently
+			}
+		}
+	}
+
+	/// Forgets about every asset
+	pub fn clear(&mut self) {
+		self.entries.clear();
+		self.stats.bytes_resident = 0;
+		self.stats.entries = 0;
+	}
+
+	/// Gets the IDs of every asset in the cache, including failed ones
+	pub fn ids(&self) -> Vec<String> {
+		self.entries.keys().cloned().collect()
+	}
+
+	/// Sets the budget in bytes, dropping unused assets if the cache is now over it
+	pub fn set_budget(&mut self, budget: usize) {
+		self.budget = budget;
+		self.evict();
+	}
+
+	/// Sets how failed loads are retried. Assets that have already failed keep their current retry time.
+	pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+		self.retry = retry;
+	}
+
+	pub fn stats(&self) -> CacheStats {
+		self.stats
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::rc::Rc;
+	use std::time::Duration;
+
+	use super::{AssetCache, RetryPolicy, KEEP_FRAMES};
+
+	#[test]
+	fn test_evict() {
+		let mut cache = AssetCache::new(100);
+		cache.insert("a".into(), Ok(Rc::new(1)), 40);
+		cache.insert("b".into(), Ok(Rc::new(2)), 40);
+		// "a" is used more recently than "b", and "b" is held onto
+		assert_eq!(cache.get("a"), Some(Ok(Rc::new(1))));
+		let b = cache.get("b").unwrap().unwrap();
+		cache.get("a");
+		for _ in 0..KEEP_FRAMES {
+			cache.next_frame();
+		}
+		cache.insert("c".into(), Ok(Rc::new(3)), 40);
+		assert!(!cache.contains("a"));
+		assert!(cache.contains("b") && cache.contains("c"));
+		assert_eq!(cache.stats().evictions, 1);
+		assert_eq!(cache.stats().bytes_resident, 80);
+
+		// Nothing can be dropped while it is in use
+		let c = cache.get("c").unwrap().unwrap();
+		cache.set_budget(10);
+		assert_eq!(cache.stats().bytes_resident, 80);
+		drop((b, c));
+		cache.evict();
+		// "c" was used this frame
+		assert_eq!(cache.stats().bytes_resident, 40);
+		for _ in 0..KEEP_FRAMES {
+			cache.next_frame();
+		}
+		cache.evict();
+		assert_eq!(cache.stats().bytes_resident, 0);
+		assert_eq!(cache.stats().evictions, 3);
+		assert_eq!(cache.get("b"), None);
+		assert_eq!(cache.stats().hits, 4);
+		assert_eq!(cache.stats().misses, 1);
+	}
+
+	#[test]
+	fn test_evict_used_every_frame() {
+		// Assets are only held onto while they are drawn, so those drawn every frame must be kept even if over budget
+		let mut cache = AssetCache::new(100);
+		cache.insert("a".into(), Ok(Rc::new(1)), 60);
+		cache.insert("b".into(), Ok(Rc::new(2)), 60);
+		for _ in 0..10 {
+			cache.next_frame();
+			cache.evict();
+			assert_eq!(cache.get("a"), Some(Ok(Rc::new(1))));
+			assert_eq!(cache.get("b"), Some(Ok(Rc::new(2))));
+		}
+		assert_eq!(cache.stats().evictions, 0);
+
+		// Once "b" stops being drawn it is dropped
+		for _ in 0..KEEP_FRAMES {
+			cache.next_frame();
+			cache.evict();
+			cache.get("a");
+		}
+		assert!(cache.contains("a") && !cache.contains("b"));
+
+		// An asset bigger than the budget is kept while it is drawn
+		cache.insert("c".into(), Ok(Rc::new(3)), 1000);
+		cache.next_frame();
+		cache.evict();
+		assert_eq!(cache.get("c"), Some(Ok(Rc::new(3))));
+		assert_eq!(cache.stats().bytes_resident, 1060);
+	}
+
+	#[test]
+	fn test_retry() {
+		let mut cache: AssetCache<u32> = AssetCache::new(100);
+		cache.set_retry_policy(RetryPolicy { max_retries: 2, delay: Duration::from_secs(0) });
+		cache.insert("a".into(), Err("bad".into()), 0);
+		assert_eq!(cache.get("a"), None);
+		cache.insert("a".into(), Err("bad".into()), 0);
+		assert_eq!(cache.get("a"), None);
+		// Out of retries
+		cache.insert("a".into(), Err("bad".into()), 0);
+		assert_eq!(cache.get("a"), Some(Err("bad".into())));
+
+		// Waits before retrying
+		cache.set_retry_policy(RetryPolicy { max_retries: 2, delay: Duration::from_secs(60) });
+		cache.insert("b".into(), Err("bad".into()), 0);
+		assert_eq!(cache.get("b"), Some(Err("bad".into())));
+		cache.insert("b".into(), Ok(Rc::new(1)), 10);
+		assert_eq!(cache.get("b"), Some(Ok(Rc::new(1))));
+		assert_eq!(cache.stats().entries, 2);
+	}
+}
@@ -0,0 +1,141 @@
+use prelude::*;
+
+/// An axis-aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+	pub min: Vec3,
+	pub max: Vec3,
+}
+impl Aabb {
+	/// Gets the smallest box containing all of the points. If there are no points, the box is a point at the origin.
+	pub fn from_points(points: &[Vec3]) -> Aabb {
+		if points.is_empty() {
+			return Aabb { min: zero(), max: zero() };
+		}
+		let mut ret = Aabb { min: points[0], max: points[0] };
+		for p in points[1..].iter() {
+			ret.min = vec3(ret.min.x.min(p.x), ret.min.y.min(p.y), ret.min.z.min(p.z));
+			ret.max = vec3(ret.max.x.max(p.x), ret.max.y.max(p.y), ret.max.z.max(p.z));
+		}
+		ret
+	}
+
+	pub fn centre(&self) -> Vec3 {
+		(self.min + self.max) * 0.5
+	}
+
+	/// Gets the box containing this box after it has been transformed by `m`
+	pub fn transform(&self, m: Mat4) -> Aabb {
+		let centre = (m * self.centre().extend(1.0)).truncate();
+		let half = (self.max - self.min) * 0.5;
+		// Each axis of the new box is as long as the transformed axes of the old box are along it
+		let extent = vec3(
+			m.x.x.abs() * half.x + m.y.x.abs() * half.y + m.z.x.abs() * half.z,
+			m.x.y.abs() * half.x + m.y.y.abs() * half.y + m.z.y.abs() * half.z,
+			m.x.z.abs() * half.x + m.y.z.abs() * half.y + m.z.z.abs() * half.z);
+		Aabb { min: centre - extent, max: centre + extent }
+	}
+}
+
+/// A sphere containing a mesh, which is quicker to test than an `Aabb`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+	pub centre: Vec3,
+	pub radius: Flt,
+}
+impl BoundingSphere {
+	/// Gets a sphere containing all of the points, centred on their bounding box
+	pub fn from_points(points: &[Vec3]) -> BoundingSphere {
+		let centre = Aabb::from_points(points).centre();
+		let radius2 = points.iter().fold(0.0 as Flt, |r, &p| r.max((p - centre).magnitude2()));
+		BoundingSphere { centre: centre, radius: radius2.sqrt() }
+	}
+
+	/// Gets the sphere containing this sphere after it has been transformed by `m`
+	pub fn transform(&self, m: Mat4) -> BoundingSphere {
+		let scale = m.x.truncate().magnitude()
+			.max(m.y.truncate().magnitude())
+			.max(m.z.truncate().magnitude());
+		BoundingSphere {
+			centre: (m * self.centre.extend(1.0)).truncate(),
+			radius: self.radius * scale,
+		}
+	}
+}
+
+/// The volume that a camera can see
+pub struct Frustum {
+	/// Planes facing into the frustum, as (normal, distance). Points inside have `dot(normal, p) + distance >= 0`.
+	planes: [Vec4; 6],
+}
+impl Frustum {
+	/// Gets the frustum of a view-projection matrix, limited to a region of the screen.
+	///
+	/// `min` & `max` are the corners of the region in normalized device coordinates, where the whole screen is
+	/// from (-1, -1) to (1, 1).
+	pub fn new(m: Mat4, min: Vec2, max: Vec2) -> Frustum {
+		let (r0, r1, r2, r3) = (m.row(0), m.row(1), m.row(2), m.row(3));
+		Frustum {
+			planes: [
+				r0 - r3 * min.x,
+				r3 * max.x - r0,
+				r1 - r3 * min.y,
+				r3 * max.y - r1,
+				r3 + r2,
+				r3 - r2,
+			],
+		}
+	}
+
+	/// Checks if the sphere is at least partly inside the frustum
+	pub fn intersects_sphere(&self, s: &BoundingSphere) -> bool {
+		self.planes.iter().all(|p| {
+			let n = p.truncate();
+			n.dot(s.centre) + p.w >= -s.radius * n.magnitude()
+		})
+	}
+
+	/// Checks if the box is at least partly inside the frustum. Boxes near the corners of the frustum may be let through.
+	pub fn intersects_aabb(&self, b: &Aabb) -> bool {
+		self.planes.iter().all(|p| {
+			// The corner of the box furthest along the normal
+			let corner = vec3(
+				if p.x >= 0.0 { b.max.x } else { b.min.x },
+				if p.y >= 0.0 { b.max.y } else { b.min.y },
+				if p.z >= 0.0 { b.max.z } else { b.min.z });
+			p.truncate().dot(corner) + p.w >= 0.0
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	use super::{Aabb, BoundingSphere, Frustum};
+
+	#[test]
+	fn test_frustum() {
+		// Looking down -z from the origin
+		let proj = Mat4::from(PerspectiveFov { fovy: Rad::from(Deg(90.0)), aspect: 1.0, near: 0.1, far: 100.0 });
+		let full = Frustum::new(proj, vec2(-1.0, -1.0), vec2(1.0, 1.0));
+		let points = [vec3(-1.0, -1.0, -1.0), vec3(1.0, 1.0, 1.0)];
+		let (b, s) = (Aabb::from_points(&points), BoundingSphere::from_points(&points));
+		assert_eq!(s.centre, vec3(0.0, 0.0, 0.0));
+		assert!((s.radius - 3.0f32.sqrt()).abs() < 1e-6);
+
+		let visible = |f: &Frustum, m: Mat4| f.intersects_sphere(&s.transform(m)) && f.intersects_aabb(&b.transform(m));
+		assert!(visible(&full, Mat4::from_translation(vec3(0.0, 0.0, -10.0))));
+		// Behind the camera, past the far plane & off to the side
+		assert!(!visible(&full, Mat4::from_translation(vec3(0.0, 0.0, 10.0))));
+		assert!(!visible(&full, Mat4::from_translation(vec3(0.0, 0.0, -200.0))));
+		assert!(!visible(&full, Mat4::from_translation(vec3(20.0, 0.0, -10.0))));
+		// Scaling makes the box reach back into view
+		assert!(visible(&full, Mat4::from_translation(vec3(20.0, 0.0, -10.0)) * Mat4::from_scale(15.0)));
+
+		// Only the right half of the screen
+		let right = Frustum::new(proj, vec2(0.0, -1.0), vec2(1.0, 1.0));
+		assert!(visible(&right, Mat4::from_translation(vec3(5.0, 0.0, -10.0))));
+		assert!(!visible(&right, Mat4::from_translation(vec3(-5.0, 0.0, -10.0))));
+	}
+}
@@ -0,0 +1,188 @@
+use prelude::*;
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of threads that each `Loader` uses
+pub const LOADER_THREADS: usize = 2;
+
+/// A job that has finished: the ID it was requested with, its ticket, the result and how long it took
+pub type Finished<R> = (String, u64, GameResult<R>, Duration);
+
+/// Runs jobs on background threads, so that reading & parsing files doesn't stop the game.
+///
+/// Each job is given a ticket when it is requested, so that results that are no longer wanted (e.g. because the
+/// asset was requested again) can be told apart from the latest one.
+pub struct Loader<J, R> {
+	jobs: Sender<(String, u64, J)>,
+	results: Receiver<Finished<R>>,
+	next_ticket: u64,
+}
+impl<J: Send + 'static, R: Send + 'static> Loader<J, R> {
+	/// Starts the threads of the loader. `work` is run on one of them for each job.
+	pub fn new(name: &str, work: fn(&str, J) -> GameResult<R>) -> Loader<J, R> {
+		let (jobs_tx, jobs_rx) = mpsc::channel::<(String, u64, J)>();
+		let (results_tx, results_rx) = mpsc::channel();
+		let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+		for i in 0..LOADER_THREADS {
+			let jobs_rx = jobs_rx.clone();
+			let results_tx = results_tx.clone();
+			let res = thread::Builder::new()
+				.name(format!("{} loader {}", name, i))
+				.spawn(move || loop {
+					// The lock is only held while waiting for a job, so that the other threads can take the next one
+					let job = match jobs_rx.lock() {
+						Ok(rx) => rx.recv(),
+						Err(_) => return,
+					};
+					let (id, ticket, job) = match job {
+						Ok(job) => job,
+						Err(_) => return, // The loader has been dropped
+					};
+					let t_start = Instant::now();
+					// A panic is sent back as an error, so that the thread carries on & the job isn't left pending forever
+					let res = panic::catch_unwind(AssertUnwindSafe(|| work(&id, job)))
+						.unwrap_or_else(|e| Err(format!("Panicked while loading: {}", panic_message(&e))));
+					if results_tx.send((id, ticket, res, t_start.elapsed())).is_err() {
+						return;
+					}
+				});
+			if let Err(e) = res {
+				warn!("Could not start {} loader thread: {}", name, e);
+			}
+		}
+		Loader {
+			jobs: jobs_tx,
+			results: results_rx,
+			next_ticket: 0,
+		}
+	}
+
+	/// Queues a job, returning its ticket
+	pub fn request(&mut self, id: String, job: J) -> u64 {
+		let ticket = self.next_ticket;
+		self.next_ticket += 1;
+		if self.jobs.send((id.clone(), ticket, job)).is_err() {
+			warn!("Could not load {}: all of the loader threads have stopped", id);
+		}
+		ticket
+	}
+
+	/// Gets the jobs that have finished since this was last called
+	pub fn finished(&self) -> Vec<Finished<R>> {
+		self.results.try_iter().collect()
+	}
+}
+
+/// Gets the message that a thread panicked with
+fn panic_message(e: &Box<Any + Send>) -> String {
+	if let Some(s) = e.downcast_ref::<&str>() {
+		s.to_string()
+	} else if let Some(s) = e.downcast_ref::<String>() {
+		s.clone()
+	} else {
+		"unknown error".into()
+	}
+}
+
+/// How many of the assets that have been requested have finished loading
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+	pub loaded: usize,
+	pub requested: usize,
+}
+impl LoadProgress {
+	/// Checks if everything that was requested has loaded (or failed to)
+	pub fn is_done(&self) -> bool {
+		self.loaded >= self.requested
+	}
+
+	/// Fraction of the assets that have loaded, from 0 to 1
+	pub fn fraction(&self) -> Flt {
+		if self.requested == 0 {
+			1.0
+		} else {
+			self.loaded as Flt / self.requested as Flt
+		}
+	}
+}
+impl ::std::ops::Add for LoadProgress {
+	type Output = LoadProgress;
+
+	fn add(self, rhs: LoadProgress) -> LoadProgress {
+		LoadProgress {
+			loaded: self.loaded + rhs.loaded,
+			requested: self.requested + rhs.requested,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::thread;
+	use std::time::Duration;
+
+	use super::{Loader, LOADER_THREADS};
+
+	fn parse(id: &str, add: u32) -> Result<u32, String> {
+		if id == "panic" {
+			panic!("test panic");
+		}
+		id.parse::<u32>().map(|x| x + add).map_err(|e| e.to_string())
+	}
+
+	#[test]
+	fn test_loader() {
+		let mut loader = Loader::new("test", parse);
+		let tickets: Vec<u64> = (0..10).map(|i| loader.request(i.to_string(), 100)).collect();
+		let t_bad = loader.request("x".into(), 0);
+		assert_eq!(tickets, (0..10).collect::<Vec<_>>());
+
+		let mut results = vec![];
+		for _ in 0..1000 {
+			results.extend(loader.finished());
+			if results.len() == 11 {
+				break;
+			}
+			thread::sleep(Duration::from_millis(1));
+		}
+		assert_eq!(results.len(), 11);
+		results.sort_by_key(|r| r.1);
+		for (i, r) in results[..10].iter().enumerate() {
+			assert_eq!((&r.0[..], r.1, r.2.clone()), (&i.to_string()[..], i as u64, Ok(i as u32 + 100)));
+		}
+		assert_eq!(results[10].1, t_bad);
+		assert!(results[10].2.is_err());
+	}
+
+	#[test]
+	fn test_loader_panic() {
+		// Panics are sent back as errors, and don't stop the threads
+		let mut loader = Loader::new("test", parse);
+		for _ in 0..LOADER_THREADS * 2 {
+			loader.request("panic".into(), 0);
+		}
+		let t_ok = loader.request("1".into(), 1);
+
+		let mut results = vec![];
+		for _ in 0..1000 {
+			results.extend(loader.finished());
+			if results.len() == LOADER_THREADS * 2 + 1 {
+				break;
+			}
+			thread::sleep(Duration::from_millis(1));
+		}
+		assert_eq!(results.len(), LOADER_THREADS * 2 + 1);
+		for r in results.iter() {
+			if r.1 == t_ok {
+				assert_eq!(r.2, Ok(2));
+			} else {
+				assert_eq!(r.2, Err("Panicked while loading: test panic".to_string()));
+			}
+		}
+	}
+}
@@ -0,0 +1,336 @@
+//! Levels of detail: simplifying meshes, and choosing which level to draw.
+//!
+//! Meshes are simplified with quadric error metrics (Garland & Heckbert), by repeatedly collapsing the edge that
+//! changes the shape of the mesh the least into one of its vertices. As vertices are only ever removed, the
+//! simplified levels can share the vertex buffer of the full mesh.
+use prelude::*;
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use super::Vertex;
+
+/// How the levels of detail of a mesh are generated and chosen between
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LodSettings {
+	/// Maximum number of simplified levels, not including the full mesh
+	pub levels: usize,
+	/// Fraction of the triangles of the previous level that each level keeps
+	pub reduction: Flt,
+	/// Meshes aren't simplified below this many triangles
+	pub min_triangles: usize,
+	/// Fraction of the screen height that a mesh has to be smaller than for the first simplified level to be used.
+	///
+	/// Each level after that is used when the mesh is half the size again.
+	pub screen_size: Flt,
+	/// The projected size is multiplied by this before choosing a level. Above 1 keeps detail for longer.
+	pub bias: Flt,
+	/// Whether the levels are saved in the mesh cache, rather than being generated every time the mesh is loaded
+	pub cache: bool,
+}
+impl LodSettings {
+	/// Checks if the same levels would be generated with these settings as with `other`
+	pub fn generates_same(&self, other: &LodSettings) -> bool {
+		self.levels == other.levels && self.reduction == other.reduction && self.min_triangles == other.min_triangles
+	}
+
+	/// Chooses a level for a mesh taking up `screen_size` of the screen's height, out of `count` levels
+	/// (including the full mesh).
+	pub fn select(&self, screen_size: Flt, count: usize) -> usize {
+		let size = screen_size * self.bias;
+		if count <= 1 || !(size < self.screen_size) {
+			return 0;
+		}
+		let level = if size > 0.0 { (self.screen_size / size).log2().floor() as usize + 1 } else { count - 1 };
+		level.min(count - 1)
+	}
+}
+impl Default for LodSettings {
+	fn default() -> LodSettings {
+		LodSettings {
+			levels: 3,
+			reduction: 0.5,
+			min_triangles: 64,
+			screen_size: 0.25,
+			bias: 1.0,
+			cache: true,
+		}
+	}
+}
+
+/// How much more removing a boundary edge costs than moving a surface by the same distance
+const BOUNDARY_WEIGHT: f64 = 100.0;
+
+/// Error of moving a point away from a set of planes, as a symmetric 4x4 matrix.
+///
+/// Stores the upper triangle of the matrix, row by row.
+#[derive(Debug, Copy, Clone)]
+struct Quadric([f64; 10]);
+impl Quadric {
+	fn zero() -> Quadric {
+		Quadric([0.0; 10])
+	}
+
+	/// Quadric of the plane through `p` with the unit normal `n`, scaled by `w`
+	fn plane(n: Vec3, p: Vec3, w: f64) -> Quadric {
+		let (a, b, c) = (n.x as f64, n.y as f64, n.z as f64);
+		let d = -(a * p.x as f64 + b * p.y as f64 + c * p.z as f64);
+		let q = [a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d];
+		let mut ret = [0.0; 10];
+		for (r, x) in ret.iter_mut().zip(q.iter()) {
+			*r = x * w;
+		}
+		Quadric(ret)
+	}
+
+	fn add(&mut self, other: &Quadric) {
+		for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+			*a += *b;
+		}
+	}
+
+	/// Sum of the squared distances from `p` to the planes
+	fn error(&self, p: Vec3) -> f64 {
+		let q = &self.0;
+		let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+		q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+			+ q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+			+ q[7] * z * z + 2.0 * q[8] * z
+			+ q[9]
+	}
+}
+
+/// Collapsing the edge between two points into `to`.
+///
+/// The versions are of the points when the cost was calculated, so that costs that are out of date can be skipped.
+struct Collapse {
+	cost: f64,
+	from: usize,
+	to: usize,
+	versions: (u32, u32),
+}
+impl PartialEq for Collapse {
+	fn eq(&self, other: &Collapse) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+impl Eq for Collapse {}
+impl PartialOrd for Collapse {
+	fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+impl Ord for Collapse {
+	/// The cheapest collapse is the greatest, so that it is at the top of a `BinaryHeap`
+	fn cmp(&self, other: &Collapse) -> Ordering {
+		other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+			.then_with(|| other.from.cmp(&self.from))
+			.then_with(|| other.to.cmp(&self.to))
+	}
+}
+
+/// Simplifies a triangle mesh until it has at most `target` triangles, or can't be simplified any more without
+/// folding triangles over.
+///
+/// Returns the index of each remaining triangle in `indices`, and its new vertices. The triangles are in the same
+/// order as they were in `indices`.
+pub fn simplify(vertices: &[Vertex], indices: &[u32], target: usize) -> Vec<(usize, [u32; 3])> {
+	// Vertices at the same position are simplified as one point, so that seams in uvs & normals aren't torn open
+	let mut points: Vec<Vec3> = vec![];
+	let mut point_of = Vec::with_capacity(vertices.len());
+	let mut vertices_of: Vec<Vec<usize>> = vec![];
+	{
+		let mut map: HashMap<[u32; 3], usize> = HashMap::new();
+		for (i, v) in vertices.iter().enumerate() {
+			let pos = v.pos();
+			let key = [pos[0].to_bits(), pos[1].to_bits(), pos[2].to_bits()];
+			let p = *map.entry(key).or_insert_with(|| {
+				points.push(Vec3::from(pos));
+				vertices_of.push(vec![]);
+				points.len() - 1
+			});
+			point_of.push(p);
+			vertices_of[p].push(i);
+		}
+	}
+
+	let mut tris: Vec<[usize; 3]> = indices.chunks(3)
+		.map(|t| [point_of[t[0] as usize], point_of[t[1] as usize], point_of[t[2] as usize]])
+		.collect();
+	let mut alive: Vec<bool> = tris.iter().map(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0]).collect();
+	let mut live = alive.iter().filter(|&&a| a).count();
+	let mut tris_of: Vec<Vec<usize>> = vec![vec![]; points.len()];
+	let mut quadrics = vec![Quadric::zero(); points.len()];
+	let mut edges: HashMap<(usize, usize), u32> = HashMap::new();
+	for (i, t) in tris.iter().enumerate().filter(|&(i, _)| alive[i]) {
+		let n = (points[t[1]] - points[t[0]]).cross(points[t[2]] - points[t[0]]);
+		let area = n.magnitude();
+		for k in 0..3 {
+			tris_of[t[k]].push(i);
+			if area > 0.0 {
+				quadrics[t[k]].add(&Quadric::plane(n / area, points[t[k]], area as f64));
+			}
+			let (a, b) = (t[k], t[(k + 1) % 3]);
+			*edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+		}
+	}
+	// Edges on the boundary of the mesh get a plane at a right angle to their triangle, so that the boundary stays put
+	for t in tris.iter().enumerate().filter(|&(i, _)| alive[i]).map(|(_, t)| t) {
+		let n = (points[t[1]] - points[t[0]]).cross(points[t[2]] - points[t[0]]);
+		for k in 0..3 {
+			let (a, b) = (t[k], t[(k + 1) % 3]);
+			if edges[&(a.min(b), a.max(b))] != 1 {
+				continue;
+			}
+			let edge = points[b] - points[a];
+			let side = edge.cross(n);
+			let len = side.magnitude();
+			if len > 0.0 {
+				let q = Quadric::plane(side / len, points[a], BOUNDARY_WEIGHT * edge.magnitude2() as f64);
+				quadrics[a].add(&q);
+				quadrics[b].add(&q);
+			}
+		}
+	}
+
+	let mut removed = vec![false; points.len()];
+	let mut versions = vec![0u32; points.len()];
+	let mut heap = BinaryHeap::new();
+	{
+		let push = |heap: &mut BinaryHeap<Collapse>, quadrics: &[Quadric], versions: &[u32], from: usize, to: usize| {
+			let mut q = quadrics[from];
+			q.add(&quadrics[to]);
+			heap.push(Collapse { cost: q.error(points[to]), from: from, to: to, versions: (versions[from], versions[to]) });
+		};
+		for &(a, b) in edges.keys() {
+			push(&mut heap, &quadrics, &versions, a, b);
+			push(&mut heap, &quadrics, &versions, b, a);
+		}
+
+		while live > target {
+			let c = match heap.pop() {
+				Some(c) => c,
+				None => break,
+			};
+			let (from, to) = (c.from, c.to);
+			if removed[from] || removed[to] || c.versions != (versions[from], versions[to]) {
+				continue;
+			}
+			// Don't collapse if it would flip any of the triangles around `from` over
+			let flips = tris_of[from].iter()
+				.filter(|&&t| alive[t] && !tris[t].contains(&to))
+				.any(|&t| {
+					let p = |k: usize| if tris[t][k] == from { points[to] } else { points[tris[t][k]] };
+					let old = (points[tris[t][1]] - points[tris[t][0]]).cross(points[tris[t][2]] - points[tris[t][0]]);
+					let new = (p(1) - p(0)).cross(p(2) - p(0));
+					new.dot(old) <= 0.0
+				});
+			if flips {
+				continue;
+			}
+
+			let from_tris = ::std::mem::replace(&mut tris_of[from], vec![]);
+			for t in from_tris {
+				if !alive[t] {
+					continue;
+				}
+				if tris[t].contains(&to) {
+					alive[t] = false;
+					live -= 1;
+				} else {
+					for p in tris[t].iter_mut() {
+						if *p == from {
+							*p = to;
+						}
+					}
+					tris_of[to].push(t);
+				}
+			}
+			let q = quadrics[from];
+			quadrics[to].add(&q);
+			removed[from] = true;
+			versions[to] += 1;
+			tris_of[to].retain(|&t| alive[t]);
+
+			let mut neighbours: Vec<usize> = tris_of[to].iter().flat_map(|&t| tris[t].iter().cloned()).filter(|&p| p != to).collect();
+			neighbours.sort();
+			neighbours.dedup();
+			for n in neighbours {
+				push(&mut heap, &quadrics, &versions, to, n);
+				push(&mut heap, &quadrics, &versions, n, to);
+			}
+		}
+	}
+
+	// Each corner uses the vertex at its new point that is most like its old vertex, to keep uv & normal seams
+	let closest = |old: usize, p: usize| -> u32 {
+		if point_of[old] == p {
+			return old as u32;
+		}
+		let (uv, normal) = (Vec2::from(vertices[old].uv()), Vec3::from(vertices[old].normal()));
+		let diff = |&v: &usize| (Vec2::from(vertices[v].uv()) - uv).magnitude2() + (Vec3::from(vertices[v].normal()) - normal).magnitude2();
+		*vertices_of[p].iter()
+			.min_by(|a, b| diff(a).partial_cmp(&diff(b)).unwrap_or(Ordering::Equal))
+			.unwrap() as u32
+	};
+	tris.iter().enumerate()
+		.filter(|&(i, _)| alive[i])
+		.map(|(i, t)| {
+			let old = &indices[i * 3..i * 3 + 3];
+			(i, [closest(old[0] as usize, t[0]), closest(old[1] as usize, t[1]), closest(old[2] as usize, t[2])])
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	use render::Vertex;
+
+	use super::{simplify, LodSettings};
+
+	#[test]
+	fn test_simplify_plane() {
+		// A flat 8x8 grid, which can be simplified without changing its shape at all
+		let n = 8;
+		let mut vertices = vec![];
+		for y in 0..n + 1 {
+			for x in 0..n + 1 {
+				let (u, v) = (x as Flt / n as Flt, y as Flt / n as Flt);
+				vertices.push(Vertex::new([u, 0.0, v], [u, v], [0.0, 1.0, 0.0], [1.0; 3], [1.0, 0.0, 0.0, 1.0]));
+			}
+		}
+		let mut indices = vec![];
+		for y in 0..n {
+			for x in 0..n {
+				let i = y * (n + 1) + x;
+				indices.extend_from_slice(&[i, i + n + 1, i + 1, i + 1, i + n + 1, i + n + 2]);
+			}
+		}
+
+		let tris = simplify(&vertices, &indices, 8);
+		assert!(tris.len() <= 8, "{} triangles left", tris.len());
+		// The triangles are kept in order, still face up, and still cover the whole plane
+		assert!(tris.windows(2).all(|w| w[0].0 < w[1].0));
+		let mut area = 0.0;
+		for &(_, t) in tris.iter() {
+			let p = |i: u32| Vec3::from(vertices[i as usize].pos());
+			let n = (p(t[1]) - p(t[0])).cross(p(t[2]) - p(t[0]));
+			assert!(n.y > 0.0);
+			area += n.magnitude() / 2.0;
+		}
+		assert!((area - 1.0).abs() < 1e-4, "area is {}", area);
+	}
+
+	#[test]
+	fn test_select() {
+		let s = LodSettings::default();
+		assert_eq!(s.select(1.0, 4), 0);
+		assert_eq!(s.select(0.2, 4), 1);
+		assert_eq!(s.select(0.1, 4), 2);
+		assert_eq!(s.select(0.001, 4), 3);
+		assert_eq!(s.select(0.001, 1), 0);
+		assert_eq!(LodSettings { bias: 2.0, ..s }.select(0.2, 4), 0);
+	}
+}
@@ -4,19 +4,36 @@ use std::collections::HashMap;
 use std::rc::Rc;
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::mem;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 use glium::backend::Context;
 use glium::VertexBuffer;
-use glium::index::{PrimitiveType, IndicesSource, IndexBuffer, IndexBufferAny};
+use glium::index::{PrimitiveType, IndicesSource, IndexBuffer};
 
 use game::duration_to_millis;
-use super::Material;
+use super::{Material, TextureID};
+use super::asset_cache::{AssetCache, CacheStats, RetryPolicy};
+use super::bounds::{Aabb, BoundingSphere};
+use super::loader::{Loader, LoadProgress};
+use super::lod::{self, LodSettings};
+use super::primitive::Primitive;
+use super::mesh_cache;
 use super::normalize_id;
+use super::parse::{ObjFile, GltfFile, ParseMode};
+use super::tangent;
+use vfs::FileStamp;
 
 /// Meshes are identified by their filename
 pub type MeshID = String;
 
 pub const MESH_DIR: &'static str = "res/mesh/";
+/// Default GPU memory budget of the meshes in a `MeshBank`, in bytes
+pub const DEFAULT_MESH_BUDGET: usize = 128 * 1024 * 1024;
+/// Extensions of the mesh files that can be loaded
+pub const MESH_EXTENSIONS: &'static [&'static str] = &["obj", "gltf", "glb", "stl", "ply"];
 pub const MESHID_AXES_TEST: &'static str = "res/mesh/axes_test.obj";
 pub const MESHID_MONKEY: &'static str = "res/mesh/monkey.obj";
 pub const MESHID_TEAPOT: &'static str = "res/mesh/teapot.obj";
@@ -24,10 +41,29 @@ pub const MESHID_FLOOR: &'static str = "res/mesh/floor.obj";
 pub const MESHID_EARTH: &'static str = "res/mesh/earth.obj";
 pub const MESHID_PORTAL: &'static str = "res/mesh/portal.obj";
 
+/// Gets the extension of a mesh file, if it is one of `MESH_EXTENSIONS`
+pub fn mesh_extension(path: &str) -> Option<&'static str> {
+	let ext = match path.rfind('.') {
+		Some(i) => path[i + 1..].to_lowercase(),
+		None => return None,
+	};
+	MESH_EXTENSIONS.iter().find(|&&e| e == ext).cloned()
+}
+
 pub struct MeshBank {
 	ctx: Rc<Context>,
-	cache: HashMap<MeshID, GameResult<Rc<Mesh>>>,
+	cache: AssetCache<Mesh>,
+	loader: Loader<LodSettings, MeshData>,
+	/// Meshes being loaded in the background, and the ticket of the latest request for each
+	pending: HashMap<MeshID, u64>,
+	progress: LoadProgress,
+	/// Level of detail settings of meshes that don't use the default ones
+	lod_settings: HashMap<MeshID, LodSettings>,
+	/// Images embedded in meshes that have loaded, which haven't been given to the `TextureBank` yet
+	images: Vec<(TextureID, Vec<u8>)>,
 	default_mesh: Rc<Mesh>,
+	/// Drawn in place of meshes that failed to load
+	error_mesh: Rc<Mesh>,
 	portal_stencil_mesh: Rc<Mesh>,
 }
 impl MeshBank {
@@ -35,22 +71,36 @@ impl MeshBank {
 		let def_buf = VertexBuffer::new(&ctx, &vec![])
 			.map_err(|e| format!("Could not initialize MeshBank: OpenGL buffer creation error: {}", e))?;
 
+		let (aabb, sphere) = bounds(&[]);
 		let def = Mesh {
-			material: Material::default(),
+			submeshes: vec![SubMesh::new(None, Material::default(), 0..0)],
 			vertices: def_buf,
 			indices: None,
+			aabb: aabb,
+			bounding_sphere: sphere,
+			lods: vec![],
+			lod_settings: LodSettings::default(),
 		};
 
+		let error = Mesh::from_data(&ctx, &MeshData::error_cube(), "error mesh")
+			.map_err(|e| format!("Could not initialize MeshBank: {}", e))?;
+
 		// Generate portal mesh
 		let p = MeshBank::generate_portal_stencil_mesh(&ctx)?;
 
 		let mut mb = MeshBank {
 			ctx: ctx,
-			cache: HashMap::new(),
+			cache: AssetCache::new(DEFAULT_MESH_BUDGET),
+			loader: Loader::new("mesh", |id, settings| MeshData::load(id, &settings)),
+			pending: HashMap::new(),
+			progress: LoadProgress::default(),
+			lod_settings: HashMap::new(),
+			images: vec![],
 			default_mesh: Rc::new(def),
+			error_mesh: Rc::new(error),
 			portal_stencil_mesh: Rc::new(p),
 		};
-		mb.load_meshes();
+		mb.request_meshes();
 		Ok(mb)
 	}
 
@@ -62,10 +112,10 @@ impl MeshBank {
 		// | |
 		// 3-2
 		let p_verts = vec![
-			Vertex { pos:[-0.5,  0.5, 0.0], uv:[0.0, 0.0], normal:[0.0, 0.0, 1.0] },
-			Vertex { pos:[ 0.5,  0.5, 0.0], uv:[1.0, 0.0], normal:[0.0, 0.0, 1.0] },
-			Vertex { pos:[ 0.5, -0.5, 0.0], uv:[1.0, 1.0], normal:[0.0, 0.0, 1.0] },
-			Vertex { pos:[-0.5, -0.5, 0.0], uv:[0.0, 1.0], normal:[0.0, 0.0, 1.0] },
+			Vertex { pos:[-0.5,  0.5, 0.0], uv:[0.0, 0.0], normal:[0.0, 0.0, 1.0], color:[1.0, 1.0, 1.0], tangent:[1.0, 0.0, 0.0, 1.0] },
+			Vertex { pos:[ 0.5,  0.5, 0.0], uv:[1.0, 0.0], normal:[0.0, 0.0, 1.0], color:[1.0, 1.0, 1.0], tangent:[1.0, 0.0, 0.0, 1.0] },
+			Vertex { pos:[ 0.5, -0.5, 0.0], uv:[1.0, 1.0], normal:[0.0, 0.0, 1.0], color:[1.0, 1.0, 1.0], tangent:[1.0, 0.0, 0.0, 1.0] },
+			Vertex { pos:[-0.5, -0.5, 0.0], uv:[0.0, 1.0], normal:[0.0, 0.0, 1.0], color:[1.0, 1.0, 1.0], tangent:[1.0, 0.0, 0.0, 1.0] },
 		];
 
 		let p_buf = VertexBuffer::new(ctx, &p_verts)
@@ -74,16 +124,35 @@ impl MeshBank {
 		let p_indices = IndexBuffer::new(ctx, PrimitiveType::TrianglesList, &vec![0u8,1,2, 0,2,3, 0,2,1, 0,3,2])
 			.map_err(|e| format!("Could not initialize MeshBank: OpenGL buffer creation error: {}", e))?;
 
+		let (aabb, sphere) = bounds(&p_verts);
 		Ok(Mesh {
-			material: p_mat,
+			submeshes: vec![SubMesh::new(None, p_mat, 0..p_indices.len())],
 			vertices: p_buf,
-			indices: Some(Box::new(IndexBufferAny::from(p_indices))),
+			indices: Some(MeshIndices::U8(p_indices)),
+			aabb: aabb,
+			bounding_sphere: sphere,
+			lods: vec![],
+			lod_settings: LodSettings::default(),
 		})
 	}
 
-	/// Clears the mesh cache
+	/// Clears the mesh cache. Meshes that are being loaded are forgotten about.
 	pub fn clear_cache(&mut self) {
 		self.cache.clear();
+		self.pending.clear();
+		self.progress = LoadProgress::default();
+	}
+
+	/// Loads every mesh that has been loaded before again, along with any new ones in the MESH_DIR directory.
+	///
+	/// The old meshes are used until the new ones have loaded.
+	pub fn reload(&mut self) {
+		self.pending.clear();
+		self.progress = LoadProgress::default();
+		for id in self.cache.ids() {
+			self.request(id);
+		}
+		self.request_meshes();
 	}
 
 	/// The default mesh
@@ -91,49 +160,157 @@ impl MeshBank {
 		self.default_mesh.clone()
 	}
 
+	/// The error mesh (a magenta cube with a "?" on each face)
+	pub fn error_mesh(&self) -> Rc<Mesh> {
+		self.error_mesh.clone()
+	}
+
 	/// Gets the portal stencil mesh
 	pub fn portal_stencil_mesh(&self) -> Rc<Mesh> {
 		self.portal_stencil_mesh.clone()
 	}
 
+	/// Gets the level of detail settings of a mesh
+	pub fn lod_settings(&self, id: &str) -> LodSettings {
+		self.lod_settings.get(&normalize_id(id.to_string())).cloned().unwrap_or_default()
+	}
+
+	/// Sets the level of detail settings of a mesh. If the mesh has already been requested, it is loaded again.
+	pub fn set_lod_settings(&mut self, id: MeshID, settings: LodSettings) {
+		let id = normalize_id(id);
+		self.lod_settings.insert(id.clone(), settings);
+		if self.cache.contains(&id) || self.pending.contains_key(&id) {
+			self.pending.remove(&id);
+			self.request(id);
+		}
+	}
+
+	/// How many of the meshes that have been requested have loaded
+	pub fn progress(&self) -> LoadProgress {
+		self.progress
+	}
+
+	/// Sets how much GPU memory the meshes may take up, in bytes. Meshes that haven't been drawn in the last
+	/// few frames are dropped, least recently drawn first, to keep within it.
+	pub fn set_budget(&mut self, bytes: usize) {
+		self.cache.set_budget(bytes);
+	}
+
+	/// Sets how meshes that failed to load are retried
+	pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+		self.cache.set_retry_policy(retry);
+	}
+
+	/// Gets the hits, misses, evictions & memory use of the mesh cache
+	pub fn stats(&self) -> CacheStats {
+		self.cache.stats()
+	}
+
 	/// Gets a mesh from the MeshBank.
 	///
-	/// If there was an error, returns a default mesh (No vertices)
+	/// If the mesh is still loading, or there was an error, returns a default mesh (No vertices). Meshes that haven't
+	/// been requested yet (or were evicted, or are due to be retried) are loaded in the background.
 	pub fn get_mesh_or_default(&mut self, id: MeshID) -> Rc<Mesh> {
-		self.get_mesh(id.clone())
-			.unwrap_or(self.default_mesh())
+		let id = normalize_id(id);
+		match self.cache.get(&id) {
+			Some(Ok(mesh)) => return mesh,
+			Some(Err(_)) => return self.default_mesh(),
+			None => {},
+		}
+		self.request(id);
+		self.default_mesh()
+	}
+
+	/// Gets a mesh from the MeshBank, or the error mesh if it failed to load.
+	///
+	/// If the mesh is still loading, returns the default mesh (No vertices), the same as `get_mesh_or_default`.
+	pub fn get_mesh_or_error(&mut self, id: MeshID) -> Rc<Mesh> {
+		let id = normalize_id(id);
+		match self.cache.get(&id) {
+			Some(Ok(mesh)) => return mesh,
+			Some(Err(_)) => return self.error_mesh(),
+			None => {},
+		}
+		self.request(id);
+		self.default_mesh()
 	}
 
-	/// Gets a mesh from the MeshBank
+	/// Gets a mesh from the MeshBank. If it hasn't been loaded yet, this waits for it to load.
 	pub fn get_mesh(&mut self, id: MeshID) -> GameResult<Rc<Mesh>> {
 		// Normalize id first
 		let id = normalize_id(id);
 		// If cache doesn't exist, loads it from a file.
-		if self.cache.get(&id).is_none() {
-			use std::time::Instant;
-			let t_start = Instant::now();
-			let res = match Mesh::from_file(&self.ctx, &id).map(|t| Rc::new(t)) {
-				Ok(t) => {
-					info!("Loaded mesh: {} ({}ms)", &id, duration_to_millis(t_start.elapsed()));
-					Ok(t)
-				},
-				Err(e) => {
-					warn!("Could not load mesh ({}): {}", &id, &e);
-					Err(e)
-				}
-			};
-			self.cache.insert(id.clone(), res);
+		if let Some(res) = self.cache.get(&id) {
+			return res;
 		}
-		self.cache.get(&id).unwrap().clone()
+		let t_start = Instant::now();
+		let settings = self.lod_settings(&id);
+		let res = MeshData::load(&id, &settings).and_then(|data| self.upload(data, &id));
+		self.insert(id, res.clone(), t_start.elapsed());
+		res
 	}
 
-	/// Loads a mesh into the MeshBank
+	/// Loads a mesh into the MeshBank, waiting for it to load
 	pub fn load_mesh(&mut self, id: MeshID) -> GameResult<()> {
 		self.get_mesh(id).map(|_| ())
 	}
 
-	/// Loads all of the meshes in the MESH_DIR directory
-	pub fn load_meshes(&mut self) {
+	/// Starts loading a mesh in the background, if it isn't already being loaded.
+	///
+	/// Meshes that are already loaded are loaded again.
+	pub fn request(&mut self, id: MeshID) {
+		let id = normalize_id(id);
+		if self.pending.contains_key(&id) {
+			return;
+		}
+		let settings = self.lod_settings(&id);
+		let ticket = self.loader.request(id.clone(), settings);
+		self.pending.insert(id, ticket);
+		self.progress.requested += 1;
+	}
+
+	/// Uploads the meshes that have finished loading in the background to OpenGL
+	pub fn upload_loaded(&mut self) {
+		self.cache.next_frame();
+		for (id, ticket, res, elapsed) in self.loader.finished() {
+			// Skip meshes that have been requested again since, or forgotten about
+			if self.pending.get(&id) != Some(&ticket) {
+				continue;
+			}
+			self.pending.remove(&id);
+			self.progress.loaded += 1;
+			let t_start = Instant::now();
+			let res = res.and_then(|data| self.upload(data, &id));
+			self.insert(id, res, elapsed + t_start.elapsed());
+		}
+		// Meshes that have stopped being drawn may need to be dropped
+		self.cache.evict();
+	}
+
+	/// Uploads a mesh that has loaded, keeping its embedded images for `take_images`
+	fn upload(&mut self, mut data: MeshData, id: &str) -> GameResult<Rc<Mesh>> {
+		self.images.append(&mut data.images);
+		Mesh::from_data(&self.ctx, &data, id).map(|t| Rc::new(t))
+	}
+
+	/// Takes the images embedded in the meshes that have loaded since this was last called, so that they can be given
+	/// to the `TextureBank`
+	pub fn take_images(&mut self) -> Vec<(TextureID, Vec<u8>)> {
+		mem::replace(&mut self.images, vec![])
+	}
+
+	/// Adds a mesh that has loaded (or failed to) to the cache
+	fn insert(&mut self, id: MeshID, res: GameResult<Rc<Mesh>>, elapsed: Duration) {
+		match res {
+			Ok(_) => info!("Loaded mesh: {} ({}ms)", &id, duration_to_millis(elapsed)),
+			Err(ref e) => warn!("Could not load mesh ({}): {}", &id, e),
+		}
+		let size = res.as_ref().map(|mesh| mesh.size_bytes()).unwrap_or(0);
+		self.cache.insert(id, res, size);
+	}
+
+	/// Starts loading all of the meshes in the MESH_DIR directory in the background
+	pub fn request_meshes(&mut self) {
 		use std::fs;
 		use vfs;
 
@@ -152,10 +329,10 @@ impl MeshBank {
 			match file {
 				Ok(f) => {
 					let id = MESH_DIR.to_string() + &f.file_name().to_string_lossy().into_owned();
-					if !id.ends_with(".obj") {
+					if mesh_extension(&id).is_none() {
 						continue;
 					}
-					self.load_mesh(id.clone()).ok();
+					self.request(id);
 				},
 				_ => {} // Ignore files that return an error when iterating over them
 			}
@@ -168,8 +345,42 @@ pub struct Vertex {
 	pos: [Flt; 3],
 	uv: [Flt; 2],
 	normal: [Flt; 3],
+	/// Vertex colour, which is multiplied with the material's colours
+	color: [Flt; 3],
+	/// Tangent, with the handedness of the bitangent in w (MikkTSpace convention)
+	tangent: [Flt; 4],
 }
 impl Vertex {
+	pub fn new(pos: [Flt; 3], uv: [Flt; 2], normal: [Flt; 3], color: [Flt; 3], tangent: [Flt; 4]) -> Vertex {
+		Vertex {
+			pos: pos,
+			uv: uv,
+			normal: normal,
+			color: color,
+			tangent: tangent,
+		}
+	}
+
+	pub fn pos(&self) -> [Flt; 3] {
+		self.pos
+	}
+
+	pub fn uv(&self) -> [Flt; 2] {
+		self.uv
+	}
+
+	pub fn normal(&self) -> [Flt; 3] {
+		self.normal
+	}
+
+	pub fn color(&self) -> [Flt; 3] {
+		self.color
+	}
+
+	pub fn tangent(&self) -> [Flt; 4] {
+		self.tangent
+	}
+
 	pub fn as_bytes(&self) -> &[u8] {
 		unsafe {
 			use std::mem;
@@ -181,7 +392,7 @@ impl Vertex {
 		}
 	}
 }
-implement_vertex!(Vertex, pos, normal, uv);
+implement_vertex!(Vertex, pos, normal, uv, color, tangent);
 impl fmt::Debug for Vertex {
 	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
 		let alt = f.alternate();
@@ -192,7 +403,11 @@ impl fmt::Debug for Vertex {
 		f.write_str(spacing)?;
 		write!(f, "uv: {:?},", self.uv)?;
 		f.write_str(spacing)?;
-		write!(f, "normal: {:?}", self.normal)?;
+		write!(f, "normal: {:?},", self.normal)?;
+		f.write_str(spacing)?;
+		write!(f, "color: {:?},", self.color)?;
+		f.write_str(spacing)?;
+		write!(f, "tangent: {:?}", self.tangent)?;
 		f.write_str(if alt { "\n" } else { " " })?;
 		f.write_str("}")?;
 		Ok(())
@@ -218,62 +433,135 @@ impl PartialEq<Vertex> for Vertex {
 }
 impl Eq for Vertex {}
 
-pub struct Mesh {
+/// Index buffer of a mesh. The smallest index type that fits the vertices is used.
+pub enum MeshIndices {
+	U8(IndexBuffer<u8>),
+	U16(IndexBuffer<u16>),
+	U32(IndexBuffer<u32>),
+}
+impl MeshIndices {
+	/// Number of indices in the buffer
+	pub fn len(&self) -> usize {
+		match self {
+			&MeshIndices::U8(ref buf) => buf.len(),
+			&MeshIndices::U16(ref buf) => buf.len(),
+			&MeshIndices::U32(ref buf) => buf.len(),
+		}
+	}
+
+	/// Size of the buffer in bytes
+	pub fn size_bytes(&self) -> usize {
+		match self {
+			&MeshIndices::U8(ref buf) => buf.get_size(),
+			&MeshIndices::U16(ref buf) => buf.get_size(),
+			&MeshIndices::U32(ref buf) => buf.get_size(),
+		}
+	}
+
+	/// Gets a source for a range of the indices. Returns None if the range is out of bounds.
+	pub fn slice<'a>(&'a self, range: Range<usize>) -> Option<IndicesSource<'a>> {
+		match self {
+			&MeshIndices::U8(ref buf) => buf.slice(range).map(IndicesSource::from),
+			&MeshIndices::U16(ref buf) => buf.slice(range).map(IndicesSource::from),
+			&MeshIndices::U32(ref buf) => buf.slice(range).map(IndicesSource::from),
+		}
+	}
+}
+
+/// A part of a mesh that is drawn with a single material
+#[derive(Debug, Clone)]
+pub struct SubMesh {
+	/// Name of the object/group that the submesh was read from
+	pub name: Option<String>,
 	pub material: Material,
+	/// Range of the mesh's indices that make up the submesh.
+	///
+	/// Ignored if the mesh has no indices.
+	pub range: Range<usize>,
+}
+impl SubMesh {
+	pub fn new(name: Option<String>, material: Material, range: Range<usize>) -> SubMesh {
+		SubMesh {
+			name: name,
+			material: material,
+			range: range,
+		}
+	}
+}
+
+pub struct Mesh {
+	/// Parts of the mesh, each drawn with its own material.
+	pub submeshes: Vec<SubMesh>,
 	pub vertices: VertexBuffer<Vertex>,
 	/// If None, use NoIndices.
-	pub indices: Option<Box<IndexBufferAny>>,
+	pub indices: Option<MeshIndices>,
+	/// Bounds of the mesh in model space
+	pub aabb: Aabb,
+	pub bounding_sphere: BoundingSphere,
+	/// Index ranges of the submeshes in each simplified level of detail, starting with the least simplified
+	pub lods: Vec<Vec<Range<usize>>>,
+	pub lod_settings: LodSettings,
 }
 impl Mesh {
 	pub fn indices_source<'a>(&'a self) -> IndicesSource<'a> {
 		match &self.indices {
-			&Some(ref buf) => IndicesSource::from(&**buf),
+			&Some(ref buf) => buf.slice(0..buf.len()).unwrap(),
 			&None => IndicesSource::NoIndices{ primitives: PrimitiveType::TrianglesList },
 		}
 	}
 
-	pub fn from_file(ctx: &Rc<Context>, rel_path: &str) -> GameResult<Mesh> {
-		use render::parse::ObjFile;
-
-		let file = ObjFile::new(rel_path.to_string())
-			.map_err(|e| format!("Invalid mesh: {}", e))?;
+	/// Gets the indices that make up a submesh of this mesh. Returns None if the submesh's range is out of bounds.
+	pub fn submesh_indices_source<'a>(&'a self, sm: &SubMesh) -> Option<IndicesSource<'a>> {
+		match &self.indices {
+			&Some(ref buf) => buf.slice(sm.range.clone()),
+			&None => Some(IndicesSource::NoIndices{ primitives: PrimitiveType::TrianglesList }),
+		}
+	}
 
-		// Get material
-		let material = file.material.clone()
-		.and_then(|mat_name| file.materials.get(&mat_name).map(Material::clone))
-		.unwrap_or_else(Material::default);
+	/// Size of the mesh's buffers in GPU memory, in bytes
+	pub fn size_bytes(&self) -> usize {
+		self.vertices.get_size() + self.indices.as_ref().map(|i| i.size_bytes()).unwrap_or(0)
+	}
 
-		let mut vertices = vec![];
-		let mut vertices_map: HashMap<Vertex, u32> = HashMap::new();
-		let mut indices: Vec<u32> = vec![];
+	/// Number of levels of detail, including the full mesh
+	pub fn lod_count(&self) -> usize {
+		self.lods.len() + 1
+	}
 
-		// Change from indices to vertices
-		for face in file.faces.iter() {
-			for vertex in [face.x, face.y, face.z].into_iter() {
-				let v = Vertex {
-					pos: array3(file.vertices[vertex.vert as usize]),
-					uv: array2(file.uvs[vertex.uv as usize]),
-					normal: array3(file.normals[vertex.norm as usize]),
-				};
-				if let Some(i) = vertices_map.get(&v).cloned() {
-					indices.push(i); // Use cached vertex
-				} else {
-					// Insert new vertex, and update the map
-					let i = vertices.len() as u32;
-					vertices.push(v);
-					indices.push(i);
-					vertices_map.insert(v, i);
-				}
-			}
+	/// Gets the indices that make up a submesh (by its index in `submeshes`) at a level of detail. Returns None if the
+	/// submesh's range is out of bounds.
+	pub fn lod_indices_source<'a>(&'a self, lod: usize, submesh: usize) -> Option<IndicesSource<'a>> {
+		match (lod, &self.indices) {
+			(0, _) | (_, &None) => self.submesh_indices_source(&self.submeshes[submesh]),
+			(_, &Some(ref buf)) => buf.slice(self.lods[lod - 1][submesh].clone()),
 		}
-		debug!("{} vertices, {} tris loaded.", vertices.len(), indices.len() / 3);
-		//trace!("Vertices loaded: {:#?}", &vertices);
-		//trace!("Indices loaded: {:?}", &indices);
+	}
+
+	/// Loads a mesh from a file or a primitive ID, and uploads it to OpenGL. See `MeshData::load`.
+	pub fn load(ctx: &Rc<Context>, id: &str, settings: &LodSettings) -> GameResult<Mesh> {
+		let data = MeshData::load(id, settings)?;
+		Mesh::from_data(ctx, &data, id)
+	}
+
+	/// Uploads mesh data to OpenGL. `rel_path` is only used for error messages.
+	pub fn from_data(ctx: &Rc<Context>, data: &MeshData, rel_path: &str) -> GameResult<Mesh> {
+		let vertices = &data.vertices;
+		let indices = &data.indices;
 
 		// Upload vertex information to OpenGL
-		let v_buffer = VertexBuffer::new(ctx, &vertices)
+		let v_buffer = VertexBuffer::new(ctx, vertices)
 			.map_err(|e| format!("Invalid mesh ({}): OpenGL buffer creation error: {}", rel_path, e))?;
 
+		// The levels of detail go after the full mesh in the index buffer
+		let mut indices = indices.clone();
+		let mut lods = vec![];
+		for lod in data.lods.iter() {
+			let offset = indices.len();
+			indices.extend_from_slice(&lod.indices);
+			lods.push(lod.ranges.iter().map(|r| r.start + offset..r.end + offset).collect());
+		}
+		let indices = &indices;
+
 		// Upload index information to OpenGL
 		// Minimize the size of the index array by choosing shorter ints
 		let i_buffer = if vertices.len() < u8::max_value() as usize {
@@ -282,28 +570,369 @@ impl Mesh {
 			let indices: Vec<_> = indices.iter().map(|&i| i as u8 ).collect();
 			let buf = IndexBuffer::new(ctx, PrimitiveType::TrianglesList, &indices)
 				.map_err(|e| format!("Invalid mesh ({}): OpenGL buffer creation error: {}", rel_path, e))?;
-			IndexBufferAny::from(buf)
+			MeshIndices::U8(buf)
 		} else if vertices.len() < u16::max_value() as usize {
 			// Use u16 indices
 			debug!("u16 indices used.");
 			let indices: Vec<_> = indices.iter().map(|&i| i as u16).collect();
 			let buf = IndexBuffer::new(ctx, PrimitiveType::TrianglesList, &indices)
 				.map_err(|e| format!("Invalid mesh ({}): OpenGL buffer creation error: {}", rel_path, e))?;
-			IndexBufferAny::from(buf)
+			MeshIndices::U16(buf)
 		} else {
 			// Use u32 indices
 			debug!("u32 indices used.");
-			let buf = IndexBuffer::new(ctx, PrimitiveType::TrianglesList, &indices)
+			let buf = IndexBuffer::new(ctx, PrimitiveType::TrianglesList, indices)
 				.map_err(|e| format!("Invalid mesh ({}): OpenGL buffer creation error: {}", rel_path, e))?;
-			IndexBufferAny::from(buf)
+			MeshIndices::U32(buf)
 		};
 
-		trace!("Material loaded: {:?}", &material);
+		trace!("Submeshes loaded: {:?}", &data.submeshes);
 
+		let (aabb, sphere) = bounds(vertices);
 		Ok(Mesh {
-			material: material,
+			submeshes: data.submeshes.clone(),
 			vertices: v_buffer,
-			indices: Some(Box::new(i_buffer)),
+			indices: Some(i_buffer),
+			aabb: aabb,
+			bounding_sphere: sphere,
+			lods: lods,
+			lod_settings: data.lod_settings.unwrap_or_default(),
 		})
 	}
 }
+
+/// Mesh data that hasn't been uploaded to OpenGL yet
+#[derive(Debug, Clone)]
+pub struct MeshData {
+	pub vertices: Vec<Vertex>,
+	/// Indices into `vertices`, three for each triangle
+	pub indices: Vec<u32>,
+	pub submeshes: Vec<SubMesh>,
+	/// Files that the mesh was loaded from, relative to the executable, and their size & modification time from when
+	/// they were read
+	pub sources: Vec<(PathBuf, Option<FileStamp>)>,
+	/// Simplified versions of the mesh, starting with the least simplified
+	pub lods: Vec<LodData>,
+	/// Settings that `lods` were generated with, or None if they haven't been generated
+	pub lod_settings: Option<LodSettings>,
+	/// .png images embedded in the mesh file, and the texture IDs that the materials refer to them by
+	pub images: Vec<(TextureID, Vec<u8>)>,
+}
+impl MeshData {
+	/// Parses a mesh from a file. The format is chosen by the extension of the file.
+	pub fn from_file(rel_path: &str) -> GameResult<MeshData> {
+		match mesh_extension(rel_path) {
+			Some("obj") => {
+				let file = ObjFile::new(rel_path.to_string(), ParseMode::default())
+					.map_err(|e| format!("Invalid mesh: {}", e))?;
+				Ok(MeshData::from_obj(&file))
+			},
+			Some("stl") => {
+				let file = ObjFile::load_stl(rel_path.to_string())
+					.map_err(|e| format!("Invalid mesh: {}", e))?;
+				Ok(MeshData::from_obj(&file))
+			},
+			Some("ply") => {
+				let file = ObjFile::load_ply(rel_path.to_string())
+					.map_err(|e| format!("Invalid mesh: {}", e))?;
+				Ok(MeshData::from_obj(&file))
+			},
+			Some("gltf") | Some("glb") => {
+				let file = GltfFile::new(rel_path.to_string())
+					.map_err(|e| format!("Invalid mesh: {}", e))?;
+				Ok(MeshData::from_gltf(&file))
+			},
+			_ => Err(format!("Invalid mesh: Unknown file type: {}", rel_path)),
+		}
+	}
+
+	/// Converts a parsed .obj file into a vertex & index buffer, removing duplicate vertices
+	pub fn from_obj(file: &ObjFile) -> MeshData {
+		// Change from indices to vertices
+		let mut corners = vec![];
+		for face in file.faces.iter() {
+			for vertex in [face.x, face.y, face.z].into_iter() {
+				corners.push(Vertex {
+					pos: array3(file.vertices[vertex.vert as usize]),
+					uv: array2(file.uvs[vertex.uv as usize]),
+					normal: array3(file.normals[vertex.norm as usize]),
+					color: array3(file.colors[vertex.vert as usize]),
+					tangent: [0.0; 4],
+				});
+			}
+		}
+		generate_tangents(&mut corners);
+		let (vertices, indices) = remove_duplicates(&corners);
+
+		// Each face range becomes a submesh with its own material
+		let submeshes: Vec<SubMesh> = file.ranges.iter().map(|r| {
+			let material = r.material.as_ref()
+				.and_then(|mat_name| file.materials.get(mat_name).map(Material::clone))
+				.unwrap_or_else(Material::default);
+			let name = r.group.clone().or(r.object.clone());
+			SubMesh::new(name, material, r.start * 3..(r.start + r.len) * 3)
+		}).collect();
+		debug!("{} vertices, {} tris, {} submeshes loaded.", vertices.len(), indices.len() / 3, submeshes.len());
+
+		MeshData {
+			vertices: vertices,
+			indices: indices,
+			submeshes: submeshes,
+			sources: file.sources.clone(),
+			lods: vec![],
+			lod_settings: None,
+			images: vec![],
+		}
+	}
+
+	/// Converts a glTF file into a vertex & index buffer. Each primitive becomes a submesh.
+	pub fn from_gltf(file: &GltfFile) -> MeshData {
+		let mut corners = vec![];
+		let mut submeshes = vec![];
+		for p in file.primitives.iter() {
+			let start = corners.len();
+			for &i in p.indices.iter() {
+				let i = i as usize;
+				corners.push(Vertex {
+					pos: array3(p.positions[i]),
+					uv: array2(p.uvs[i]),
+					normal: array3(p.normals[i]),
+					color: array3(p.colors[i]),
+					tangent: p.tangents.as_ref().map(|t| array4(t[i])).unwrap_or([0.0; 4]),
+				});
+			}
+			// Only generate tangents if the file doesn't have them
+			if p.tangents.is_none() {
+				generate_tangents(&mut corners[start..]);
+			}
+			let material = p.material
+				.and_then(|m| file.materials.get(m).map(Material::clone))
+				.unwrap_or_else(Material::default);
+			submeshes.push(SubMesh::new(p.name.clone(), material, start..corners.len()));
+		}
+		let (vertices, indices) = remove_duplicates(&corners);
+		debug!("{} vertices, {} tris, {} submeshes loaded.", vertices.len(), indices.len() / 3, submeshes.len());
+
+		MeshData {
+			vertices: vertices,
+			indices: indices,
+			submeshes: submeshes,
+			sources: file.sources.clone(),
+			lods: vec![],
+			lod_settings: None,
+			images: file.images.clone(),
+		}
+	}
+
+	/// Loads the data of a mesh, and generates its levels of detail. `id` is either the path of a file, or the ID of
+	/// a primitive (e.g. `proc:box?w=2&h=1`).
+	///
+	/// If a file has a cache that is up to date, that is used instead. Otherwise the cache is rebuilt. This doesn't
+	/// use OpenGL, so it can be called from any thread.
+	pub fn load(id: &str, settings: &LodSettings) -> GameResult<MeshData> {
+		if Primitive::is_primitive_id(id) {
+			let mut data = Primitive::from_id(id)?.generate();
+			data.generate_lods(settings);
+			return Ok(data);
+		}
+
+		let (mut data, mut save) = match mesh_cache::load_cache(id) {
+			Some(data) => {
+				debug!("Using mesh cache for {}", id);
+				(data, false)
+			},
+			None => (MeshData::from_file(id)?, true),
+		};
+		if !data.lod_settings.map(|s| s.generates_same(settings)).unwrap_or(false) {
+			data.generate_lods(settings);
+			save |= settings.cache;
+		}
+		if save {
+			let res = if settings.cache {
+				mesh_cache::save_cache(id, &data)
+			} else {
+				mesh_cache::save_cache(id, &data.without_lods())
+			};
+			if let Err(e) = res {
+				warn!("Could not write mesh cache ({}): {}", id, e);
+			}
+		}
+		// The levels may have been generated with settings that only choose between them differently
+		data.lod_settings = Some(*settings);
+		Ok(data)
+	}
+
+	/// Makes a mesh with a single submesh from a list of triangles, where each vertex is a corner of a triangle.
+	///
+	/// The tangents of the vertices are generated.
+	pub fn from_corners(mut corners: Vec<Vertex>, name: Option<String>) -> MeshData {
+		generate_tangents(&mut corners);
+		let (vertices, indices) = remove_duplicates(&corners);
+		let len = indices.len();
+		MeshData {
+			vertices: vertices,
+			indices: indices,
+			submeshes: vec![SubMesh::new(name, Material::default(), 0..len)],
+			sources: vec![],
+			lods: vec![],
+			lod_settings: None,
+			images: vec![],
+		}
+	}
+
+	/// Generates the error mesh: a unit cube with black edges and a black "?" on each magenta face.
+	///
+	/// The cube isn't lit, so that it stands out wherever it is.
+	pub fn error_cube() -> MeshData {
+		const GLYPH: [&'static str; 7] = [
+			".###.",
+			"#...#",
+			"....#",
+			"...#.",
+			"..#..",
+			".....",
+			"..#..",
+		];
+		// Size of a pixel of the glyph, & width of the edges
+		const PIXEL: Flt = 0.1;
+		const EDGE: Flt = 0.05;
+		// The glyph & edges are raised slightly off the faces, so that they don't z-fight
+		const RAISE: Flt = 0.002;
+		let (magenta, black) = ([1.0, 0.0, 1.0], [0.0, 0.0, 0.0]);
+
+		let mut corners = vec![];
+		let (x, y, z) = (Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z());
+		for &(n, up) in [(x, y), (-x, y), (z, y), (-z, y), (y, -z), (-y, z)].iter() {
+			// Axes of the face, as seen from outside the cube
+			let right = up.cross(n);
+			let mut quad = |min: Vec2, max: Vec2, depth: Flt, color: [Flt; 3]| {
+				let corner = |x: Flt, y: Flt| {
+					let pos = n * depth + right * x + up * y;
+					Vertex::new(pos.into(), [x + 0.5, 0.5 - y], n.into(), color, right.extend(1.0).into())
+				};
+				corners.extend_from_slice(&[
+					corner(min.x, min.y), corner(max.x, min.y), corner(max.x, max.y),
+					corner(min.x, min.y), corner(max.x, max.y), corner(min.x, max.y),
+				]);
+			};
+
+			quad(vec2(-0.5, -0.5), vec2(0.5, 0.5), 0.5, magenta);
+			let depth = 0.5 + RAISE;
+			quad(vec2(-0.5, -0.5), vec2(0.5, -0.5 + EDGE), depth, black);
+			quad(vec2(-0.5, 0.5 - EDGE), vec2(0.5, 0.5), depth, black);
+			quad(vec2(-0.5, -0.5 + EDGE), vec2(-0.5 + EDGE, 0.5 - EDGE), depth, black);
+			quad(vec2(0.5 - EDGE, -0.5 + EDGE), vec2(0.5, 0.5 - EDGE), depth, black);
+			for (row, line) in GLYPH.iter().enumerate() {
+				for (col, c) in line.chars().enumerate() {
+					if c != '#' {
+						continue;
+					}
+					let min = vec2((col as Flt - 2.5) * PIXEL, (2.5 - row as Flt) * PIXEL);
+					quad(min, min + vec2(PIXEL, PIXEL), depth, black);
+				}
+			}
+		}
+
+		let mut data = MeshData::from_corners(corners, Some("error".into()));
+		{
+			let material = &mut data.submeshes[0].material;
+			material.lighting_disabled = true;
+			material.Ka = vec3(1.0, 1.0, 1.0);
+		}
+		data
+	}
+
+	/// Generates simplified levels of detail, replacing any that were there before.
+	///
+	/// Each level is simplified from the full mesh. Levels stop being added once they stop getting smaller.
+	pub fn generate_lods(&mut self, settings: &LodSettings) {
+		self.lods.clear();
+		self.lod_settings = Some(*settings);
+		let mut prev = self.indices.len() / 3;
+		for _ in 0..settings.levels {
+			let target = ((prev as Flt * settings.reduction) as usize).max(settings.min_triangles);
+			if target >= prev {
+				break;
+			}
+			let tris = lod::simplify(&self.vertices, &self.indices, target);
+			if tris.len() as Flt > prev as Flt * 0.95 {
+				break;
+			}
+			// The triangles are still in order, so each submesh's triangles are together
+			let mut lod = LodData { indices: vec![], ranges: vec![] };
+			let mut it = tris.iter().peekable();
+			for sm in self.submeshes.iter() {
+				let start = lod.indices.len();
+				while let Some(&&(i, t)) = it.peek() {
+					if i * 3 >= sm.range.end {
+						break;
+					}
+					if i * 3 >= sm.range.start {
+						lod.indices.extend_from_slice(&t);
+					}
+					it.next();
+				}
+				lod.ranges.push(start..lod.indices.len());
+			}
+			debug!("Level of detail {}: {} tris", self.lods.len() + 1, tris.len());
+			prev = tris.len();
+			self.lods.push(lod);
+		}
+	}
+
+	/// Gets a copy of the mesh without its levels of detail
+	pub fn without_lods(&self) -> MeshData {
+		MeshData {
+			lods: vec![],
+			lod_settings: None,
+			..self.clone()
+		}
+	}
+}
+
+/// A simplified version of a mesh, which uses the mesh's vertices
+#[derive(Debug, Clone)]
+pub struct LodData {
+	/// Indices into the mesh's vertices, three for each triangle
+	pub indices: Vec<u32>,
+	/// Range of `indices` that each submesh uses
+	pub ranges: Vec<Range<usize>>,
+}
+
+/// Calculates the bounding volumes of a mesh's vertices
+fn bounds(vertices: &[Vertex]) -> (Aabb, BoundingSphere) {
+	let positions: Vec<Vec3> = vertices.iter().map(|v| Vec3::from(v.pos)).collect();
+	(Aabb::from_points(&positions), BoundingSphere::from_points(&positions))
+}
+
+/// Generates the tangents of a list of triangles, where each vertex is a corner of a triangle
+fn generate_tangents(corners: &mut [Vertex]) {
+	let positions: Vec<Vec3> = corners.iter().map(|v| Vec3::from(v.pos)).collect();
+	let normals: Vec<Vec3> = corners.iter().map(|v| Vec3::from(v.normal)).collect();
+	let uvs: Vec<Vec2> = corners.iter().map(|v| Vec2::from(v.uv)).collect();
+	let tangents = tangent::generate_tangents(&positions, &normals, &uvs);
+	for (v, t) in corners.iter_mut().zip(tangents.into_iter()) {
+		v.tangent = array4(t);
+	}
+}
+
+/// Merges identical vertices in a list of triangle corners. Returns the vertices, and the index of each corner.
+///
+/// Tangents must be generated first, so that vertices are only merged if their tangent frames match too.
+fn remove_duplicates(corners: &[Vertex]) -> (Vec<Vertex>, Vec<u32>) {
+	let mut vertices = vec![];
+	let mut vertices_map: HashMap<Vertex, u32> = HashMap::new();
+	let mut indices: Vec<u32> = Vec::with_capacity(corners.len());
+	for &v in corners.iter() {
+		if let Some(i) = vertices_map.get(&v).cloned() {
+			indices.push(i); // Use cached vertex
+		} else {
+			// Insert new vertex, and update the map
+			let i = vertices.len() as u32;
+			vertices.push(v);
+			indices.push(i);
+			vertices_map.insert(v, i);
+		}
+	}
+	(vertices, indices)
+}
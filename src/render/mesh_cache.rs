@@ -0,0 +1,768 @@
+//! Binary cache of meshes, so that mesh files don't have to be parsed every time the game starts.
+//!
+//! The cache of `res/mesh/foo.obj` is stored at `res/mesh/foo.obj.cache`, and holds the final vertex
+//! buffer, index buffer, materials and levels of detail of the mesh. It is only used if all of the files
+//! the mesh was loaded from have exactly the same size & modification time as when they were read.
+//!
+//! All values are stored little endian. The layout of the file is:
+//!
+//! - magic `P2MC`, then the format version as a u32
+//! - source files: count, then each path as a string, followed by whether it has a stamp, and its size as a
+//!   u64 and modification time as a u64 of seconds & a u32 of nanoseconds if it does
+//! - vertices: count, then the position, uv, normal, colour and tangent of each as f32s
+//! - indices: count, then each index as a u32
+//! - submeshes: count, then the name, index range and material of each
+//! - levels of detail: whether they were generated, then the settings used, a count, and the indices
+//!   & submesh ranges of each
+//! - embedded images: count, then the texture ID of each as a string and its .png file as bytes
+//!
+//! Counts are u32s, strings are a u32 byte length followed by UTF-8, and bytes are a u32 length followed by the
+//! bytes.
+#[allow(unused_imports)]
+use prelude::*;
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use render::{mesh_extension, LodData, LodSettings, Material, MeshData, SubMesh, Vertex, TextureOptions, TextureChannel, ReflectionType};
+use render::parse::{Axis, UvProjection};
+use vfs::{self, FileStamp};
+
+/// Extension that is appended to the path of a mesh to get the path of its cache
+pub const CACHE_EXT: &'static str = ".cache";
+/// Version of the cache format. This must be incremented whenever the format changes.
+pub const CACHE_VERSION: u32 = 7;
+
+const MAGIC: &'static [u8; 4] = b"P2MC";
+
+/// Numbers the temporary files that caches are written to, so that threads saving at once don't share one
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+/// Gets the path of the cache of the mesh at `rel_path`
+pub fn cache_path(rel_path: &str) -> String {
+	rel_path.to_string() + CACHE_EXT
+}
+
+/// Loads the cache of a mesh, if it exists and none of the mesh's source files have changed since it was made
+pub fn load_cache(rel_path: &str) -> Option<MeshData> {
+	let path = vfs::canonicalize_exe(cache_path(rel_path));
+	let mut r = match File::open(&path) {
+		Ok(f) => BufReader::new(f),
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => return None, // No cache
+		Err(e) => {
+			warn!("Could not open mesh cache ({}): {}", path.display(), e);
+			return None;
+		}
+	};
+
+	let sources = match read_header(&mut r) {
+		Ok(sources) => sources,
+		Err(e) => {
+			warn!("Invalid mesh cache ({}): {}", path.display(), e);
+			return None;
+		}
+	};
+	// The cache is out of date if any of the source files have changed since they were read. The stamps must match
+	// exactly, as a file can be written after it was read but before the cache was, or within the same tick of a
+	// coarse modification time.
+	for &(ref src, stamp) in sources.iter() {
+		if stamp.is_none() || vfs::file_stamp(src) != stamp {
+			debug!("Mesh cache out of date ({}): {} has changed", path.display(), src.display());
+			return None;
+		}
+	}
+
+	match read_body(&mut r, sources) {
+		Ok(data) => Some(data),
+		Err(e) => {
+			warn!("Invalid mesh cache ({}): {}", path.display(), e);
+			None
+		}
+	}
+}
+
+/// Writes the cache of the mesh at `rel_path`. Meshes with a source file that isn't on disk aren't cached.
+///
+/// The cache is written to a temporary file which is then moved into place, so that a cache is never read while it
+/// is half written, and two threads saving the same mesh at once can't mix their writes together.
+pub fn save_cache(rel_path: &str, data: &MeshData) -> io::Result<()> {
+	if let Some(&(ref src, _)) = data.sources.iter().find(|&&(_, stamp)| stamp.is_none()) {
+		return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not on disk", src.display())));
+	}
+	let path = vfs::canonicalize_exe(cache_path(rel_path));
+	let mut temp = path.clone().into_os_string();
+	temp.push(format!(".{}.{}.tmp", process::id(), NEXT_TEMP.fetch_add(1, Ordering::Relaxed)));
+	let temp = PathBuf::from(temp);
+
+	let res = File::create(&temp).and_then(|f| {
+		let mut w = BufWriter::new(f);
+		write_mesh(&mut w, data)?;
+		w.flush()
+	}).and_then(|()| fs::rename(&temp, &path));
+	if res.is_err() {
+		fs::remove_file(&temp).ok();
+	}
+	res
+}
+
+/// Bakes the caches of all of the mesh files in a directory (relative to the executable).
+///
+/// Returns the number of meshes baked.
+pub fn bake_dir(rel_dir: &str) -> GameResult<usize> {
+	let dir = vfs::canonicalize_exe(rel_dir);
+	let it = fs::read_dir(&dir)
+		.map_err(|e| format!("Could not iterate over directory ({}): {}", dir.display(), e))?;
+
+	let mut baked = 0;
+	let mut failed = 0;
+	for file in it {
+		let file = match file {
+			Ok(f) => f,
+			Err(_) => continue, // Ignore files that return an error when iterating over them
+		};
+		let name = file.file_name().to_string_lossy().into_owned();
+		if mesh_extension(&name).is_none() {
+			continue;
+		}
+		let rel_path = PathBuf::from(rel_dir).join(&name).to_string_lossy().into_owned();
+		let settings = LodSettings::default();
+		let res = MeshData::from_file(&rel_path).and_then(|mut d| {
+			if settings.cache {
+				d.generate_lods(&settings);
+			}
+			save_cache(&rel_path, &d).map_err(|e| e.to_string())
+		});
+		match res {
+			Ok(()) => {
+				info!("Baked mesh: {}", rel_path);
+				baked += 1;
+			},
+			Err(e) => {
+				error!("Could not bake mesh ({}): {}", rel_path, e);
+				failed += 1;
+			}
+		}
+	}
+	if failed != 0 {
+		Err(format!("{} meshes could not be baked", failed))
+	} else {
+		Ok(baked)
+	}
+}
+
+/// Writes a mesh in the cache format
+pub fn write_mesh<W: Write>(w: &mut W, data: &MeshData) -> io::Result<()> {
+	w.write_all(MAGIC)?;
+	write_u32(w, CACHE_VERSION)?;
+
+	write_u32(w, data.sources.len() as u32)?;
+	for &(ref src, stamp) in data.sources.iter() {
+		write_str(w, &src.to_string_lossy())?;
+		match stamp {
+			Some(stamp) => {
+				write_bool(w, true)?;
+				write_u64(w, stamp.len)?;
+				write_u64(w, stamp.modified.as_secs())?;
+				write_u32(w, stamp.modified.subsec_nanos())?;
+			},
+			None => write_bool(w, false)?,
+		}
+	}
+
+	write_u32(w, data.vertices.len() as u32)?;
+	for v in data.vertices.iter() {
+		for &x in v.pos().iter().chain(v.uv().iter()).chain(v.normal().iter()).chain(v.color().iter()).chain(v.tangent().iter()) {
+			write_f32(w, x)?;
+		}
+	}
+
+	write_u32(w, data.indices.len() as u32)?;
+	for &i in data.indices.iter() {
+		write_u32(w, i)?;
+	}
+
+	write_u32(w, data.submeshes.len() as u32)?;
+	for sm in data.submeshes.iter() {
+		write_opt_str(w, sm.name.as_ref().map(|s| &s[..]))?;
+		write_u32(w, sm.range.start as u32)?;
+		write_u32(w, sm.range.end as u32)?;
+		write_material(w, &sm.material)?;
+	}
+
+	match data.lod_settings {
+		Some(ref s) => {
+			write_bool(w, true)?;
+			write_lod_settings(w, s)?;
+		},
+		None => write_bool(w, false)?,
+	}
+	write_u32(w, data.lods.len() as u32)?;
+	for lod in data.lods.iter() {
+		write_u32(w, lod.indices.len() as u32)?;
+		for &i in lod.indices.iter() {
+			write_u32(w, i)?;
+		}
+		for r in lod.ranges.iter() {
+			write_u32(w, r.start as u32)?;
+			write_u32(w, r.end as u32)?;
+		}
+	}
+
+	write_u32(w, data.images.len() as u32)?;
+	for &(ref id, ref png) in data.images.iter() {
+		write_str(w, id)?;
+		write_bytes(w, png)?;
+	}
+	Ok(())
+}
+
+/// Reads a mesh in the cache format
+pub fn read_mesh<R: Read>(r: &mut R) -> io::Result<MeshData> {
+	let sources = read_header(r)?;
+	read_body(r, sources)
+}
+
+fn invalid_data<T>(msg: &str) -> io::Result<T> {
+	Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+/// Reads the magic, version and source files
+fn read_header<R: Read>(r: &mut R) -> io::Result<Vec<(PathBuf, Option<FileStamp>)>> {
+	let mut magic = [0; 4];
+	r.read_exact(&mut magic)?;
+	if &magic != MAGIC {
+		return invalid_data("not a mesh cache");
+	}
+	let version = read_u32(r)?;
+	if version != CACHE_VERSION {
+		return Err(io::Error::new(io::ErrorKind::InvalidData,
+			format!("mesh cache version is {}, expected {}", version, CACHE_VERSION)));
+	}
+
+	let n = read_u32(r)?;
+	let mut sources = vec![];
+	for _ in 0..n {
+		let src = PathBuf::from(read_str(r)?);
+		let stamp = if read_bool(r)? {
+			let len = read_u64(r)?;
+			let secs = read_u64(r)?;
+			let nanos = read_u32(r)?;
+			if nanos >= 1_000_000_000 {
+				return invalid_data("invalid modification time");
+			}
+			Some(FileStamp { len: len, modified: Duration::new(secs, nanos) })
+		} else {
+			None
+		};
+		sources.push((src, stamp));
+	}
+	Ok(sources)
+}
+
+/// Reads everything after the header.
+///
+/// Counts in the file aren't used to allocate space up front, so that a corrupt count is an error once the file runs
+/// out rather than a huge allocation.
+fn read_body<R: Read>(r: &mut R, sources: Vec<(PathBuf, Option<FileStamp>)>) -> io::Result<MeshData> {
+	let n = read_u32(r)? as usize;
+	let mut vertices = vec![];
+	for _ in 0..n {
+		let mut xs = [0.0; 15];
+		for x in xs.iter_mut() {
+			*x = read_f32(r)?;
+		}
+		vertices.push(Vertex::new([xs[0], xs[1], xs[2]], [xs[3], xs[4]], [xs[5], xs[6], xs[7]], [xs[8], xs[9], xs[10]],
+			[xs[11], xs[12], xs[13], xs[14]]));
+	}
+
+	let n = read_u32(r)? as usize;
+	let mut indices = vec![];
+	for _ in 0..n {
+		let i = read_u32(r)?;
+		if i as usize >= vertices.len() {
+			return invalid_data("index out of range");
+		}
+		indices.push(i);
+	}
+
+	let n = read_u32(r)?;
+	let mut submeshes = vec![];
+	for _ in 0..n {
+		let name = read_opt_str(r)?;
+		let start = read_u32(r)? as usize;
+		let end = read_u32(r)? as usize;
+		if start > end || end > indices.len() {
+			return invalid_data("submesh range out of range");
+		}
+		let material = read_material(r)?;
+		submeshes.push(SubMesh::new(name, material, start..end));
+	}
+
+	let lod_settings = if read_bool(r)? { Some(read_lod_settings(r)?) } else { None };
+	let n = read_u32(r)?;
+	let mut lods = vec![];
+	for _ in 0..n {
+		let len = read_u32(r)? as usize;
+		let mut lod = LodData { indices: vec![], ranges: vec![] };
+		for _ in 0..len {
+			let i = read_u32(r)?;
+			if i as usize >= vertices.len() {
+				return invalid_data("index out of range");
+			}
+			lod.indices.push(i);
+		}
+		// There is a range for each submesh
+		for _ in 0..submeshes.len() {
+			let start = read_u32(r)? as usize;
+			let end = read_u32(r)? as usize;
+			if start > end || end > lod.indices.len() {
+				return invalid_data("level of detail range out of range");
+			}
+			lod.ranges.push(start..end);
+		}
+		lods.push(lod);
+	}
+
+	let n = read_u32(r)?;
+	let mut images = vec![];
+	for _ in 0..n {
+		images.push((read_str(r)?, read_bytes(r)?));
+	}
+
+	Ok(MeshData {
+		vertices: vertices,
+		indices: indices,
+		submeshes: submeshes,
+		sources: sources,
+		lods: lods,
+		lod_settings: lod_settings,
+		images: images,
+	})
+}
+
+fn write_lod_settings<W: Write>(w: &mut W, s: &LodSettings) -> io::Result<()> {
+	write_u32(w, s.levels as u32)?;
+	write_f32(w, s.reduction)?;
+	write_u32(w, s.min_triangles as u32)?;
+	write_f32(w, s.screen_size)?;
+	write_f32(w, s.bias)?;
+	write_bool(w, s.cache)
+}
+
+fn read_lod_settings<R: Read>(r: &mut R) -> io::Result<LodSettings> {
+	Ok(LodSettings {
+		levels: read_u32(r)? as usize,
+		reduction: read_f32(r)?,
+		min_triangles: read_u32(r)? as usize,
+		screen_size: read_f32(r)?,
+		bias: read_f32(r)?,
+		cache: read_bool(r)?,
+	})
+}
+
+fn write_material<W: Write>(w: &mut W, m: &Material) -> io::Result<()> {
+	write_bool(w, m.lighting_disabled)?;
+	for v in [m.Ka, m.Kd, m.Ks, m.Ke].iter() {
+		write_vec3(w, *v)?;
+	}
+	write_f32(w, m.Ns)?;
+	write_f32(w, m.Ni)?;
+	write_f32(w, m.d)?;
+	write_u32(w, m.illum)?;
+	for map in [&m.map_Ka, &m.map_Kd, &m.map_Ks, &m.map_Ke, &m.map_Ns, &m.map_d, &m.bump, &m.disp, &m.decal].iter() {
+		match **map {
+			Some((ref id, opt)) => {
+				write_bool(w, true)?;
+				write_str(w, id)?;
+				write_texture_options(w, &opt)?;
+			},
+			None => write_bool(w, false)?,
+		}
+	}
+	write_u32(w, m.refl.len() as u32)?;
+	for &(ref id, opt) in m.refl.iter() {
+		write_str(w, id)?;
+		write_texture_options(w, &opt)?;
+	}
+	write_uv_projection(w, m.uv_projection)
+}
+
+fn read_material<R: Read>(r: &mut R) -> io::Result<Material> {
+	let mut m = Material::default();
+	m.lighting_disabled = read_bool(r)?;
+	m.Ka = read_vec3(r)?;
+	m.Kd = read_vec3(r)?;
+	m.Ks = read_vec3(r)?;
+	m.Ke = read_vec3(r)?;
+	m.Ns = read_f32(r)?;
+	m.Ni = read_f32(r)?;
+	m.d = read_f32(r)?;
+	m.illum = read_u32(r)?;
+	for map in [&mut m.map_Ka, &mut m.map_Kd, &mut m.map_Ks, &mut m.map_Ke, &mut m.map_Ns, &mut m.map_d, &mut m.bump, &mut m.disp, &mut m.decal].iter_mut() {
+		if read_bool(r)? {
+			let id = read_str(r)?;
+			**map = Some((id, read_texture_options(r)?));
+		}
+	}
+	let n = read_u32(r)?;
+	for _ in 0..n {
+		let id = read_str(r)?;
+		m.refl.push((id, read_texture_options(r)?));
+	}
+	m.uv_projection = read_uv_projection(r)?;
+	Ok(m)
+}
+
+/// Written as the kind of projection, then its axis (0 if it doesn't have one)
+fn write_uv_projection<W: Write>(w: &mut W, p: UvProjection) -> io::Result<()> {
+	let axis = |a| match a { Axis::X => 1, Axis::Y => 2, Axis::Z => 3 };
+	let (kind, a) = match p {
+		UvProjection::Planar(a) => (0, axis(a)),
+		UvProjection::Box => (1, 0),
+		UvProjection::Spherical => (2, 0),
+		UvProjection::Cylindrical(a) => (3, axis(a)),
+	};
+	write_u8(w, kind)?;
+	write_u8(w, a)
+}
+
+fn read_uv_projection<R: Read>(r: &mut R) -> io::Result<UvProjection> {
+	let kind = read_u8(r)?;
+	let axis = match read_u8(r)? {
+		0 => None,
+		1 => Some(Axis::X),
+		2 => Some(Axis::Y),
+		3 => Some(Axis::Z),
+		_ => return invalid_data("invalid axis"),
+	};
+	match (kind, axis) {
+		(0, Some(a)) => Ok(UvProjection::Planar(a)),
+		(1, None) => Ok(UvProjection::Box),
+		(2, None) => Ok(UvProjection::Spherical),
+		(3, Some(a)) => Ok(UvProjection::Cylindrical(a)),
+		_ => invalid_data("invalid uv projection"),
+	}
+}
+
+fn write_texture_options<W: Write>(w: &mut W, o: &TextureOptions) -> io::Result<()> {
+	write_vec2(w, o.uv_scale)?;
+	write_vec2(w, o.uv_offset)?;
+	write_vec3(w, o.turbulence)?;
+	write_bool(w, o.clamp)?;
+	write_bool(w, o.blend_u)?;
+	write_bool(w, o.blend_v)?;
+	write_bool(w, o.color_correction)?;
+	write_f32(w, o.bump_mult)?;
+	write_f32(w, o.boost)?;
+	write_vec2(w, o.mm)?;
+	// Optional values are stored as 0 if they are None, and 1 more than their value otherwise
+	write_u8(w, o.imfchan.map(|c| {
+		use render::TextureChannel::*;
+		match c { R => 1, G => 2, B => 3, M => 4, L => 5, Z => 6 }
+	}).unwrap_or(0))?;
+	write_u32(w, o.texres.map(|x| x + 1).unwrap_or(0))?;
+	write_u8(w, o.refl_type.map(|t| {
+		use render::ReflectionType::*;
+		match t { Sphere => 1, CubeTop => 2, CubeBottom => 3, CubeFront => 4, CubeBack => 5, CubeLeft => 6, CubeRight => 7 }
+	}).unwrap_or(0))
+}
+
+fn read_texture_options<R: Read>(r: &mut R) -> io::Result<TextureOptions> {
+	let mut o = TextureOptions::default();
+	o.uv_scale = read_vec2(r)?;
+	o.uv_offset = read_vec2(r)?;
+	o.turbulence = read_vec3(r)?;
+	o.clamp = read_bool(r)?;
+	o.blend_u = read_bool(r)?;
+	o.blend_v = read_bool(r)?;
+	o.color_correction = read_bool(r)?;
+	o.bump_mult = read_f32(r)?;
+	o.boost = read_f32(r)?;
+	o.mm = read_vec2(r)?;
+	o.imfchan = match read_u8(r)? {
+		0 => None,
+		1 => Some(TextureChannel::R),
+		2 => Some(TextureChannel::G),
+		3 => Some(TextureChannel::B),
+		4 => Some(TextureChannel::M),
+		5 => Some(TextureChannel::L),
+		6 => Some(TextureChannel::Z),
+		_ => return invalid_data("invalid texture channel"),
+	};
+	o.texres = match read_u32(r)? {
+		0 => None,
+		x => Some(x - 1),
+	};
+	o.refl_type = match read_u8(r)? {
+		0 => None,
+		1 => Some(ReflectionType::Sphere),
+		2 => Some(ReflectionType::CubeTop),
+		3 => Some(ReflectionType::CubeBottom),
+		4 => Some(ReflectionType::CubeFront),
+		5 => Some(ReflectionType::CubeBack),
+		6 => Some(ReflectionType::CubeLeft),
+		7 => Some(ReflectionType::CubeRight),
+		_ => return invalid_data("invalid reflection type"),
+	};
+	Ok(o)
+}
+
+fn write_u8<W: Write>(w: &mut W, x: u8) -> io::Result<()> {
+	w.write_all(&[x])
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+	let mut buf = [0; 1];
+	r.read_exact(&mut buf)?;
+	Ok(buf[0])
+}
+
+fn write_bool<W: Write>(w: &mut W, x: bool) -> io::Result<()> {
+	write_u8(w, x as u8)
+}
+
+fn read_bool<R: Read>(r: &mut R) -> io::Result<bool> {
+	match read_u8(r)? {
+		0 => Ok(false),
+		1 => Ok(true),
+		_ => invalid_data("invalid bool"),
+	}
+}
+
+fn write_u32<W: Write>(w: &mut W, x: u32) -> io::Result<()> {
+	w.write_all(&[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8])
+}
+
+fn write_u64<W: Write>(w: &mut W, x: u64) -> io::Result<()> {
+	write_u32(w, x as u32)?;
+	write_u32(w, (x >> 32) as u32)
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+	Ok(read_u32(r)? as u64 | (read_u32(r)? as u64) << 32)
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+	let mut buf = [0; 4];
+	r.read_exact(&mut buf)?;
+	Ok(buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24)
+}
+
+fn write_f32<W: Write>(w: &mut W, x: f32) -> io::Result<()> {
+	write_u32(w, x.to_bits())
+}
+
+fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+	read_u32(r).map(f32::from_bits)
+}
+
+fn write_vec2<W: Write>(w: &mut W, v: Vec2) -> io::Result<()> {
+	write_f32(w, v.x)?;
+	write_f32(w, v.y)
+}
+
+fn read_vec2<R: Read>(r: &mut R) -> io::Result<Vec2> {
+	Ok(vec2(read_f32(r)?, read_f32(r)?))
+}
+
+fn write_vec3<W: Write>(w: &mut W, v: Vec3) -> io::Result<()> {
+	write_f32(w, v.x)?;
+	write_f32(w, v.y)?;
+	write_f32(w, v.z)
+}
+
+fn read_vec3<R: Read>(r: &mut R) -> io::Result<Vec3> {
+	Ok(vec3(read_f32(r)?, read_f32(r)?, read_f32(r)?))
+}
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+	write_u32(w, s.len() as u32)?;
+	w.write_all(s.as_bytes())
+}
+
+fn read_str<R: Read>(r: &mut R) -> io::Result<String> {
+	let len = read_u32(r)? as usize;
+	let mut buf = vec![];
+	r.take(len as u64).read_to_end(&mut buf)?;
+	if buf.len() != len {
+		return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "string is cut off"));
+	}
+	String::from_utf8(buf).or_else(|_| invalid_data("string is not valid UTF-8"))
+}
+
+fn write_bytes<W: Write>(w: &mut W, b: &[u8]) -> io::Result<()> {
+	write_u32(w, b.len() as u32)?;
+	w.write_all(b)
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+	let len = read_u32(r)? as usize;
+	let mut buf = vec![];
+	r.take(len as u64).read_to_end(&mut buf)?;
+	if buf.len() != len {
+		return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "bytes are cut off"));
+	}
+	Ok(buf)
+}
+
+fn write_opt_str<W: Write>(w: &mut W, s: Option<&str>) -> io::Result<()> {
+	match s {
+		Some(s) => {
+			write_bool(w, true)?;
+			write_str(w, s)
+		},
+		None => write_bool(w, false),
+	}
+}
+
+fn read_opt_str<R: Read>(r: &mut R) -> io::Result<Option<String>> {
+	if read_bool(r)? {
+		read_str(r).map(Some)
+	} else {
+		Ok(None)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::io;
+	use std::path::{Path, PathBuf};
+
+	use render::{LodSettings, MeshData, TextureChannel};
+	use render::parse::{ObjFile, ParseMode};
+
+	const OBJ: &'static str = "
+mtllib test.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+usemtl test
+f 1 2 3 4
+";
+
+	const MTL: &'static str = "
+newmtl test
+Kd 0.5 0.25 1
+map_Kd -o 0.5 0.5 -imfchan l test.png
+refl -type sphere sky.png
+";
+
+	#[test]
+	fn test_round_trip() {
+		let file = ObjFile::from_str("res/mesh/test.obj".into(), OBJ, &mut |p: &Path| {
+			if p == Path::new("res/mesh/test.mtl") {
+				Ok(MTL.to_string())
+			} else {
+				Err(io::Error::new(io::ErrorKind::NotFound, "not found"))
+			}
+		}, ParseMode::Strict).unwrap();
+		let mut data = MeshData::from_obj(&file);
+		data.generate_lods(&LodSettings { min_triangles: 1, ..LodSettings::default() });
+		assert_eq!(data.lods.len(), 1);
+		assert_eq!(data.sources, vec![(PathBuf::from("res/mesh/test.obj"), None), (PathBuf::from("res/mesh/test.mtl"), None)]);
+		data.images.push(("res/mesh/test.obj#image0".into(), vec![1, 2, 3]));
+
+		let mut buf = vec![];
+		super::write_mesh(&mut buf, &data).unwrap();
+		let read = super::read_mesh(&mut &buf[..]).unwrap();
+		assert_eq!(format!("{:?}", data), format!("{:?}", read));
+		let map_Kd = read.submeshes[0].material.map_Kd.as_ref().unwrap().1;
+		assert_eq!(map_Kd.imfchan, Some(TextureChannel::L));
+
+		// Truncated and corrupted caches must be rejected
+		assert!(super::read_mesh(&mut &buf[..buf.len() - 1]).is_err());
+		buf[4] += 1;
+		assert!(super::read_mesh(&mut &buf[..]).is_err());
+	}
+
+	#[test]
+	fn test_huge_counts() {
+		// A cache that claims to have far more vertices than it does is rejected without allocating space for them
+		let mut buf = vec![];
+		super::write_mesh(&mut buf, &MeshData::from_corners(vec![], None)).unwrap();
+		let header_len = 4 + 4 + 4;
+		let mut bad = buf[..header_len].to_vec();
+		bad.extend_from_slice(&[0xFF; 4]);
+		assert!(super::read_mesh(&mut &bad[..]).is_err());
+		// Or indices
+		let mut bad = buf[..header_len + 4].to_vec();
+		bad.extend_from_slice(&[0xFF; 4]);
+		assert!(super::read_mesh(&mut &bad[..]).is_err());
+		// Or level of detail indices. The last counts of the mesh are its number of levels of detail & images.
+		let mut bad = buf[..buf.len() - 8].to_vec();
+		bad.extend_from_slice(&[1, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+		assert!(super::read_mesh(&mut &bad[..]).is_err());
+		// Or a bigger image
+		let mut bad = buf[..buf.len() - 4].to_vec();
+		bad.extend_from_slice(&[1, 0, 0, 0, 1, 0, 0, 0, b'a', 0xFF, 0xFF, 0xFF, 0xFF]);
+		assert!(super::read_mesh(&mut &bad[..]).is_err());
+	}
+
+	#[test]
+	fn test_save_concurrently() {
+		use std::fs;
+		use std::thread;
+		use render::Primitive;
+		use vfs;
+
+		// Threads saving different meshes to the same cache must leave one of them whole
+		let id = "test_save_concurrently.obj";
+		let meshes: Vec<MeshData> = ["proc:box", "proc:icosphere?subdivisions=3"].iter()
+			.map(|id| Primitive::from_id(id).unwrap().generate())
+			.collect();
+		let threads: Vec<_> = meshes.iter().cloned().map(|data| thread::spawn(move || {
+			for _ in 0..20 {
+				super::save_cache(id, &data).unwrap();
+			}
+		})).collect();
+		for _ in 0..20 {
+			if let Some(read) = super::load_cache(id) {
+				assert!(meshes.iter().any(|m| format!("{:?}", m) == format!("{:?}", read)));
+			}
+		}
+		for t in threads {
+			t.join().unwrap();
+		}
+		let read = super::load_cache(id).unwrap();
+		assert!(meshes.iter().any(|m| format!("{:?}", m) == format!("{:?}", read)));
+		fs::remove_file(vfs::canonicalize_exe(super::cache_path(id))).unwrap();
+	}
+
+	#[test]
+	fn test_stamps() {
+		use std::fs::{self, File};
+		use std::io::Write;
+		use std::time::Duration;
+		use vfs;
+
+		let id = "test_stamps.obj";
+		let path = vfs::canonicalize_exe(id);
+		File::create(&path).and_then(|mut f| f.write_all(b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n")).unwrap();
+		let data = MeshData::from_file(id).unwrap();
+		assert_eq!(data.sources, vec![(PathBuf::from(id), vfs::file_stamp(id))]);
+		super::save_cache(id, &data).unwrap();
+		assert!(super::load_cache(id).is_some());
+
+		// A source that was written after it was read, even in the same tick, doesn't match its stamp
+		let mut stale = data.clone();
+		stale.sources[0].1.as_mut().unwrap().modified -= Duration::new(0, 1);
+		super::save_cache(id, &stale).unwrap();
+		assert!(super::load_cache(id).is_none());
+		super::save_cache(id, &data).unwrap();
+		File::create(&path).and_then(|mut f| f.write_all(b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 3 2\n\n")).unwrap();
+		assert!(super::load_cache(id).is_none());
+
+		// Meshes that weren't read from disk aren't cached
+		let mut from_memory = data.clone();
+		from_memory.sources[0].1 = None;
+		assert!(super::save_cache(id, &from_memory).is_err());
+
+		fs::remove_file(vfs::canonicalize_exe(super::cache_path(id))).unwrap();
+		fs::remove_file(&path).unwrap();
+	}
+}
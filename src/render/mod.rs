@@ -3,26 +3,46 @@ use prelude::*;
 
 use world::entity::Portal;
 
+use std::collections::HashMap;
+use std::mem;
 use std::rc::Rc;
 
-use glium::{Depth, DepthTest, Frame, Program, Surface, StencilTest, StencilOperation};
+use glium::{Depth, DepthTest, Frame, Surface, StencilTest, StencilOperation, VertexBuffer};
 use glium::draw_parameters::{Stencil, DrawParameters, BackfaceCullingMode};
 use glium::backend::Context;
 use glium::uniforms::MinifySamplerFilter;
 use glium::uniforms::MagnifySamplerFilter;
 use glium::uniforms::Sampler;
 use glium::texture::Texture2d;
+use glium::index::IndicesSource;
+use glium::vertex::MultiVerticesSource;
 
+pub use self::asset_cache::{CacheStats, RetryPolicy};
+pub use self::bounds::{Aabb, BoundingSphere, Frustum};
 pub use self::camera::Camera;
+pub use self::loader::LoadProgress;
+pub use self::lod::LodSettings;
+pub use self::primitive::{Primitive, PROC_PREFIX};
+pub use self::parse::UvProjection;
+pub use self::shader::Shader;
 pub use self::mesh::*;
 pub use self::texture::*;
 
+mod asset_cache;
+mod bounds;
 mod camera;
+mod loader;
+mod lod;
+mod primitive;
 mod mesh;
-mod parse;
+pub mod mesh_cache;
+pub mod parse;
+mod shader;
+mod tangent;
 mod texture;
 
-fn normalize_id(id: String) -> String {
+/// Converts the path separators in a texture or mesh ID into the ones used by the platform
+pub fn normalize_id(id: String) -> String {
 	use std::path::MAIN_SEPARATOR;
 	use std::path::is_separator;
 		
@@ -39,19 +59,45 @@ fn normalize_id(id: String) -> String {
 	}
 }
 
+pub const SHADER_DIR: &'static str = "res/shader/";
+
 const SHADER_PHONG: &'static str = "res/shader/phong";
 const SHADER_UNLIT: &'static str = "res/shader/unlit";
 
+/// Instances of a mesh at a level of detail that are waiting to be drawn
+struct Batch {
+	mesh_id: MeshID,
+	mesh: Rc<Mesh>,
+	lod: usize,
+	/// Model matrix of each instance
+	instances: Vec<Mat4>,
+}
+
+/// Per-instance attributes of the instanced shaders
+#[derive(Copy, Clone)]
+struct Instance {
+	i_model_mat: [[Flt; 4]; 4],
+}
+implement_vertex!(Instance, i_model_mat);
+
 pub struct Render {
-	#[allow(dead_code)]
 	ctx: Rc<Context>,
 	mesh_bank: MeshBank,
 	tex_bank: TextureBank,
-	phong_program: Program,
-	unlit_program: Program,
+	phong_program: Shader,
+	unlit_program: Shader,
+	/// Variants of the programs that take the model matrices of many instances of a mesh at once
+	phong_instanced_program: Shader,
+	unlit_instanced_program: Shader,
+	/// Meshes that have been drawn since the last flush, in the order they were first drawn
+	batches: Vec<Batch>,
+	/// Index in `batches` of each mesh & level of detail
+	batch_index: HashMap<(MeshID, usize), usize>,
 	camera_org: Camera,
 	light: Light,
 	portals: Option<[Portal; 2]>,
+	/// Region of the screen that the portal being drawn through covers, in normalized device coordinates
+	portal_region: (Vec2, Vec2),
 	camera_trans: Camera,
 }
 impl Render {
@@ -60,36 +106,83 @@ impl Render {
 			ctx: ctx.clone(),
 			mesh_bank: MeshBank::new(ctx.clone())?,
 			tex_bank: TextureBank::new(ctx.clone())?,
-			phong_program: parse::load_shader_program(&ctx, SHADER_PHONG)?,
-			unlit_program: parse::load_shader_program(&ctx, SHADER_UNLIT)?,
+			phong_program: Shader::new(&ctx, SHADER_PHONG, &[])?,
+			unlit_program: Shader::new(&ctx, SHADER_UNLIT, &[])?,
+			phong_instanced_program: Shader::new(&ctx, SHADER_PHONG, &[("INSTANCED", "1")])?,
+			unlit_instanced_program: Shader::new(&ctx, SHADER_UNLIT, &[("INSTANCED", "1")])?,
+			batches: vec![],
+			batch_index: HashMap::new(),
 			camera_org: c,
 			light: l,
 			portals: None,
+			portal_region: (vec2(-1.0, -1.0), vec2(1.0, 1.0)),
 			camera_trans: c,
 		})
 	}
 	
+	/// Reloads the meshes in the background. The old meshes are drawn until the new ones have loaded.
 	pub fn reload_meshes(&mut self) {
-		self.mesh_bank.clear_cache();
-		self.mesh_bank.load_meshes();
+		self.mesh_bank.reload();
 	}
 	
+	/// Reloads the textures in the background. The old textures are used until the new ones have loaded.
 	pub fn reload_textures(&mut self) {
-		self.tex_bank.clear_cache();
-		self.tex_bank.load_textures();
+		self.tex_bank.reload();
 	}
 	
-	pub fn reload_shaders(&mut self) {
-		match parse::load_shader_program(&self.ctx, SHADER_PHONG) {
-			Ok(p) => self.phong_program = p,
-			Err(e) => warn!("Could not reload shader '{}': {}", SHADER_PHONG, e),
+	/// Uploads the meshes & textures that have finished loading in the background. This should be called every frame.
+	pub fn upload_loaded(&mut self) {
+		self.mesh_bank.upload_loaded();
+		// Textures embedded in the meshes are decoded in the background, like the rest
+		for (id, png) in self.mesh_bank.take_images() {
+			self.tex_bank.add_png(id, png);
 		}
-		match parse::load_shader_program(&self.ctx, SHADER_UNLIT) {
-			Ok(p) => self.unlit_program = p,
-			Err(e) => warn!("Could not reload shader '{}': {}", SHADER_UNLIT, e),
+		self.tex_bank.upload_loaded();
+	}
+	
+	/// How many of the meshes & textures that have been requested have loaded, e.g. for a loading screen
+	pub fn loading_progress(&self) -> LoadProgress {
+		self.mesh_bank.progress() + self.tex_bank.progress()
+	}
+	
+	/// Sets how much GPU memory meshes & textures may take up, in bytes. Assets that haven't been used in the last
+	/// few frames are dropped, least recently used first, to keep within the budgets, and loaded again when they are
+	/// next drawn.
+	pub fn set_memory_budget(&mut self, mesh_bytes: usize, texture_bytes: usize) {
+		self.mesh_bank.set_budget(mesh_bytes);
+		self.tex_bank.set_budget(texture_bytes);
+	}
+	
+	/// Sets how meshes & textures that failed to load are retried
+	pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+		self.mesh_bank.set_retry_policy(retry);
+		self.tex_bank.set_retry_policy(retry);
+	}
+	
+	/// Gets the stats of the mesh & texture caches
+	pub fn cache_stats(&self) -> (CacheStats, CacheStats) {
+		(self.mesh_bank.stats(), self.tex_bank.stats())
+	}
+	
+	/// Reloads the shaders whose files (or the files they include) have changed
+	pub fn reload_shaders(&mut self) {
+		for shader in [&mut self.phong_program, &mut self.unlit_program,
+			&mut self.phong_instanced_program, &mut self.unlit_instanced_program].iter_mut() {
+			if !shader.is_outdated() {
+				continue;
+			}
+			match shader.reload(&self.ctx) {
+				Ok(()) => info!("Reloaded shader '{}'", shader.rel_base()),
+				Err(e) => warn!("Could not reload shader '{}': {}", shader.rel_base(), e),
+			}
 		}
 	}
 	
+	/// Sets the level of detail settings of a mesh, reloading it if it has already been loaded
+	pub fn set_lod_settings(&mut self, mesh_id: MeshID, settings: LodSettings) {
+		self.mesh_bank.set_lod_settings(mesh_id, settings);
+	}
+	
 	pub fn set_light(&mut self, l: Light) {
 		self.light = l;
 	}
@@ -100,6 +193,8 @@ impl Render {
 	}
 	
 	pub fn set_portals(&mut self, f: &mut Frame, portal_from: Portal, portal_to: Portal) {
+		// Finish the previous pass before the camera & stencil change
+		self.flush(f);
 		if let Some([portal_from, _]) = self.portals {
 			// Clear the stencil
 			f.clear_stencil(0);
@@ -109,6 +204,7 @@ impl Render {
 		}
 		self.portals = Some([portal_from, portal_to]);
 		self.camera_trans = self.camera_org.clone();
+		self.portal_region = self.portal_region(f.get_dimensions(), portal_from);
 		// Draw stencil
 		self.draw_portal_stencil(f, portal_from, false, true);
 		// Transform camera
@@ -116,6 +212,7 @@ impl Render {
 	}
 	
 	pub fn unset_portals(&mut self, f: &mut Frame) {
+		self.flush(f);
 		if let Some([portal_from, _]) = self.portals {
 			// Clear the stencil
 			f.clear_stencil(0);
@@ -127,6 +224,37 @@ impl Render {
 		self.camera_trans = self.camera_org;
 	}
 	
+	/// Gets the region of the screen that a portal covers, as seen by the original camera
+	fn portal_region(&self, dims: (u32, u32), p: Portal) -> (Vec2, Vec2) {
+		let full = (vec2(-1.0, -1.0), vec2(1.0, 1.0));
+		let mat_mvp = self.camera_org.projection_matrix(dims.0, dims.1) * self.camera_org.view_matrix() * p.model_matrix();
+		let mut min = vec2(Flt::INFINITY, Flt::INFINITY);
+		let mut max = vec2(Flt::NEG_INFINITY, Flt::NEG_INFINITY);
+		for &(x, y) in [(-0.5, 0.5), (0.5, 0.5), (0.5, -0.5), (-0.5, -0.5)].iter() {
+			let clip = mat_mvp * vec4(x, y, 0.0, 1.0);
+			if clip.w <= 0.0 {
+				// The portal goes behind the camera, so it could cover any of the screen
+				return full;
+			}
+			let ndc = clip.truncate().truncate() / clip.w;
+			min = vec2(min.x.min(ndc.x), min.y.min(ndc.y));
+			max = vec2(max.x.max(ndc.x), max.y.max(ndc.y));
+		}
+		(vec2(min.x.max(-1.0), min.y.max(-1.0)), vec2(max.x.min(1.0), max.y.min(1.0)))
+	}
+	
+	/// Checks if any of a mesh could be seen by the current camera. When drawing through a portal, only the part of
+	/// the screen that the portal covers is checked.
+	fn is_visible(&self, mesh: &Mesh, mat_view_projection: Mat4, mat_model: Mat4) -> bool {
+		let (min, max) = if self.portals.is_some() { self.portal_region } else { (vec2(-1.0, -1.0), vec2(1.0, 1.0)) };
+		if min.x >= max.x || min.y >= max.y {
+			return false;
+		}
+		let frustum = Frustum::new(mat_view_projection, min, max);
+		frustum.intersects_sphere(&mesh.bounding_sphere.transform(mat_model))
+			&& frustum.intersects_aabb(&mesh.aabb.transform(mat_model))
+	}
+	
 	pub fn draw_portal_stencil(&mut self, f: &mut Frame, p: Portal, write_depth: bool, write_stencil: bool) {
 		let dims = f.get_dimensions();
 		let mesh = self.mesh_bank.portal_stencil_mesh();
@@ -137,14 +265,16 @@ impl Render {
 		f.draw(
 			&mesh.vertices,
 			mesh.indices_source(),
-			&self.unlit_program,
+			self.unlit_program.program(),
 			&uniform! {
 				u_mvp: array4x4(mat_mvp),
 				u_model_mat: array4x4(mat_model),
-				u_color: array3(mesh.material.Ka),
-				u_d: mesh.material.d,
+				u_color: array3(mesh.submeshes[0].material.Ka),
+				u_d: mesh.submeshes[0].material.d,
 				u_map: Render::sample_tex(&map.0),
 				u_map_uv_scale: array2(map.1.uv_scale),
+				u_map_uv_offset: array2(map.1.uv_offset),
+				u_map_clamp: map.1.clamp,
 			},
 			&DrawParameters {
 				depth: Depth {
@@ -180,20 +310,105 @@ impl Render {
 			.magnify_filter(MagnifySamplerFilter::Nearest)
 	}
 	
+	/// Queues a mesh to be drawn. Nothing is drawn until `flush` is called, so that all of the instances of a mesh can
+	/// be drawn at once.
 	pub fn draw_mesh(&mut self, f: &mut Frame, mesh_id: MeshID, mat_model: Mat4) {
 		let dims = f.get_dimensions();
 		let mat_projection = self.camera_trans.projection_matrix(dims.0, dims.1);
-		let mat_mvp = mat_projection * self.camera_trans.view_matrix() * mat_model;
-		// TODO: Get a default mesh if failed to load mesh_id
-		let mesh = self.mesh_bank.get_mesh_or_default(mesh_id.clone());
-		let map_Ka = Render::get_tex(&mut self.tex_bank, mesh.material.get_map_Ka());
-		let map_Kd = Render::get_tex(&mut self.tex_bank, mesh.material.map_Kd.clone());
-		
-		self.draw_mesh_ext(f, mesh, mat_mvp, mat_model, map_Ka, map_Kd)
-			.map_err(|e| warn!("Could not draw mesh '{}': {}", mesh_id, e)).ok();
+		let mat_view_projection = mat_projection * self.camera_trans.view_matrix();
+		let mesh = self.mesh_bank.get_mesh_or_error(mesh_id.clone());
+		if !self.is_visible(&mesh, mat_view_projection, mat_model) {
+			return;
+		}
+		let lod = self.select_lod(&mesh, mat_model);
+		let key = (mesh_id, lod);
+		if let Some(&i) = self.batch_index.get(&key) {
+			self.batches[i].instances.push(mat_model);
+			return;
+		}
+		self.batch_index.insert(key.clone(), self.batches.len());
+		self.batches.push(Batch {
+			mesh_id: key.0,
+			mesh: mesh,
+			lod: lod,
+			instances: vec![mat_model],
+		});
 	}
 	
-	fn draw_mesh_ext(&mut self, f: &mut Frame, mesh: Rc<Mesh>, mat_mvp: Mat4, mat_model: Mat4, map_Ka: (Rc<Texture2d>, TextureOptions), map_Kd: (Rc<Texture2d>, TextureOptions)) -> GameResult<()> {
+	/// Draws the meshes that have been queued by `draw_mesh` since the last flush.
+	/// 
+	/// Each submesh (and so each material) of a mesh is drawn with one instanced draw call, however many times the
+	/// mesh was drawn. This is done whenever the portals change, and must be done at the end of each frame.
+	pub fn flush(&mut self, f: &mut Frame) {
+		let dims = f.get_dimensions();
+		let mat_projection = self.camera_trans.projection_matrix(dims.0, dims.1);
+		let mat_view_projection = mat_projection * self.camera_trans.view_matrix();
+		let batches = mem::replace(&mut self.batches, vec![]);
+		self.batch_index.clear();
+		for batch in batches.iter() {
+			// A single instance is drawn without an instance buffer
+			let instances = if batch.instances.len() > 1 { self.instance_buffer(&batch.instances) } else { None };
+			let mesh = &batch.mesh;
+			for (i, sm) in mesh.submeshes.iter().enumerate() {
+				let indices = match mesh.lod_indices_source(batch.lod, i) {
+					Some(indices) => indices,
+					None => {
+						warn!("Could not draw submesh {} of mesh '{}': its indices are out of bounds", i, batch.mesh_id);
+						continue;
+					}
+				};
+				let map_Ka = Render::get_tex(&mut self.tex_bank, sm.material.get_map_Ka());
+				let map_Kd = Render::get_tex(&mut self.tex_bank, sm.material.map_Kd.clone());
+				
+				let res = match instances {
+					Some(ref buf) => buf.per_instance()
+						.map_err(|e| format!("{:?}", e))
+						.and_then(|per| self.draw_submesh(f, (&mesh.vertices, per), true, sm, indices.clone(),
+							mat_view_projection, Mat4::identity(), &map_Ka, &map_Kd)),
+					None => batch.instances.iter()
+						.map(|&mat_model| self.draw_submesh(f, &mesh.vertices, false, sm, indices.clone(),
+							mat_view_projection, mat_model, &map_Ka, &map_Kd))
+						.collect(),
+				};
+				res.map_err(|e| warn!("Could not draw mesh '{}': {}", batch.mesh_id, e)).ok();
+			}
+		}
+	}
+	
+	/// Uploads the model matrices of instances of a mesh. Returns None if they can't be drawn with instancing, in which
+	/// case each instance is drawn on its own.
+	fn instance_buffer(&self, instances: &[Mat4]) -> Option<VertexBuffer<Instance>> {
+		let data: Vec<Instance> = instances.iter().map(|&m| Instance { i_model_mat: array4x4(m) }).collect();
+		let buf = match VertexBuffer::new(&self.ctx, &data) {
+			Ok(buf) => buf,
+			Err(e) => {
+				warn!("Could not create instance buffer: {}", e);
+				return None;
+			}
+		};
+		if buf.per_instance().is_err() {
+			return None;
+		}
+		Some(buf)
+	}
+	
+	/// Chooses the level of detail of a mesh from how much of the screen it takes up
+	fn select_lod(&self, mesh: &Mesh, mat_model: Mat4) -> usize {
+		let sphere = mesh.bounding_sphere.transform(mat_model);
+		let dist = (sphere.centre - self.camera_trans.pos).magnitude();
+		if dist <= sphere.radius {
+			return 0;
+		}
+		// Fraction of the screen's height that the sphere covers
+		let size = sphere.radius / (dist * (self.camera_trans.fovy / 2.0).tan());
+		mesh.lod_settings.select(size, mesh.lod_count())
+	}
+	
+	/// Draws a submesh. If `instanced`, `vertices` must include a per-instance buffer of `Instance`s, and `mat_model`
+	/// is ignored.
+	fn draw_submesh<'a, V>(&self, f: &mut Frame, vertices: V, instanced: bool, sm: &SubMesh, indices: IndicesSource, mat_view_projection: Mat4, mat_model: Mat4, map_Ka: &(Rc<Texture2d>, TextureOptions), map_Kd: &(Rc<Texture2d>, TextureOptions)) -> GameResult<()>
+		where V: MultiVerticesSource<'a>
+	{
 		let stencil = if self.portals.is_some() {
 			Stencil {
 				reference_value_counter_clockwise: 1,
@@ -213,39 +428,51 @@ impl Render {
 			backface_culling: BackfaceCullingMode::CullClockwise,
 			..Default::default()
 		};
-		let ret = if mesh.material.lighting_disabled {
+		let mat_mvp = mat_view_projection * mat_model;
+		let ret = if sm.material.lighting_disabled {
+			let program = if instanced { &self.unlit_instanced_program } else { &self.unlit_program };
 			f.draw(
-				&mesh.vertices,
-				mesh.indices_source(),
-				&self.unlit_program,
+				vertices,
+				indices,
+				program.program(),
 				&uniform! {
 					u_mvp: array4x4(mat_mvp),
 					u_model_mat: array4x4(mat_model),
-					u_color: array3(mesh.material.Ka),
-					u_d: mesh.material.d,
+					u_view_projection: array4x4(mat_view_projection),
+					u_color: array3(sm.material.Ka),
+					u_d: sm.material.d,
 					u_map: Render::sample_tex(&map_Ka.0),
 					u_map_uv_scale: array2(map_Ka.1.uv_scale),
+					u_map_uv_offset: array2(map_Ka.1.uv_offset),
+					u_map_clamp: map_Ka.1.clamp,
 				},
 				&params
 			)
 		} else {
+			let program = if instanced { &self.phong_instanced_program } else { &self.phong_program };
 			f.draw(
-				&mesh.vertices,
-				mesh.indices_source(),
-				&self.phong_program,
+				vertices,
+				indices,
+				program.program(),
 				&uniform! {
 					u_light_ambient: array4(self.light.ambient),
 					u_light_diffuse: array4(self.light.diffuse),
 					u_light_pos: array3(self.light.pos),
 					u_mvp: array4x4(mat_mvp),
 					u_model_mat: array4x4(mat_model),
-					u_Ka: array3(mesh.material.Ka),
-					u_Kd: array3(mesh.material.Kd),
-					u_d: mesh.material.d,
+					u_view_projection: array4x4(mat_view_projection),
+					u_Ka: array3(sm.material.Ka),
+					u_Kd: array3(sm.material.Kd),
+					u_d: sm.material.d,
+					u_illum: sm.material.illum as i32,
 					u_map_Ka: Render::sample_tex(&map_Ka.0),
 					u_map_Ka_uv_scale: array2(map_Ka.1.uv_scale),
+					u_map_Ka_uv_offset: array2(map_Ka.1.uv_offset),
+					u_map_Ka_clamp: map_Ka.1.clamp,
 					u_map_Kd: Render::sample_tex(&map_Kd.0),
 					u_map_Kd_uv_scale: array2(map_Kd.1.uv_scale),
+					u_map_Kd_uv_offset: array2(map_Kd.1.uv_offset),
+					u_map_Kd_clamp: map_Kd.1.clamp,
 				},
 				&params
 			)
@@ -253,7 +480,6 @@ impl Render {
 		ret.map_err(|e| format!("{}", e))
 	}
 }
-
 #[derive(Debug, Copy, Clone)]
 pub struct Light {
 	/// Ambient RGBA intensity
@@ -311,8 +537,14 @@ pub struct Material {
 	pub Ke: Vec3,
 	/// Specular exponent (TODO)
 	pub Ns: Flt,
+	/// Optical density (index of refraction) (TODO)
+	pub Ni: Flt,
 	/// Transparency
 	pub d: Flt,
+	/// Illumination model
+	///
+	/// 0 is a constant colour with no lighting, 1 and above are lit (TODO: specular, reflection & refraction models)
+	pub illum: u32,
 	/// Ambient texture map
 	pub map_Ka: Option<(TextureID, TextureOptions)>,
 	/// Diffuse texture map
@@ -329,8 +561,24 @@ pub struct Material {
 	pub bump: Option<(TextureID, TextureOptions)>,
 	/// Displacement map (TODO)
 	pub disp: Option<(TextureID, TextureOptions)>,
+	/// Decal texture map (TODO)
+	pub decal: Option<(TextureID, TextureOptions)>,
+	/// Reflection maps. Cube maps have one map for each side. (TODO)
+	pub refl: Vec<(TextureID, TextureOptions)>,
+	/// How uvs are generated for faces that don't have them (`uv_projection`)
+	pub uv_projection: UvProjection,
 }
 impl Material {
+	/// Gets the IDs of all of the textures that the material uses
+	pub fn textures(&self) -> Vec<&TextureID> {
+		let maps = [&self.map_Ka, &self.map_Kd, &self.map_Ks, &self.map_Ke, &self.map_Ns, &self.map_d,
+			&self.bump, &self.disp, &self.decal];
+		maps.iter()
+			.filter_map(|m| m.as_ref().map(|&(ref id, _)| id))
+			.chain(self.refl.iter().map(|&(ref id, _)| id))
+			.collect()
+	}
+
 	/// Getting the ambient texture map requires hacky logic.
 	/// 
 	/// For objects with a diffuse texture map, we want it to be that.
@@ -354,7 +602,9 @@ impl Default for Material {
 			Ks: vec3(0.0, 0.0, 0.0),
 			Ke: vec3(0.0, 0.0, 0.0),
 			Ns: 10.0,
+			Ni: 1.0,
 			d: 1.0,
+			illum: 1,
 			map_Ka: None,
 			map_Kd: None,
 			map_Ks: None,
@@ -363,6 +613,9 @@ impl Default for Material {
 			map_d: None,
 			bump: None,
 			disp: None,
+			decal: None,
+			refl: vec![],
+			uv_projection: UvProjection::default(),
 		}
 	}
 }
@@ -387,4 +640,39 @@ mod test {
 		
 		tni!("res/thing\\other/thing2", "res\\thing\\other\\thing2", "res/thing/other/thing2");
 	}
+	
+	#[test]
+	fn test_submeshes() {
+		use std::io;
+		use std::path::Path;
+		use super::MeshData;
+		use super::parse::{ObjFile, ParseMode};
+		
+		let src = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\ng a\nf 1 2 3 4\ng b\nf 1 3 2\n";
+		let file = ObjFile::from_str("res/mesh/test.obj".into(), src, &mut |_: &Path| {
+			Err(io::Error::new(io::ErrorKind::NotFound, "not found"))
+		}, ParseMode::Strict).unwrap();
+		let data = MeshData::from_obj(&file);
+		// Each group is a submesh, covering the indices of its faces
+		let ranges: Vec<_> = data.submeshes.iter().map(|sm| (sm.name.clone(), sm.range.clone())).collect();
+		assert_eq!(ranges, vec![(Some("a".to_string()), 0..6), (Some("b".to_string()), 6..9)]);
+		assert_eq!(data.indices.len(), 9);
+	}
+	
+	#[test]
+	fn test_error_cube() {
+		use prelude::*;
+		use super::MeshData;
+		
+		let data = MeshData::error_cube();
+		assert!(data.submeshes[0].material.lighting_disabled);
+		assert_eq!(data.submeshes[0].range, 0..data.indices.len());
+		for tri in data.indices.chunks(3) {
+			let p: Vec<Vec3> = tri.iter().map(|&i| Vec3::from(data.vertices[i as usize].pos())).collect();
+			// Every triangle is on the outside of the cube, facing out
+			let centre = (p[0] + p[1] + p[2]) / 3.0;
+			assert!(centre.x.abs().max(centre.y.abs()).max(centre.z.abs()) >= 0.5);
+			assert!((p[1] - p[0]).cross(p[2] - p[0]).dot(centre) > 0.0);
+		}
+	}
 }
\ No newline at end of file
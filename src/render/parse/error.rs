@@ -0,0 +1,148 @@
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+/// The different kinds of errors that can occur while parsing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+	/// The command is not recognized
+	UnknownCommand(String),
+	/// The command has the wrong number of arguments
+	BadArity(String),
+	/// An argument could not be parsed as a number
+	BadNumber(String),
+	/// An argument has an invalid value, e.g. an unknown texture option
+	BadArgument(String),
+	/// A face refers to a vertex, uv or normal that hasn't been read
+	IndexOutOfRange {
+		/// "vertex", "uv" or "normal"
+		what: &'static str,
+		/// Index as written in the file
+		index: isize,
+	},
+	/// A material is used that isn't defined in any of the material libraries
+	MissingMaterial(String),
+	/// The file could not be read
+	Io(String),
+	/// The file is not syntactically valid, e.g. malformed JSON in a .gltf file
+	Syntax(String),
+	/// The file is well formed, but its contents are invalid or unsupported
+	Invalid(String),
+}
+impl fmt::Display for ParseErrorKind {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		use self::ParseErrorKind::*;
+		match self {
+			&UnknownCommand(ref c) => write!(f, "unknown command `{}`", c),
+			&BadArity(ref c) => write!(f, "wrong number of arguments for `{}`", c),
+			&BadNumber(ref s) => write!(f, "invalid number `{}`", s),
+			&BadArgument(ref s) => write!(f, "invalid argument `{}`", s),
+			&IndexOutOfRange { what, index } => write!(f, "{} index {} is out of range", what, index),
+			&MissingMaterial(ref m) => write!(f, "unknown material `{}`", m),
+			&Io(ref e) => write!(f, "could not read file: {}", e),
+			&Syntax(ref e) => write!(f, "syntax error: {}", e),
+			&Invalid(ref e) => write!(f, "invalid file: {}", e),
+		}
+	}
+}
+
+/// An error in an .obj or .mtl file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+	pub kind: ParseErrorKind,
+	/// Path of the file the error is in
+	pub path: PathBuf,
+	/// Line of the error, starting from 1. If 0, the error isn't on a specific line.
+	pub line: usize,
+	/// Column of the error, starting from 1.
+	pub column: usize,
+	/// Length of the span that caused the error
+	pub len: usize,
+	/// Source code of the line the error is on
+	pub source: Option<String>,
+}
+impl ParseError {
+	/// Creates an error that isn't on a specific line of the file
+	pub fn new<P>(kind: ParseErrorKind, path: P) -> ParseError where P: AsRef<Path> {
+		ParseError {
+			kind: kind,
+			path: path.as_ref().to_path_buf(),
+			line: 0,
+			column: 0,
+			len: 0,
+			source: None,
+		}
+	}
+}
+impl ParseError {
+	/// Displays where the error is, with the source of the line if it is known
+	pub fn fmt_location(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		if self.line == 0 {
+			return write!(f, "  --> {}", self.path.display());
+		}
+		let lno = self.line.to_string();
+		let pad: String = lno.chars().map(|_| ' ').collect();
+		writeln!(f, "{} --> {}:{}:{}", pad, self.path.display(), self.line, self.column)?;
+		match self.source {
+			Some(ref src) => {
+				writeln!(f, "{} |", pad)?;
+				writeln!(f, "{} | {}", lno, src)?;
+				let spaces: String = src.chars().take(self.column.saturating_sub(1))
+					.map(|c| if c == '\t' { '\t' } else { ' ' })
+					.collect();
+				let carets: String = (0..self.len.max(1)).map(|_| '^').collect();
+				write!(f, "{} | {}{}", pad, spaces, carets)
+			},
+			None => write!(f, "{} |", pad),
+		}
+	}
+}
+impl fmt::Display for ParseError {
+	/// Displays the error in the same format as rustc's errors
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		writeln!(f, "error: {}", self.kind)?;
+		self.fmt_location(f)
+	}
+}
+impl Error for ParseError {
+	fn description(&self) -> &str {
+		use self::ParseErrorKind::*;
+		match self.kind {
+			UnknownCommand(_) => "unknown command",
+			BadArity(_) => "wrong number of arguments",
+			BadNumber(_) => "invalid number",
+			BadArgument(_) => "invalid argument",
+			IndexOutOfRange { .. } => "index out of range",
+			MissingMaterial(_) => "unknown material",
+			Io(_) => "could not read file",
+			Syntax(_) => "syntax error",
+			Invalid(_) => "invalid file",
+		}
+	}
+}
+impl From<ParseError> for String {
+	fn from(e: ParseError) -> String {
+		e.to_string()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_display() {
+		let e = ParseError {
+			kind: ParseErrorKind::BadNumber("1.o".into()),
+			path: PathBuf::from("res/mesh/test.obj"),
+			line: 12,
+			column: 7,
+			len: 3,
+			source: Some("v 1.0 1.o 2.0".into()),
+		};
+		let expected = "error: invalid number `1.o`\n   --> res/mesh/test.obj:12:7\n   |\n12 | v 1.0 1.o 2.0\n   |       ^^^";
+		assert_eq!(e.to_string(), expected);
+	}
+}
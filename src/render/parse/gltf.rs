@@ -0,0 +1,874 @@
+use prelude::*;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str;
+
+use render::{Material, TextureID, TextureOptions};
+use vfs::{self, FileStamp};
+
+use super::{Resolver, FsResolver};
+use super::error::{ParseError, ParseErrorKind, ParseResult};
+use super::json::{self, Json};
+use super::util;
+
+/// "glTF" as a little endian u32
+const GLB_MAGIC: u32 = 0x46546C67;
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+/// Signature at the start of every .png file
+const PNG_SIGNATURE: &'static [u8] = b"\x89PNG\r\n\x1a\n";
+
+// Accessor component types
+const BYTE: usize = 5120;
+const UNSIGNED_BYTE: usize = 5121;
+const SHORT: usize = 5122;
+const UNSIGNED_SHORT: usize = 5123;
+const UNSIGNED_INT: usize = 5125;
+const FLOAT: usize = 5126;
+
+// Primitive modes
+const TRIANGLES: usize = 4;
+const TRIANGLE_STRIP: usize = 5;
+const TRIANGLE_FAN: usize = 6;
+
+/// Sampler wrap mode
+const CLAMP_TO_EDGE: usize = 33071;
+
+/// Most elements an accessor without a buffer view can have. Their elements are all zeros, so their size isn't limited
+/// by the size of the file.
+const MAX_ZERO_ACCESSOR_COUNT: usize = 1 << 24;
+
+/// A primitive of a glTF mesh, with the transform of the node it is in applied
+#[derive(Debug, Clone)]
+pub struct GltfPrimitive {
+	/// Name of the mesh the primitive is part of, or of its node if the mesh has no name
+	pub name: Option<String>,
+	pub positions: Vec<Vec3>,
+	/// Vertex normals. If the file has none, these are generated from the faces.
+	pub normals: Vec<Vec3>,
+	/// Uvs of the first texture coordinate set. If the file has none, these are all 0.
+	pub uvs: Vec<Vec2>,
+	/// Colours of the first vertex colour set. If the file has none, these are all white.
+	pub colors: Vec<Vec3>,
+	/// Tangents, with the handedness of the bitangent in w. If the file has none, these are generated later.
+	pub tangents: Option<Vec<Vec4>>,
+	/// Triangles, as indices into the vertex attributes
+	pub indices: Vec<u32>,
+	/// Index into `GltfFile::materials`
+	pub material: Option<usize>,
+}
+
+/// A .gltf or .glb file.
+///
+/// Only the parts that the engine can draw are loaded: triangle meshes in the default scene, and the
+/// base colour, emissive & normal maps of their materials.
+#[derive(Debug)]
+pub struct GltfFile {
+	/// Filepath (relative to current exe pos) e.g. "res/mesh/test.gltf"
+	pub rel_path: String,
+	/// Files read (the file itself, then any external buffers) relative to the executable, along with their size &
+	/// modification time from just before they were read. The stamps are None for files that aren't on disk.
+	pub sources: Vec<(PathBuf, Option<FileStamp>)>,
+	pub materials: Vec<Material>,
+	/// Primitives of all of the meshes in the default scene
+	pub primitives: Vec<GltfPrimitive>,
+	/// .png images embedded in the file (as data URIs or in buffer views) that the materials use, along with the
+	/// texture IDs that the materials refer to them by, e.g. `res/mesh/foo.glb#image0`
+	pub images: Vec<(TextureID, Vec<u8>)>,
+}
+impl GltfFile {
+	/// Loads a .gltf or .glb file from a path relative to the executable
+	pub fn new(rel_path: String) -> ParseResult<GltfFile> {
+		let path = vfs::canonicalize_exe(&rel_path);
+		let stamp = vfs::file_stamp(&rel_path);
+		let mut data = vec![];
+		File::open(&path)
+			.and_then(|mut file| file.read_to_end(&mut data))
+			.map_err(|e| ParseError::new(ParseErrorKind::Io(e.to_string()), &rel_path))?;
+
+		let mut f = GltfFile::from_bytes(rel_path, &data, &mut FsResolver)?;
+		f.sources[0].1 = stamp;
+		Ok(f)
+	}
+
+	/// Parses a .gltf or .glb file from memory. Binary files are detected by their header.
+	///
+	/// `rel_path` is used for error messages, and to find external buffers & images using `resolver`.
+	pub fn from_bytes<Res>(rel_path: String, data: &[u8], resolver: &mut Res) -> ParseResult<GltfFile>
+			where Res: Resolver {
+		let path = PathBuf::from(&rel_path);
+		let (json_data, bin) = if read_u32(data, 0) == Some(GLB_MAGIC) {
+			split_glb(&path, data)?
+		} else {
+			(data, None)
+		};
+		let s = str::from_utf8(json_data).map_err(|_| invalid(&path, "JSON is not valid UTF-8"))?;
+		let root = json::parse_json(&path, s)?;
+
+		let version = root.get("asset").and_then(|a| a.get("version")).and_then(|v| v.as_str()).unwrap_or("");
+		if !version.starts_with("2.") {
+			return Err(invalid(&path, &format!("unsupported glTF version `{}`", version)));
+		}
+
+		let mut l = Loader {
+			path: &path,
+			root: &root,
+			buffers: vec![],
+			sources: vec![(path.clone(), None)],
+			images: vec![],
+		};
+		l.load_buffers(bin, resolver)?;
+		let mut materials = vec![];
+		for m in array(&root, "materials") {
+			materials.push(l.material(m, resolver)?);
+		}
+		let primitives = l.scene()?;
+
+		Ok(GltfFile {
+			rel_path: rel_path,
+			sources: l.sources,
+			materials: materials,
+			primitives: primitives,
+			images: l.images,
+		})
+	}
+}
+
+fn invalid(path: &Path, msg: &str) -> ParseError {
+	ParseError::new(ParseErrorKind::Invalid(msg.to_string()), path)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+	data.get(offset..offset + 4)
+		.map(|b| b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+}
+
+/// Splits a .glb file into its JSON and binary chunks
+fn split_glb<'a>(path: &Path, data: &'a [u8]) -> ParseResult<(&'a [u8], Option<&'a [u8]>)> {
+	let version = read_u32(data, 4).unwrap_or(0);
+	if version != 2 {
+		return Err(invalid(path, &format!("unsupported .glb version {}", version)));
+	}
+	let len = (read_u32(data, 8).unwrap_or(0) as usize).min(data.len());
+
+	let mut json = None;
+	let mut bin = None;
+	let mut offset = 12;
+	while offset + 8 <= len {
+		let chunk_len = read_u32(data, offset).unwrap() as usize;
+		let chunk_type = read_u32(data, offset + 4).unwrap();
+		let start = offset + 8;
+		let end = start + chunk_len;
+		if end > len {
+			return Err(invalid(path, "chunk is cut off"));
+		}
+		match chunk_type {
+			GLB_CHUNK_JSON if json.is_none() => json = Some(&data[start..end]),
+			GLB_CHUNK_BIN if bin.is_none() => bin = Some(&data[start..end]),
+			_ => {}, // Unknown chunks must be ignored
+		}
+		offset = end;
+	}
+	match json {
+		Some(json) => Ok((json, bin)),
+		None => Err(invalid(path, "no JSON chunk")),
+	}
+}
+
+/// Gets an array member of an object, or an empty array if it doesn't exist
+fn array<'a>(j: &'a Json, key: &str) -> &'a [Json] {
+	j.get(key).and_then(|a| a.as_array()).unwrap_or(&[])
+}
+
+fn index(j: &Json, key: &str) -> Option<usize> {
+	j.get(key).and_then(|i| i.as_usize())
+}
+
+/// Decodes standard base64, ignoring padding & whitespace
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+	let mut ret = Vec::with_capacity(s.len() * 3 / 4);
+	let mut acc = 0u32;
+	let mut bits = 0;
+	for c in s.bytes() {
+		let x = match c {
+			b'+' => 62,
+			b'/' => 63,
+			b'=' | b' ' | b'\n' | b'\r' | b'\t' => continue,
+			c if c >= b'A' && c <= b'Z' => c - b'A',
+			c if c >= b'a' && c <= b'z' => c - b'a' + 26,
+			c if c >= b'0' && c <= b'9' => c - b'0' + 52,
+			_ => return None,
+		};
+		acc = (acc << 6) | x as u32;
+		bits += 6;
+		if bits >= 8 {
+			bits -= 8;
+			ret.push((acc >> bits) as u8);
+		}
+	}
+	Some(ret)
+}
+
+/// Decodes `%XX` escapes in a URI
+fn decode_uri(uri: &str) -> String {
+	let b = uri.as_bytes();
+	let mut ret = vec![];
+	let mut i = 0;
+	while i < b.len() {
+		if b[i] == b'%' && i + 2 < b.len() {
+			if let Some(x) = str::from_utf8(&b[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+				ret.push(x);
+				i += 3;
+				continue;
+			}
+		}
+		ret.push(b[i]);
+		i += 1;
+	}
+	String::from_utf8_lossy(&ret).into_owned()
+}
+
+struct Loader<'a> {
+	path: &'a Path,
+	root: &'a Json,
+	buffers: Vec<Vec<u8>>,
+	sources: Vec<(PathBuf, Option<FileStamp>)>,
+	images: Vec<(TextureID, Vec<u8>)>,
+}
+impl<'a> Loader<'a> {
+	fn invalid(&self, msg: &str) -> ParseError {
+		invalid(self.path, msg)
+	}
+
+	/// Gets item `i` of the top level array `kind`, e.g. `accessors`
+	fn item(&self, kind: &str, i: usize) -> ParseResult<&'a Json> {
+		array(self.root, kind).get(i)
+			.ok_or_else(|| self.invalid(&format!("{} index {} is out of range", kind, i)))
+	}
+
+	/// Resolves a URI relative to the file
+	fn resolve(&self, uri: &str) -> PathBuf {
+		util::remove_parents(&self.path.join("..").join(decode_uri(uri)))
+	}
+
+	fn floats(&self, j: &Json, n: usize) -> ParseResult<Vec<Flt>> {
+		let ret: Vec<Flt> = j.as_array().unwrap_or(&[]).iter()
+			.filter_map(|x| x.as_f64())
+			.map(|x| x as Flt)
+			.collect();
+		if ret.len() != n {
+			return Err(self.invalid(&format!("expected an array of {} numbers", n)));
+		}
+		Ok(ret)
+	}
+
+	/// Decodes a base64 data URI
+	fn data_uri(&self, uri: &str) -> ParseResult<Vec<u8>> {
+		let (header, data) = uri.split_at(uri.find(',').unwrap_or(uri.len()));
+		if !header.ends_with(";base64") {
+			return Err(self.invalid("data URIs must be base64"));
+		}
+		decode_base64(&data[1..]).ok_or_else(|| self.invalid("invalid base64 in data URI"))
+	}
+
+	/// Gets the bytes of buffer view `i`
+	fn buffer_view(&self, i: usize) -> ParseResult<&[u8]> {
+		let view = self.item("bufferViews", i)?;
+		let buf = index(view, "buffer")
+			.and_then(|b| self.buffers.get(b))
+			.ok_or_else(|| self.invalid("buffer view has an invalid buffer"))?;
+		let offset = index(view, "byteOffset").unwrap_or(0);
+		let len = index(view, "byteLength").unwrap_or(0);
+		match offset.checked_add(len) {
+			Some(end) if end <= buf.len() => Ok(&buf[offset..end]),
+			_ => Err(self.invalid(&format!("buffer view {} is out of bounds of its buffer", i))),
+		}
+	}
+
+	fn load_buffers<Res>(&mut self, mut bin: Option<&[u8]>, resolver: &mut Res) -> ParseResult<()>
+			where Res: Resolver {
+		for (i, b) in array(self.root, "buffers").iter().enumerate() {
+			let len = index(b, "byteLength").ok_or_else(|| self.invalid("buffer has no byteLength"))?;
+			let data = match b.get("uri").and_then(|u| u.as_str()) {
+				Some(uri) if uri.starts_with("data:") => self.data_uri(uri)?,
+				Some(uri) => {
+					let rel_path = self.resolve(uri);
+					let stamp = resolver.stamp(&rel_path);
+					let data = resolver.read_bytes(&rel_path)
+						.map_err(|e| ParseError::new(ParseErrorKind::Io(format!("{}: {}", rel_path.display(), e)), self.path))?;
+					self.sources.push((rel_path, stamp));
+					data
+				},
+				// Only the first buffer may refer to the binary chunk of a .glb
+				None if i == 0 => match bin.take() {
+					Some(bin) => bin.to_vec(),
+					None => return Err(self.invalid("buffer has no uri")),
+				},
+				None => return Err(self.invalid("buffer has no uri")),
+			};
+			if data.len() < len {
+				return Err(self.invalid(&format!("buffer {} is shorter than its byteLength", i)));
+			}
+			self.buffers.push(data);
+		}
+		Ok(())
+	}
+
+	/// Reads an accessor. Returns the components of all of its elements, and the number of components per element.
+	fn accessor(&self, i: usize) -> ParseResult<(Vec<f64>, usize)> {
+		let acc = self.item("accessors", i)?;
+		let count = index(acc, "count").ok_or_else(|| self.invalid("accessor has no count"))?;
+		let ncomp = match acc.get("type").and_then(|t| t.as_str()).unwrap_or("") {
+			"SCALAR" => 1,
+			"VEC2" => 2,
+			"VEC3" => 3,
+			"VEC4" | "MAT2" => 4,
+			"MAT3" => 9,
+			"MAT4" => 16,
+			t => return Err(self.invalid(&format!("invalid accessor type `{}`", t))),
+		};
+		let ctype = index(acc, "componentType").unwrap_or(0);
+		let csize = match ctype {
+			BYTE | UNSIGNED_BYTE => 1,
+			SHORT | UNSIGNED_SHORT => 2,
+			UNSIGNED_INT | FLOAT => 4,
+			_ => return Err(self.invalid(&format!("invalid accessor component type {}", ctype))),
+		};
+		let normalized = acc.get("normalized").and_then(|n| n.as_bool()).unwrap_or(false);
+		if acc.get("sparse").is_some() {
+			return Err(self.invalid("sparse accessors are not supported"));
+		}
+
+		let view = match index(acc, "bufferView") {
+			Some(v) => self.item("bufferViews", v)?,
+			None => {
+				// No buffer view means all zeros
+				if count > MAX_ZERO_ACCESSOR_COUNT {
+					return Err(self.invalid(&format!("accessor {} has too many elements", i)));
+				}
+				return Ok((vec![0.0; count * ncomp], ncomp));
+			},
+		};
+		let buf = index(view, "buffer")
+			.and_then(|b| self.buffers.get(b))
+			.ok_or_else(|| self.invalid("buffer view has an invalid buffer"))?;
+		let view_offset = index(view, "byteOffset").unwrap_or(0);
+		let view_len = index(view, "byteLength").unwrap_or(0);
+		let stride = index(view, "byteStride").unwrap_or(ncomp * csize);
+		let offset = index(acc, "byteOffset").unwrap_or(0);
+		// The count & offsets come from the file, so they are checked for overflow before anything is allocated
+		let end = stride.checked_mul(count.saturating_sub(1))
+			.and_then(|x| x.checked_add(offset))
+			.and_then(|x| x.checked_add(ncomp * csize));
+		let in_bounds = match (end, view_offset.checked_add(view_len)) {
+			(Some(end), Some(view_end)) => end <= view_len && view_end <= buf.len(),
+			_ => false,
+		};
+		if count > 0 && !in_bounds {
+			return Err(self.invalid(&format!("accessor {} is out of bounds of its buffer", i)));
+		}
+
+		let mut ret = vec![0.0; count * ncomp];
+
+		for e in 0..count {
+			for c in 0..ncomp {
+				let o = view_offset + offset + e * stride + c * csize;
+				let b = &buf[o..o + csize];
+				let u = match csize {
+					1 => b[0] as u32,
+					2 => b[0] as u32 | (b[1] as u32) << 8,
+					_ => read_u32(b, 0).unwrap(),
+				};
+				ret[e * ncomp + c] = match (ctype, normalized) {
+					(FLOAT, _) => f32::from_bits(u) as f64,
+					(BYTE, false) => u as u8 as i8 as f64,
+					(BYTE, true) => (u as u8 as i8 as f64 / 127.0).max(-1.0),
+					(UNSIGNED_BYTE, true) => u as f64 / 255.0,
+					(SHORT, false) => u as u16 as i16 as f64,
+					(SHORT, true) => (u as u16 as i16 as f64 / 32767.0).max(-1.0),
+					(UNSIGNED_SHORT, true) => u as f64 / 65535.0,
+					_ => u as f64,
+				};
+			}
+		}
+		Ok((ret, ncomp))
+	}
+
+	fn vec3_accessor(&self, i: usize) -> ParseResult<Vec<Vec3>> {
+		let (v, n) = self.accessor(i)?;
+		if n != 3 {
+			return Err(self.invalid(&format!("accessor {} should be a VEC3", i)));
+		}
+		Ok(v.chunks(3).map(|c| vec3(c[0] as Flt, c[1] as Flt, c[2] as Flt)).collect())
+	}
+
+	fn material<Res>(&mut self, m: &Json, resolver: &mut Res) -> ParseResult<Material>
+			where Res: Resolver {
+		let mut ret = Material::default();
+		if let Some(pbr) = m.get("pbrMetallicRoughness") {
+			if let Some(c) = pbr.get("baseColorFactor") {
+				let c = self.floats(c, 4)?;
+				ret.Kd = vec3(c[0], c[1], c[2]);
+				ret.d = c[3];
+			}
+			if let Some(t) = pbr.get("baseColorTexture") {
+				ret.map_Kd = self.texture(t, resolver)?;
+			}
+		}
+		if let Some(c) = m.get("emissiveFactor") {
+			let c = self.floats(c, 3)?;
+			ret.Ke = vec3(c[0], c[1], c[2]);
+		}
+		if let Some(t) = m.get("emissiveTexture") {
+			ret.map_Ke = self.texture(t, resolver)?;
+		}
+		if let Some(t) = m.get("normalTexture") {
+			ret.bump = self.texture(t, resolver)?;
+			if let Some(&mut (_, ref mut opt)) = ret.bump.as_mut() {
+				opt.bump_mult = t.get("scale").and_then(|s| s.as_f64()).unwrap_or(1.0) as Flt;
+			}
+		}
+		if m.get("extensions").and_then(|e| e.get("KHR_materials_unlit")).is_some() {
+			ret.lighting_disabled = true;
+		}
+		Ok(ret)
+	}
+
+	/// Gets the texture referred to by a textureInfo object.
+	///
+	/// Images embedded in the file are added to `images`. Returns None if the texture has no image, or if an embedded
+	/// image isn't a .png file, as those are the only images that textures can be loaded from.
+	fn texture<Res>(&mut self, info: &Json, resolver: &mut Res) -> ParseResult<Option<(TextureID, TextureOptions)>>
+			where Res: Resolver {
+		let tex = index(info, "index").ok_or_else(|| self.invalid("texture info has no index"))?;
+		let tex = self.item("textures", tex)?;
+
+		let mut opt = TextureOptions::default();
+		if let Some(s) = index(tex, "sampler") {
+			let s = self.item("samplers", s)?;
+			opt.clamp = index(s, "wrapS") == Some(CLAMP_TO_EDGE) && index(s, "wrapT") == Some(CLAMP_TO_EDGE);
+		}
+		if let Some(t) = info.get("extensions").and_then(|e| e.get("KHR_texture_transform")) {
+			if let Some(o) = t.get("offset") {
+				let o = self.floats(o, 2)?;
+				opt.uv_offset = vec2(o[0], o[1]);
+			}
+			if let Some(s) = t.get("scale") {
+				let s = self.floats(s, 2)?;
+				opt.uv_scale = vec2(s[0], s[1]);
+			}
+		}
+
+		let (i, image) = match index(tex, "source") {
+			Some(i) => (i, self.item("images", i)?),
+			None => return Ok(None),
+		};
+		let png = match image.get("uri").and_then(|u| u.as_str()) {
+			Some(uri) if !uri.starts_with("data:") => {
+				let rel_path = self.resolve(uri);
+				return Ok(Some((resolver.texture_id(&rel_path), opt)));
+			},
+			Some(uri) => self.data_uri(uri)?,
+			None => match index(image, "bufferView") {
+				Some(v) => self.buffer_view(v)?.to_vec(),
+				None => return Err(self.invalid(&format!("image {} has no uri or buffer view", i))),
+			},
+		};
+		if !png.starts_with(PNG_SIGNATURE) {
+			warn!("Only .png images are supported: image {} of {}", i, self.path.display());
+			return Ok(None);
+		}
+		let id = format!("{}#image{}", self.path.display(), i);
+		if !self.images.iter().any(|&(ref other, _)| *other == id) {
+			self.images.push((id.clone(), png));
+		}
+		Ok(Some((id, opt)))
+	}
+
+	/// Gets the primitives of all the meshes in the default scene
+	fn scene(&self) -> ParseResult<Vec<GltfPrimitive>> {
+		let mut ret = vec![];
+		let nodes = array(self.root, "nodes");
+		let scenes = array(self.root, "scenes");
+		let roots: Vec<usize> = if !scenes.is_empty() {
+			let scene = self.item("scenes", index(self.root, "scene").unwrap_or(0))?;
+			array(scene, "nodes").iter().filter_map(|n| n.as_usize()).collect()
+		} else if !nodes.is_empty() {
+			// Without scenes, use all of the nodes that are not a child of another node
+			let children: Vec<usize> = nodes.iter()
+				.flat_map(|n| array(n, "children").iter().filter_map(|c| c.as_usize()))
+				.collect();
+			(0..nodes.len()).filter(|i| !children.contains(i)).collect()
+		} else {
+			// Without nodes, use all of the meshes untransformed
+			for m in array(self.root, "meshes") {
+				self.mesh(&mut ret, m, None, Mat4::identity())?;
+			}
+			return Ok(ret);
+		};
+		let mut visited = vec![false; nodes.len()];
+		for n in roots {
+			self.node(&mut ret, n, Mat4::identity(), &mut visited)?;
+		}
+		Ok(ret)
+	}
+
+	/// Adds the primitives of a node and its children. `visited` is the nodes that have been added already.
+	fn node(&self, ret: &mut Vec<GltfPrimitive>, i: usize, parent: Mat4, visited: &mut [bool]) -> ParseResult<()> {
+		let node = self.item("nodes", i)?;
+		// The nodes must form a tree, so a node that is reached twice is either in a cycle or shared
+		if visited[i] {
+			return Err(self.invalid(&format!("node {} is reached more than once, so the nodes don't form a tree", i)));
+		}
+		visited[i] = true;
+		let transform = parent * self.node_transform(node)?;
+		if let Some(m) = index(node, "mesh") {
+			let name = node.get("name").and_then(|n| n.as_str());
+			self.mesh(ret, self.item("meshes", m)?, name, transform)?;
+		}
+		for c in array(node, "children").iter().filter_map(|c| c.as_usize()) {
+			self.node(ret, c, transform, visited)?;
+		}
+		Ok(())
+	}
+
+	fn node_transform(&self, node: &Json) -> ParseResult<Mat4> {
+		if let Some(m) = node.get("matrix") {
+			let m = self.floats(m, 16)?;
+			// Column major, in the same way as cgmath
+			return Ok(Mat4::new(
+				m[0], m[1], m[2], m[3],
+				m[4], m[5], m[6], m[7],
+				m[8], m[9], m[10], m[11],
+				m[12], m[13], m[14], m[15]));
+		}
+		let t = match node.get("translation") {
+			Some(t) => { let t = self.floats(t, 3)?; vec3(t[0], t[1], t[2]) },
+			None => vec3(0.0, 0.0, 0.0),
+		};
+		let r = match node.get("rotation") {
+			Some(r) => { let r = self.floats(r, 4)?; Quat::new(r[3], r[0], r[1], r[2]) },
+			None => one(),
+		};
+		let s = match node.get("scale") {
+			Some(s) => { let s = self.floats(s, 3)?; vec3(s[0], s[1], s[2]) },
+			None => vec3(1.0, 1.0, 1.0),
+		};
+		Ok(Mat4::from_translation(t) * Mat4::from(r) * Mat4::from_nonuniform_scale(s.x, s.y, s.z))
+	}
+
+	fn mesh(&self, ret: &mut Vec<GltfPrimitive>, mesh: &Json, node_name: Option<&str>, transform: Mat4) -> ParseResult<()> {
+		let name = mesh.get("name").and_then(|n| n.as_str()).or(node_name).map(|n| n.to_string());
+		for p in array(mesh, "primitives") {
+			if let Some(p) = self.primitive(p, name.clone(), transform)? {
+				ret.push(p);
+			}
+		}
+		Ok(())
+	}
+
+	/// Reads a primitive. Returns None if it isn't made of triangles.
+	fn primitive(&self, p: &Json, name: Option<String>, transform: Mat4) -> ParseResult<Option<GltfPrimitive>> {
+		let mode = index(p, "mode").unwrap_or(TRIANGLES);
+		if mode != TRIANGLES && mode != TRIANGLE_STRIP && mode != TRIANGLE_FAN {
+			warn!("Skipping primitive with unsupported mode {} ({})", mode, self.path.display());
+			return Ok(None);
+		}
+		let attrs = p.get("attributes").ok_or_else(|| self.invalid("primitive has no attributes"))?;
+		let mut positions = match index(attrs, "POSITION") {
+			Some(i) => self.vec3_accessor(i)?,
+			None => return Err(self.invalid("primitive has no POSITION attribute")),
+		};
+		let n = positions.len();
+		let mut normals = match index(attrs, "NORMAL") {
+			Some(i) => Some(self.vec3_accessor(i)?),
+			None => None,
+		};
+		let uvs = match index(attrs, "TEXCOORD_0") {
+			Some(i) => {
+				let (v, ncomp) = self.accessor(i)?;
+				if ncomp != 2 {
+					return Err(self.invalid(&format!("accessor {} should be a VEC2", i)));
+				}
+				v.chunks(2).map(|c| vec2(c[0] as Flt, c[1] as Flt)).collect()
+			},
+			None => vec![vec2(0.0, 0.0); n],
+		};
+		let colors = match index(attrs, "COLOR_0") {
+			Some(i) => {
+				// Colours can be RGB or RGBA, but only RGB is used
+				let (v, ncomp) = self.accessor(i)?;
+				if ncomp != 3 && ncomp != 4 {
+					return Err(self.invalid(&format!("accessor {} should be a VEC3 or VEC4", i)));
+				}
+				v.chunks(ncomp).map(|c| vec3(c[0] as Flt, c[1] as Flt, c[2] as Flt)).collect()
+			},
+			None => vec![vec3(1.0, 1.0, 1.0); n],
+		};
+		let mut tangents = match index(attrs, "TANGENT") {
+			Some(i) => {
+				let (v, ncomp) = self.accessor(i)?;
+				if ncomp != 4 {
+					return Err(self.invalid(&format!("accessor {} should be a VEC4", i)));
+				}
+				Some(v.chunks(4).map(|c| vec4(c[0] as Flt, c[1] as Flt, c[2] as Flt, c[3] as Flt)).collect::<Vec<_>>())
+			},
+			None => None,
+		};
+		if normals.as_ref().map(|v| v.len() != n).unwrap_or(false) || uvs.len() != n || colors.len() != n
+		|| tangents.as_ref().map(|v| v.len() != n).unwrap_or(false) {
+			return Err(self.invalid("primitive attributes have different lengths"));
+		}
+
+		let elements: Vec<u32> = match index(p, "indices") {
+			Some(i) => self.accessor(i)?.0.into_iter().map(|x| x as u32).collect(),
+			None => (0..n as u32).collect(),
+		};
+		if elements.iter().any(|&i| i as usize >= n) {
+			return Err(self.invalid("primitive index is out of range"));
+		}
+		let mut indices = vec![];
+		match mode {
+			TRIANGLE_STRIP => for i in 0..elements.len().saturating_sub(2) {
+				// Every other triangle in a strip has the opposite winding
+				if i % 2 == 0 {
+					indices.extend_from_slice(&[elements[i], elements[i + 1], elements[i + 2]]);
+				} else {
+					indices.extend_from_slice(&[elements[i + 1], elements[i], elements[i + 2]]);
+				}
+			},
+			TRIANGLE_FAN => for i in 1..elements.len().saturating_sub(1) {
+				indices.extend_from_slice(&[elements[0], elements[i], elements[i + 1]]);
+			},
+			_ => indices.extend_from_slice(&elements[..elements.len() / 3 * 3]),
+		}
+
+		// Apply the node's transform
+		for p in positions.iter_mut() {
+			*p = (transform * p.extend(1.0)).truncate();
+		}
+		let m3 = Mat3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate());
+		if let Some(ref mut normals) = normals {
+			let normal_mat = m3.invert().map(|m| m.transpose()).unwrap_or(m3);
+			for n in normals.iter_mut() {
+				let t = normal_mat * *n;
+				*n = if t.magnitude2() == 0.0 { t } else { t.normalize() };
+			}
+		}
+		// Mirroring transforms flip the winding of the triangles, and the handedness of the tangents
+		let mirrored = m3.determinant() < 0.0;
+		if mirrored {
+			for t in indices.chunks_mut(3) {
+				t.swap(1, 2);
+			}
+		}
+		if let Some(ref mut tangents) = tangents {
+			for t in tangents.iter_mut() {
+				let d = m3 * t.truncate();
+				let d = if d.magnitude2() == 0.0 { d } else { d.normalize() };
+				*t = d.extend(if mirrored { -t.w } else { t.w });
+			}
+		}
+		let normals = match normals {
+			Some(normals) => normals,
+			None => generate_normals(&positions, &indices),
+		};
+
+		Ok(Some(GltfPrimitive {
+			name: name,
+			positions: positions,
+			normals: normals,
+			uvs: uvs,
+			colors: colors,
+			tangents: tangents,
+			indices: indices,
+			material: index(p, "material"),
+		}))
+	}
+}
+
+/// Generates vertex normals by averaging the normals of the faces around each vertex, weighted by area
+fn generate_normals(positions: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
+	let mut ret = vec![vec3(0.0, 0.0, 0.0); positions.len()];
+	for t in indices.chunks(3) {
+		let (a, b, c) = (positions[t[0] as usize], positions[t[1] as usize], positions[t[2] as usize]);
+		let n = (b - a).cross(c - a);
+		for &i in t.iter() {
+			ret[i as usize] += n;
+		}
+	}
+	for n in ret.iter_mut() {
+		*n = if n.magnitude2() == 0.0 { vec3(0.0, 1.0, 0.0) } else { n.normalize() };
+	}
+	ret
+}
+
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	use std::io;
+	use std::path::Path;
+
+	use super::*;
+
+	/// A triangle with vertices (0, 0, 0), (1, 0, 0) & (0, 1, 0), and u16 indices
+	const BUFFER: &'static str = "AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAIAAAA=";
+
+	fn gltf(buffer: &str) -> String {
+		format!(r#"{{
+	"asset": {{ "version": "2.0" }},
+	"scene": 0,
+	"scenes": [ {{ "nodes": [0] }} ],
+	"nodes": [
+		{{ "translation": [0, 0, -2], "children": [1] }},
+		{{ "name": "tri", "mesh": 0, "scale": [2, 2, 2] }}
+	],
+	"meshes": [ {{ "primitives": [ {{ "attributes": {{ "POSITION": 0 }}, "indices": 1, "material": 0 }} ] }} ],
+	"materials": [ {{ "pbrMetallicRoughness": {{ "baseColorFactor": [1, 0.5, 0, 0.75], "baseColorTexture": {{ "index": 0 }} }} }} ],
+	"textures": [ {{ "source": 0, "sampler": 0 }} ],
+	"samplers": [ {{ "wrapS": 33071, "wrapT": 33071 }} ],
+	"images": [ {{ "uri": "../tex/tri%20base.png" }} ],
+	"buffers": [ {{ {}"byteLength": 44 }} ],
+	"bufferViews": [ {{ "buffer": 0, "byteLength": 36 }}, {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }} ],
+	"accessors": [
+		{{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }},
+		{{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+	]
+}}"#, buffer)
+	}
+
+	fn check(f: &GltfFile) {
+		assert_eq!(f.primitives.len(), 1);
+		let p = &f.primitives[0];
+		assert_eq!(p.name, Some("tri".into()));
+		assert_eq!(p.positions, vec![vec3(0.0, 0.0, -2.0), vec3(2.0, 0.0, -2.0), vec3(0.0, 2.0, -2.0)]);
+		assert_eq!(p.normals, vec![vec3(0.0, 0.0, 1.0); 3]);
+		assert_eq!(p.indices, vec![0, 1, 2]);
+
+		let m = &f.materials[p.material.unwrap()];
+		assert_eq!(m.Kd, vec3(1.0, 0.5, 0.0));
+		assert_eq!(m.d, 0.75);
+		let (ref id, opt) = *m.map_Kd.as_ref().unwrap();
+		assert_eq!(Path::new(id), Path::new("res/tex/tri base.png"));
+		assert!(opt.clamp);
+	}
+
+	fn no_files(_: &Path) -> io::Result<String> {
+		Err(io::Error::new(io::ErrorKind::NotFound, "not found"))
+	}
+
+	#[test]
+	fn test_gltf_embedded() {
+		let s = gltf(&format!(r#""uri": "data:application/octet-stream;base64,{}", "#, BUFFER));
+		let f = GltfFile::from_bytes("res/mesh/tri.gltf".into(), s.as_bytes(), &mut no_files).unwrap();
+		check(&f);
+	}
+
+	/// Makes a .glb file from its JSON & binary chunk
+	fn glb(json: &str, bin: Vec<u8>) -> Vec<u8> {
+		fn chunk(ret: &mut Vec<u8>, ty: u32, mut data: Vec<u8>, pad: u8) {
+			while data.len() % 4 != 0 {
+				data.push(pad);
+			}
+			for &x in [data.len() as u32, ty].iter() {
+				ret.extend_from_slice(&[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8]);
+			}
+			ret.extend(data);
+		}
+		let mut body = vec![];
+		chunk(&mut body, GLB_CHUNK_JSON, json.as_bytes().to_vec(), b' ');
+		chunk(&mut body, GLB_CHUNK_BIN, bin, 0);
+		let mut data = vec![];
+		for &x in [GLB_MAGIC, 2, 12 + body.len() as u32].iter() {
+			data.extend_from_slice(&[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8]);
+		}
+		data.extend(body);
+		data
+	}
+
+	/// Encodes standard base64, with padding
+	fn encode_base64(data: &[u8]) -> String {
+		const CHARS: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+		let mut ret = String::new();
+		for c in data.chunks(3) {
+			let n = (c[0] as u32) << 16 | (*c.get(1).unwrap_or(&0) as u32) << 8 | *c.get(2).unwrap_or(&0) as u32;
+			for i in 0..4 {
+				if i <= c.len() {
+					ret.push(CHARS[(n >> (18 - 6 * i) & 63) as usize] as char);
+				} else {
+					ret.push('=');
+				}
+			}
+		}
+		ret
+	}
+
+	#[test]
+	fn test_glb() {
+		let data = glb(&gltf(""), decode_base64(BUFFER).unwrap());
+		let f = GltfFile::from_bytes("res/mesh/tri.glb".into(), &data, &mut no_files).unwrap();
+		check(&f);
+	}
+
+	#[test]
+	fn test_glb_embedded_image() {
+		// The image comes after the triangle in the binary chunk
+		let png = b"\x89PNG\r\n\x1a\nnot really a png".to_vec();
+		let mut bin = decode_base64(BUFFER).unwrap();
+		bin.extend_from_slice(&png);
+		let s = gltf("")
+			.replace(r#""uri": "../tex/tri%20base.png""#, r#""bufferView": 2, "mimeType": "image/png""#)
+			.replace(r#""byteLength": 44"#, &format!(r#""byteLength": {}"#, bin.len()))
+			.replace(r#""byteOffset": 36, "byteLength": 6 }"#,
+				&format!(r#""byteOffset": 36, "byteLength": 6 }}, {{ "buffer": 0, "byteOffset": 44, "byteLength": {} }}"#, png.len()));
+		let f = GltfFile::from_bytes("res/mesh/tri.glb".into(), &glb(&s, bin), &mut no_files).unwrap();
+		let m = &f.materials[0];
+		assert_eq!(m.map_Kd.as_ref().unwrap().0, "res/mesh/tri.glb#image0");
+		assert_eq!(f.images, vec![("res/mesh/tri.glb#image0".to_string(), png.clone())]);
+
+		// Images in data URIs too, but only .png files
+		let uri = |data: &[u8]| {
+			let s = gltf(&format!(r#""uri": "data:application/octet-stream;base64,{}", "#, BUFFER));
+			s.replace("../tex/tri%20base.png", &format!("data:image/png;base64,{}", encode_base64(data)))
+		};
+		let f = GltfFile::from_bytes("res/mesh/tri.gltf".into(), uri(&png).as_bytes(), &mut no_files).unwrap();
+		assert_eq!(f.materials[0].map_Kd.as_ref().unwrap().0, "res/mesh/tri.gltf#image0");
+		assert_eq!(f.images[0].1, png);
+		let f = GltfFile::from_bytes("res/mesh/tri.gltf".into(), uri(b"\xff\xd8\xff").as_bytes(), &mut no_files).unwrap();
+		assert!(f.materials[0].map_Kd.is_none() && f.images.is_empty());
+	}
+
+	#[test]
+	fn test_gltf_node_tree() {
+		let s = gltf(&format!(r#""uri": "data:application/octet-stream;base64,{}", "#, BUFFER));
+		let with_nodes = |nodes: &str| {
+			s.replace(r#"{ "translation": [0, 0, -2], "children": [1] },
+		{ "name": "tri", "mesh": 0, "scale": [2, 2, 2] }"#, nodes)
+		};
+		// A cycle
+		let bad = with_nodes(r#"{ "children": [1] }, { "mesh": 0, "children": [0] }"#);
+		assert!(GltfFile::from_bytes("res/mesh/tri.gltf".into(), bad.as_bytes(), &mut no_files).is_err());
+
+		// Each node uses the next twice, which would be walked 2^64 times if nodes could be shared
+		let nodes: Vec<String> = (0..64).map(|i| format!(r#"{{ "mesh": 0, "children": [{}, {}] }}"#, i + 1, i + 1)).collect();
+		let bad = with_nodes(&(nodes.join(", ") + r#", { "mesh": 0 }"#));
+		let e = GltfFile::from_bytes("res/mesh/tri.gltf".into(), bad.as_bytes(), &mut no_files).unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::Invalid("node 64 is reached more than once, so the nodes don't form a tree".into()));
+	}
+
+	#[test]
+	fn test_gltf_huge_count() {
+		// Counts that would overflow or use up all memory are errors
+		let s = gltf(&format!(r#""uri": "data:application/octet-stream;base64,{}", "#, BUFFER));
+		for &count in ["1e19", "4611686018427387904"].iter() {
+			let bad = s.replace(r#""count": 3, "type": "VEC3""#, &format!(r#""count": {}, "type": "VEC3""#, count));
+			assert!(GltfFile::from_bytes("res/mesh/tri.gltf".into(), bad.as_bytes(), &mut no_files).is_err());
+			let bad = s.replace(r#""bufferView": 0, "componentType": 5126, "count": 3"#,
+				&format!(r#""componentType": 5126, "count": {}"#, count));
+			assert!(GltfFile::from_bytes("res/mesh/tri.gltf".into(), bad.as_bytes(), &mut no_files).is_err());
+		}
+	}
+}
@@ -0,0 +1,293 @@
+#[allow(unused_imports)]
+use prelude::*;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::error::{ParseError, ParseErrorKind, ParseResult};
+
+/// A JSON value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+	Null,
+	Bool(bool),
+	Number(f64),
+	String(String),
+	Array(Vec<Json>),
+	Object(HashMap<String, Json>),
+}
+impl Json {
+	/// Gets a member of an object. Returns None if this is not an object, or it has no such member.
+	pub fn get(&self, key: &str) -> Option<&Json> {
+		match self {
+			&Json::Object(ref m) => m.get(key),
+			_ => None,
+		}
+	}
+
+	pub fn as_bool(&self) -> Option<bool> {
+		match self {
+			&Json::Bool(b) => Some(b),
+			_ => None,
+		}
+	}
+
+	pub fn as_f64(&self) -> Option<f64> {
+		match self {
+			&Json::Number(x) => Some(x),
+			_ => None,
+		}
+	}
+
+	/// Gets the value as a non-negative integer
+	pub fn as_usize(&self) -> Option<usize> {
+		match self {
+			&Json::Number(x) if x >= 0.0 && x.fract() == 0.0 => Some(x as usize),
+			_ => None,
+		}
+	}
+
+	pub fn as_str(&self) -> Option<&str> {
+		match self {
+			&Json::String(ref s) => Some(s),
+			_ => None,
+		}
+	}
+
+	pub fn as_array(&self) -> Option<&[Json]> {
+		match self {
+			&Json::Array(ref a) => Some(a),
+			_ => None,
+		}
+	}
+}
+
+/// Parses a JSON document. `path` is used for error messages.
+pub fn parse_json(path: &Path, s: &str) -> ParseResult<Json> {
+	let mut p = JsonParser { s: s, pos: 0, depth: 0 };
+	let ret = p.value()
+		.and_then(|v| {
+			p.skip_ws();
+			if p.pos != s.len() {
+				Err(p.error("expected end of file"))
+			} else {
+				Ok(v)
+			}
+		});
+	ret.map_err(|(msg, pos)| {
+		// Find the line that the error is on
+		let line_start = s[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+		let line_end = s[pos..].find('\n').map(|i| pos + i).unwrap_or(s.len());
+		let mut e = ParseError::new(ParseErrorKind::Syntax(msg), path);
+		e.line = s[..pos].matches('\n').count() + 1;
+		e.column = s[line_start..pos].chars().count() + 1;
+		e.len = 1;
+		e.source = Some(s[line_start..line_end].trim_matches('\r').to_string());
+		e
+	})
+}
+
+/// Error message & byte offset
+type JsonResult<T> = Result<T, (String, usize)>;
+
+/// How deeply arrays & objects can be nested, so that bad files can't overflow the stack
+const MAX_DEPTH: usize = 128;
+
+struct JsonParser<'a> {
+	s: &'a str,
+	pos: usize,
+	/// Number of arrays & objects that the parser is inside
+	depth: usize,
+}
+impl<'a> JsonParser<'a> {
+	fn error(&self, msg: &str) -> (String, usize) {
+		(msg.to_string(), self.pos)
+	}
+
+	fn peek(&self) -> Option<u8> {
+		self.s.as_bytes().get(self.pos).cloned()
+	}
+
+	fn skip_ws(&mut self) {
+		while let Some(c) = self.peek() {
+			match c {
+				b' ' | b'\t' | b'\n' | b'\r' => self.pos += 1,
+				_ => break,
+			}
+		}
+	}
+
+	fn expect(&mut self, lit: &str) -> JsonResult<()> {
+		if self.s[self.pos..].starts_with(lit) {
+			self.pos += lit.len();
+			Ok(())
+		} else {
+			Err(self.error(&format!("expected `{}`", lit)))
+		}
+	}
+
+	fn value(&mut self) -> JsonResult<Json> {
+		if self.depth >= MAX_DEPTH {
+			return Err(self.error("too deeply nested"));
+		}
+		self.depth += 1;
+		let ret = self.value_inner();
+		self.depth -= 1;
+		ret
+	}
+
+	fn value_inner(&mut self) -> JsonResult<Json> {
+		self.skip_ws();
+		match self.peek() {
+			Some(b'n') => self.expect("null").map(|_| Json::Null),
+			Some(b't') => self.expect("true").map(|_| Json::Bool(true)),
+			Some(b'f') => self.expect("false").map(|_| Json::Bool(false)),
+			Some(b'"') => self.string().map(Json::String),
+			Some(b'[') => {
+				self.pos += 1;
+				let mut ret = vec![];
+				self.skip_ws();
+				if self.peek() == Some(b']') {
+					self.pos += 1;
+					return Ok(Json::Array(ret));
+				}
+				loop {
+					ret.push(self.value()?);
+					self.skip_ws();
+					match self.peek() {
+						Some(b',') => self.pos += 1,
+						Some(b']') => {
+							self.pos += 1;
+							return Ok(Json::Array(ret));
+						},
+						_ => return Err(self.error("expected `,` or `]`")),
+					}
+				}
+			},
+			Some(b'{') => {
+				self.pos += 1;
+				let mut ret = HashMap::new();
+				self.skip_ws();
+				if self.peek() == Some(b'}') {
+					self.pos += 1;
+					return Ok(Json::Object(ret));
+				}
+				loop {
+					self.skip_ws();
+					if self.peek() != Some(b'"') {
+						return Err(self.error("expected string"));
+					}
+					let key = self.string()?;
+					self.skip_ws();
+					self.expect(":")?;
+					let value = self.value()?;
+					ret.insert(key, value);
+					self.skip_ws();
+					match self.peek() {
+						Some(b',') => self.pos += 1,
+						Some(b'}') => {
+							self.pos += 1;
+							return Ok(Json::Object(ret));
+						},
+						_ => return Err(self.error("expected `,` or `}`")),
+					}
+				}
+			},
+			Some(c) if c == b'-' || (c >= b'0' && c <= b'9') => self.number(),
+			Some(_) => Err(self.error("expected value")),
+			None => Err(self.error("unexpected end of file")),
+		}
+	}
+
+	fn number(&mut self) -> JsonResult<Json> {
+		let start = self.pos;
+		while let Some(c) = self.peek() {
+			match c {
+				b'-' | b'+' | b'.' | b'e' | b'E' => self.pos += 1,
+				c if c >= b'0' && c <= b'9' => self.pos += 1,
+				_ => break,
+			}
+		}
+		let s = &self.s[start..self.pos];
+		s.parse().map(Json::Number).map_err(|_| (format!("invalid number `{}`", s), start))
+	}
+
+	/// Parses a string, starting at the opening quote
+	fn string(&mut self) -> JsonResult<String> {
+		self.pos += 1;
+		let mut ret = String::new();
+		loop {
+			let c = match self.s[self.pos..].chars().next() {
+				Some(c) => c,
+				None => return Err(self.error("unterminated string")),
+			};
+			self.pos += c.len_utf8();
+			match c {
+				'"' => return Ok(ret),
+				'\\' => {
+					let c = match self.peek() {
+						Some(c) => c,
+						None => return Err(self.error("unterminated string")),
+					};
+					self.pos += 1;
+					match c {
+						b'"' => ret.push('"'),
+						b'\\' => ret.push('\\'),
+						b'/' => ret.push('/'),
+						b'b' => ret.push('\u{8}'),
+						b'f' => ret.push('\u{c}'),
+						b'n' => ret.push('\n'),
+						b'r' => ret.push('\r'),
+						b't' => ret.push('\t'),
+						b'u' => {
+							let mut c = self.hex4()?;
+							// Surrogate pairs
+							if c >= 0xD800 && c < 0xDC00 && self.s[self.pos..].starts_with("\\u") {
+								self.pos += 2;
+								let lo = self.hex4()?;
+								c = 0x10000 + ((c - 0xD800) << 10) + (lo.wrapping_sub(0xDC00) & 0x3FF);
+							}
+							ret.push(::std::char::from_u32(c).unwrap_or('\u{FFFD}'));
+						},
+						_ => {
+							self.pos -= 1;
+							return Err(self.error("invalid escape"));
+						}
+					}
+				},
+				c => ret.push(c),
+			}
+		}
+	}
+
+	fn hex4(&mut self) -> JsonResult<u32> {
+		let s = self.s.get(self.pos..self.pos + 4).unwrap_or("");
+		let c = u32::from_str_radix(s, 16).map_err(|_| self.error("invalid unicode escape"))?;
+		self.pos += 4;
+		Ok(c)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::path::Path;
+
+	use super::*;
+
+	#[test]
+	fn test_parse_json() {
+		let j = parse_json(Path::new("test.json"), r#" { "a": [1, -2.5e1, true, null], "b": { "c": "x\"é" } } "#).unwrap();
+		assert_eq!(j.get("a").and_then(|a| a.as_array()).map(|a| a.len()), Some(4));
+		assert_eq!(j.get("a").unwrap().as_array().unwrap()[1], Json::Number(-25.0));
+		assert_eq!(j.get("b").and_then(|b| b.get("c")).and_then(|c| c.as_str()), Some("x\"\u{e9}"));
+
+		let e = parse_json(Path::new("test.json"), "{\n  \"a\": [1 2]\n}").unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::Syntax("expected `,` or `]`".into()));
+		assert_eq!((e.line, e.column), (2, 11));
+
+		let nested = |n: usize| "[".repeat(n) + &"]".repeat(n);
+		assert!(parse_json(Path::new("test.json"), &nested(MAX_DEPTH)).is_ok());
+		let e = parse_json(Path::new("test.json"), &nested(100000)).unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::Syntax("too deeply nested".into()));
+	}
+}
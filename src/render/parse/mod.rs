@@ -4,21 +4,36 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
 use std::iter::Peekable;
 
 use render::{TextureID, TextureOptions, Material};
 
-use glium::Program;
-use glium::backend::Context;
+pub use self::error::{ParseError, ParseErrorKind, ParseResult};
+pub use self::gltf::{GltfFile, GltfPrimitive};
+pub use self::projection::{Axis, UvProjection};
+pub use self::resolver::{Resolver, FsResolver};
+pub use self::shader::{load_compute_shader, load_shader_program, Preprocessor};
+pub use self::write::{save_obj, write_obj, write_mtl};
 
+mod error;
+mod gltf;
+mod json;
+mod ply;
+mod projection;
+mod stl;
+mod resolver;
+mod shader;
+mod triangulate;
 mod util;
+mod write;
 
-use vfs;
+use vfs::{self, FileStamp};
 
 /// Face is a series of 3 points representing a triangle
 ///
 /// f 1/2/3 4/5/6 7/8/9 == vec3(vec3(1,2,3), vec3(4,5,6), vec3(7,8,9))
+///
+/// Faces with more than 3 points are triangulated when they are parsed.
 #[derive(Copy, Clone, Debug)]
 pub struct IndexInfo {
 	pub vert: Idx,
@@ -50,20 +65,65 @@ impl PreIndexInfo {
 	}
 }
 
+/// A range of faces that share the same object, group and material
+#[derive(Clone, Debug)]
+pub struct FaceRange {
+	/// Name of the object that the faces are in (`o`)
+	pub object: Option<String>,
+	/// Name of the group that the faces are in (`g`)
+	pub group: Option<String>,
+	/// Material used for the faces (`usemtl`)
+	pub material: Option<String>,
+	/// Index of the first face in the range
+	pub start: usize,
+	/// Number of faces in the range
+	pub len: usize,
+}
+impl FaceRange {
+	fn same_kind(&self, o: &FaceRange) -> bool {
+		self.object == o.object && self.group == o.group && self.material == o.material
+	}
+}
+
+/// How strictly files are parsed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseMode {
+	/// Any statement that isn't understood is an error
+	Strict,
+	/// Unknown & unsupported statements (e.g. `l`, `vp` or `Tf`) and texture options are skipped, and added to
+	/// `ObjFile::warnings`
+	Lenient,
+}
+impl Default for ParseMode {
+	/// The game is lenient, so that files from other exporters still load
+	fn default() -> ParseMode {
+		ParseMode::Lenient
+	}
+}
+
 #[derive(Debug)]
 pub struct ObjFile {
 	/// Filepath (relative to current exe pos) e.g. "res/mesh/test.obj"
 	pub rel_path: String,
-	/// Absolute filepath
+	/// Absolute filepath. If the file was parsed from memory, this is the same as `rel_path`.
 	pub path: PathBuf,
-	/// Name of the object
+	/// Name of the first object in the file
 	pub name: Option<String>,
 	/// Materials referenced
 	pub materials: HashMap<String, Material>,
-	/// Material used for the object
-	pub material: Option<String>,
+	/// Material libraries read, relative to the executable
+	pub mtllibs: Vec<PathBuf>,
+	/// Files read (the file itself, then its material libraries) relative to the executable, along with their size &
+	/// modification time from just before they were read. The stamps are None for files that aren't on disk.
+	pub sources: Vec<(PathBuf, Option<FileStamp>)>,
+	/// Ranges of faces that share an object, group & material, in the order they were read.
+	///
+	/// These index into both `pre_faces` and `faces`.
+	pub ranges: Vec<FaceRange>,
 	/// Vertices read
 	pub vertices: Vec<Vec3>,
+	/// Colour of each vertex in `vertices`. White if the file doesn't give one.
+	pub colors: Vec<Vec3>,
 	/// Uvs read
 	pub uvs: Vec<Vec2>,
 	/// Vertex normals read
@@ -73,36 +133,140 @@ pub struct ObjFile {
 	/// Guaranteed to be valid (every index points to a vertex/uv/normal that has been read)
 	pub faces: Vec<Vector3<IndexInfo>>,
 	pub pre_faces: Vec<Vector3<PreIndexInfo>>,
+	/// Smoothing group of each face in `pre_faces` (`s`). 0 means smoothing is off.
+	pub smoothing_groups: Vec<u32>,
+	/// Statements that were skipped in lenient mode, in the order they were read
+	pub warnings: Vec<ParseError>,
 }
 impl ObjFile {
-	pub fn new(rel_path: String) -> GameResult<ObjFile> {
-		let mut f = ObjFile {
-			rel_path: rel_path.clone(),
-			path: vfs::canonicalize_exe(&rel_path),
+	/// Loads an .obj file from a path relative to the executable
+	pub fn new(rel_path: String, mode: ParseMode) -> ParseResult<ObjFile> {
+		let path = vfs::canonicalize_exe(&rel_path);
+		let stamp = vfs::file_stamp(&rel_path);
+		let mut s = String::new();
+		File::open(&path)
+			.and_then(|mut file| file.read_to_string(&mut s))
+			.map_err(|e| ParseError::new(ParseErrorKind::Io(e.to_string()), &rel_path))?;
+
+		let mut f = ObjFile::parse(rel_path, path, &s, &mut FsResolver, mode)?;
+		f.sources[0].1 = stamp;
+		Ok(f)
+	}
+
+	/// Parses an .obj file from a reader.
+	///
+	/// `rel_path` is used for error messages, and to find the files referenced by `mtllib` using `resolver`.
+	pub fn from_reader<R, Res>(rel_path: String, mut r: R, resolver: &mut Res, mode: ParseMode) -> ParseResult<ObjFile>
+			where R: Read, Res: Resolver {
+		let mut s = String::new();
+		r.read_to_string(&mut s)
+			.map_err(|e| ParseError::new(ParseErrorKind::Io(e.to_string()), &rel_path))?;
+		ObjFile::from_str(rel_path, &s, resolver, mode)
+	}
+
+	/// Parses an .obj file from a string.
+	///
+	/// `rel_path` is used for error messages, and to find the files referenced by `mtllib` using `resolver`.
+	pub fn from_str<Res>(rel_path: String, s: &str, resolver: &mut Res, mode: ParseMode) -> ParseResult<ObjFile>
+			where Res: Resolver {
+		let path = PathBuf::from(&rel_path);
+		ObjFile::parse(rel_path, path, s, resolver, mode)
+	}
+
+	fn parse<Res>(rel_path: String, path: PathBuf, s: &str, resolver: &mut Res, mode: ParseMode) -> ParseResult<ObjFile>
+			where Res: Resolver {
+		let mut f = ObjFile::empty(rel_path, path);
+		parse_string(&mut f, s, resolver, mode)?;
+		f.finish()?;
+
+		// Each kind of statement is logged once, along with every line it was skipped on
+		let mut skipped: Vec<(&Path, &ParseErrorKind, Vec<String>)> = vec![];
+		for w in f.warnings.iter() {
+			match skipped.iter_mut().find(|s| s.0 == w.path.as_path() && *s.1 == w.kind) {
+				Some(s) => s.2.push(w.line.to_string()),
+				None => skipped.push((&w.path, &w.kind, vec![w.line.to_string()])),
+			}
+		}
+		for (path, kind, lines) in skipped {
+			warn!("Skipped {} unsupported statement(s) in {} on line(s) {}: {}",
+				lines.len(), path.display(), lines.join(", "), kind);
+		}
+		if f.ranges.iter().all(|r| r.material.is_none()) {
+			warn!("Object file loaded without a material: {}", &f.rel_path);
+		}
+		Ok(f)
+	}
+
+	/// Creates a file with nothing in it, so that it can be filled in by a parser
+	fn empty(rel_path: String, path: PathBuf) -> ObjFile {
+		let sources = vec![(PathBuf::from(&rel_path), None)];
+		ObjFile {
+			rel_path: rel_path,
+			path: path,
 			name: None,
 			materials: HashMap::new(),
-			material: None,
+			mtllibs: vec![],
+			sources: sources,
+			ranges: vec![],
 			vertices: vec![],
+			colors: vec![],
 			uvs: vec![],
 			normals: vec![],
 			faces: vec![],
 			pre_faces: vec![],
-		};
-
-		parse_file(&mut f)?;
+			smoothing_groups: vec![],
+			warnings: vec![],
+		}
+	}
 
+	/// Validates the file and calculates `faces` from `pre_faces`. Called once everything has been read.
+	fn finish(&mut self) -> ParseResult<()> {
 		// Validate pre_faces, so that we know all indices are in bounds
-		f.validate()?;
+		self.validate()?;
 
 		// Calculate faces from pre_faces
-		f.calculate_faces();
+		self.calculate_faces();
+		Ok(())
+	}
 
-		Ok(f)
+	/// Calculates the smooth normal of each vertex in each smoothing group.
+	///
+	/// The normals of the faces around a vertex are weighted by the angle of the face at that vertex. Vertices where the
+	/// normals cancel out, such as where the two sides of double sided geometry meet, are left out.
+	fn calculate_smooth_normals(&self) -> HashMap<(Idx, u32), Vec3> {
+		let mut ret: HashMap<(Idx, u32), Vec3> = HashMap::new();
+		for (f, &s) in self.pre_faces.iter().zip(self.smoothing_groups.iter()) {
+			if s == 0 {
+				continue;
+			}
+			let is = [f.x.vert, f.y.vert, f.z.vert];
+			let vs = [self.vertices[is[0] as usize], self.vertices[is[1] as usize], self.vertices[is[2] as usize]];
+			let n = (vs[1] - vs[0]).cross(vs[2] - vs[0]);
+			if n.magnitude2() == 0.0 {
+				continue; // Degenerate faces don't contribute
+			}
+			let n = n.normalize();
+			for i in 0..3 {
+				let a = vs[(i + 1) % 3] - vs[i];
+				let b = vs[(i + 2) % 3] - vs[i];
+				let cos = a.dot(b) / (a.magnitude() * b.magnitude());
+				let angle = cos.max(-1.0).min(1.0).acos();
+				*ret.entry((is[i], s)).or_insert(zero()) += n * angle;
+			}
+		}
+		ret.retain(|_, n| n.magnitude2() > 1e-10);
+		for n in ret.values_mut() {
+			*n = n.normalize();
+		}
+		ret
 	}
 
 	/// Calculates the faces from pre_faces.
 	///
-	/// To calculate missing uvs, we are using a flat generating algorithm.
+	/// Missing normals are flat unless the face is in a smoothing group, in which case they are
+	/// averaged over the faces in the same group that share the vertex.
+	///
+	/// Missing uvs are generated using the `uv_projection` of the face's material.
 	fn calculate_faces(&mut self) {
 		trace!("Calculating faces...");
 		if self.pre_faces.is_empty() {
@@ -123,31 +287,63 @@ impl ObjFile {
 			}
 		}
 
+		// Projection used to generate the uvs of each face, from the face's material
+		let mut projections = Vec::with_capacity(self.pre_faces.len());
+		for r in self.ranges.iter() {
+			let projection = r.material.as_ref()
+				.and_then(|m| self.materials.get(m))
+				.map(|m| m.uv_projection)
+				.unwrap_or_default();
+			projections.extend((0..r.len).map(|_| projection));
+		}
+
+		let smooth_normals = self.calculate_smooth_normals();
+		// Indices of the smooth normals that have been added to `normals`
+		let mut smooth_idxs: HashMap<(Idx, u32), Idx> = HashMap::new();
+
 		// Process faces
-		for f in self.pre_faces.iter() {
+		for ((f, &s), &projection) in self.pre_faces.iter().zip(self.smoothing_groups.iter()).zip(projections.iter()) {
 			let v0 = self.vertices[f.x.vert as usize];
 			let v1 = self.vertices[f.y.vert as usize];
 			let v2 = self.vertices[f.z.vert as usize];
+			let face_normal = (v1 - v0).cross(v2 - v0);
+			let face_normal = if face_normal.magnitude2() == 0.0 { vec3(0.0, 1.0, 0.0) } else { face_normal.normalize() };
 
 			// Calculate normals
-			let normals = if f.x.norm.is_none() || f.y.norm.is_none() || f.z.norm.is_none() {
-				let n = (v1 - v0).cross(v2 - v0).normalize();
-				self.normals.push(n);
+			let normals = if s != 0 && (f.x.norm.is_none() || f.y.norm.is_none() || f.z.norm.is_none()) {
+				let normals = &mut self.normals;
+				let mut smooth_idx = |vert: Idx| {
+					match smooth_normals.get(&(vert, s)) {
+						Some(&n) => *smooth_idxs.entry((vert, s)).or_insert_with(|| {
+							normals.push(n);
+							(normals.len() - 1) as Idx
+						}),
+						// The faces around the vertex cancel out, so it has no smooth normal
+						None => {
+							normals.push(face_normal);
+							(normals.len() - 1) as Idx
+						},
+					}
+				};
+				let n0 = f.x.norm.unwrap_or_else(|| smooth_idx(f.x.vert));
+				let n1 = f.y.norm.unwrap_or_else(|| smooth_idx(f.y.vert));
+				let n2 = f.z.norm.unwrap_or_else(|| smooth_idx(f.z.vert));
+				vec3(n0, n1, n2)
+			} else if f.x.norm.is_none() || f.y.norm.is_none() || f.z.norm.is_none() {
+				self.normals.push(face_normal);
 				let i = (self.normals.len() - 1) as Idx;
 				vec3(f.x.norm.unwrap_or(i), f.y.norm.unwrap_or(i), f.z.norm.unwrap_or(i))
 			} else {
 				vec3(f.x.norm.unwrap()    , f.y.norm.unwrap()    , f.z.norm.unwrap()    )
 			};
 			// Calculate uvs
-			let calc_prop = |f, min, max| { (f - min) / (min - max) };
-			let calc_uv = |v: Vec3| { vec2(calc_prop(v.x, min.x, max.x), calc_prop(v.z, min.z, max.z)) };
-			let calc_uv_idx = |uvs: &mut Vec<_>, v| { uvs.push(calc_uv(v)); uvs.len() as Idx - 1 };
-
 			let uvs = &mut self.uvs;
 			let uvs = if f.x.uv.is_none() || f.y.uv.is_none() || f.z.uv.is_none() {
-				let uv0 = f.x.uv.unwrap_or_else(|| calc_uv_idx(uvs, v0));
-				let uv1 = f.y.uv.unwrap_or_else(|| calc_uv_idx(uvs, v1));
-				let uv2 = f.z.uv.unwrap_or_else(|| calc_uv_idx(uvs, v2));
+				let calc = projection.project([v0, v1, v2], min, max);
+				let mut calc_uv_idx = |i: usize| { uvs.push(calc[i]); uvs.len() as Idx - 1 };
+				let uv0 = f.x.uv.unwrap_or_else(|| calc_uv_idx(0));
+				let uv1 = f.y.uv.unwrap_or_else(|| calc_uv_idx(1));
+				let uv2 = f.z.uv.unwrap_or_else(|| calc_uv_idx(2));
 				vec3(uv0, uv1, uv2)
 			} else {
 				vec3(f.x.uv.unwrap(), f.y.uv.unwrap(), f.z.uv.unwrap())
@@ -161,87 +357,147 @@ impl ObjFile {
 		}
 	}
 
+	/// Records a statement that was skipped in lenient mode
+	fn skip(&mut self, e: ParseError) {
+		self.warnings.push(e);
+	}
+
+	/// Adds a face to `pre_faces`, extending the last face range if `cur` matches it.
+	fn push_pre_face(&mut self, face: Vector3<PreIndexInfo>, cur: &FaceRange, smoothing: u32) {
+		let i = self.pre_faces.len();
+		self.pre_faces.push(face);
+		self.smoothing_groups.push(smoothing);
+		if let Some(last) = self.ranges.last_mut() {
+			if last.same_kind(cur) && last.start + last.len == i {
+				last.len += 1;
+				return;
+			}
+		}
+		let mut r = cur.clone();
+		r.start = i;
+		r.len = 1;
+		self.ranges.push(r);
+	}
+
 	/// Validate the file
-	fn validate(&self) -> GameResult<()> {
-		// Check if material is one of the materials read
-		if let Some(ref m) = self.material {
-			if self.materials.get(m).is_none() {
-				return Err(format!("Invalid obj file ({}): Unknown material `{}`", self.rel_path, m));
+	///
+	/// Most problems are caught with their location while parsing, so this is a last line of defense.
+	fn validate(&self) -> ParseResult<()> {
+		// Check if all materials used are one of the materials read
+		for r in self.ranges.iter() {
+			if let Some(ref m) = r.material {
+				if self.materials.get(m).is_none() {
+					return Err(ParseError::new(ParseErrorKind::MissingMaterial(m.clone()), &self.rel_path));
+				}
 			}
 		}
 
 		// Check if all pre_faces are valid
-		for (i, f) in self.pre_faces.iter().enumerate() {
-			fn check_index_info(i: usize, o: &ObjFile, ii: PreIndexInfo) -> GameResult<()> {
+		for f in self.pre_faces.iter() {
+			fn check_index_info(o: &ObjFile, ii: PreIndexInfo) -> ParseResult<()> {
+				let err = |what, i: Idx| {
+					Err(ParseError::new(ParseErrorKind::IndexOutOfRange { what: what, index: i as isize + 1 }, &o.rel_path))
+				};
 				if ii.vert as usize >= o.vertices.len() {
-					return Err(format!("Invalid obj file ({}): Invalid face (index {}) vertex index: {}", &o.rel_path, i, ii.vert));
+					return err("vertex", ii.vert);
 				} else if ii.uv.is_some() && ii.uv.unwrap() as usize >= o.uvs.len() {
-					return Err(format!("Invalid obj file ({}): Invalid face (index {}) uv index: {}", &o.rel_path, i, ii.uv.unwrap()));
+					return err("uv", ii.uv.unwrap());
 				} else if ii.norm.is_some() && ii.norm.unwrap() as usize >= o.normals.len() {
-					return Err(format!("Invalid obj file ({}): Invalid face (index {}) normal index: {}", &o.rel_path, i, ii.norm.unwrap()));
+					return err("normal", ii.norm.unwrap());
 				}
 				Ok(())
 			}
-			check_index_info(i, self, f.x)?;
-			check_index_info(i, self, f.y)?;
-			check_index_info(i, self, f.z)?;
+			check_index_info(self, f.x)?;
+			check_index_info(self, f.y)?;
+			check_index_info(self, f.z)?;
 		}
-		if self.material.is_none() {
-			warn!("Object file loaded without a material: {}", &self.rel_path);
+		if self.colors.len() != self.vertices.len() {
+			return Err(ParseError::new(ParseErrorKind::Invalid("every vertex must have a colour".into()), &self.rel_path));
 		}
 		Ok(())
 	}
 }
 
 #[derive(Debug)]
-pub struct ParseState {
-	command: String,
+pub struct ParseState<'a> {
+	command: &'a str,
+	/// The line currently being parsed
+	line: &'a str,
 	lno: usize,
 	path: PathBuf,
 	rel_path: PathBuf,
 }
-impl ParseState {
-	pub fn new(command: String, lno: usize, path: PathBuf, rel_path: PathBuf) -> ParseState {
+impl<'a> ParseState<'a> {
+	pub fn new(path: PathBuf, rel_path: PathBuf) -> ParseState<'a> {
 		ParseState {
-			command: command,
-			lno: lno,
+			command: "",
+			line: "",
+			lno: 0,
 			path: path,
 			rel_path: rel_path,
 		}
 	}
-	pub fn to_error(&self) -> String {
-		format!("Invalid command format `{}` at {}:{}", self.command, self.path.display(), self.lno)
+
+	/// Creates an error that spans `token`, which must be a slice of the current line.
+	pub fn error_at(&self, kind: ParseErrorKind, token: &str) -> ParseError {
+		let start = self.line.as_ptr() as usize;
+		let p = token.as_ptr() as usize;
+		let (column, len) = if p >= start && p + token.len() <= start + self.line.len() {
+			(p - start + 1, token.len())
+		} else {
+			(1, self.line.len())
+		};
+		ParseError {
+			kind: kind,
+			path: self.rel_path.clone(),
+			line: self.lno,
+			column: column,
+			len: len,
+			source: Some(self.line.to_string()),
+		}
 	}
-}
 
-fn parse_file(f: &mut ObjFile) -> GameResult<()> {
-	let mut s = String::new();
-	File::open(&f.path)
-		.map_err(|e| format!("Invalid obj file ({}): {}", e, &f.rel_path))?
-		.read_to_string(&mut s)
-		.map_err(|e| format!("Invalid obj file ({}): {}", e, &f.rel_path))?;
+	/// Creates an error that spans the current command
+	pub fn error(&self, kind: ParseErrorKind) -> ParseError {
+		self.error_at(kind, self.command)
+	}
 
-	parse_string(f, s)
+	/// Creates an error for when there are too few arguments to the current command
+	pub fn arity_error(&self) -> ParseError {
+		let mut e = self.error(ParseErrorKind::BadArity(self.command.to_string()));
+		// Point just past the last argument
+		e.column = self.line.rfind(|c: char| !c.is_whitespace()).map(|i| i + 2).unwrap_or(1);
+		e.len = 1;
+		e
+	}
 }
 
-fn parse_string(f: &mut ObjFile, s: String) -> GameResult<()> {
+fn parse_string<Res>(f: &mut ObjFile, s: &str, resolver: &mut Res, mode: ParseMode) -> ParseResult<()>
+		where Res: Resolver {
 	// Get an iterator that ignores comments and empty lines
 	let li = s.lines()
 		.map(|l| l.split("#").next().unwrap_or(""));
 
-	let mut state = ParseState::new(String::new(), 0, f.path.clone(), PathBuf::from(&f.rel_path));
+	let mut state = ParseState::new(f.path.clone(), PathBuf::from(&f.rel_path));
+	// Materials used, so that they can be checked once all material libraries have been loaded
+	let mut material_uses: Vec<(String, ParseError)> = vec![];
+	// The object, group & material that new faces are added to
+	let mut cur = FaceRange { object: None, group: None, material: None, start: 0, len: 0 };
+	// The smoothing group that new faces are added to
+	let mut smoothing = 0;
 
 	for (lno, line) in li.enumerate().map(|(lno, l)| (lno + 1, l)) {
 		if line == "" { continue; }
 		let mut args = line.split_whitespace().peekable();
 		let command = args.next().unwrap_or("");
-		state.command = command.to_string();
+		state.command = command;
+		state.line = line;
 		state.lno = lno;
 		trace!("Obj State: {:?}", state);
 		match command {
 			"mtllib" => {
 				let mtl_rel_path = args.next()
-					.ok_or_else(|| state.to_error())?;
+					.ok_or_else(|| state.arity_error())?;
 
 				let mtl_rel_exe_path = util::remove_parents(&Path::new(&f.rel_path).join("..").join(&mtl_rel_path));
 				trace!("mtl_rel_exe_path: {}", mtl_rel_exe_path.display());
@@ -251,21 +507,47 @@ fn parse_string(f: &mut ObjFile, s: String) -> GameResult<()> {
 				mtl_path.pop();
 				let mtl_path = mtl_path.join(mtl_rel_path);
 				// Load the mtl file
-				let mut mtl_s = String::new();
-				File::open(&mtl_path)
-					.and_then(|mut f| f.read_to_string(&mut mtl_s))
-					.map_err(|e| format!("Invalid mtl file ({}): {}", e, mtl_path.display()))?;
-				parse_mtl_string(f, &mtl_path, &mtl_rel_exe_path, &mtl_s)?;
+				let stamp = resolver.stamp(&mtl_rel_exe_path);
+				let mtl_s = resolver.read_mtl(&mtl_rel_exe_path)
+					.map_err(|e| state.error_at(ParseErrorKind::Io(e.to_string()), mtl_rel_path))?;
+				parse_mtl_string(f, &mtl_path, &mtl_rel_exe_path, &mtl_s, resolver, mode)?;
+				f.sources.push((mtl_rel_exe_path.clone(), stamp));
+				f.mtllibs.push(mtl_rel_exe_path);
 			},
 			"o" => {
-				if f.name.is_some() {
-					warn!("Object name redefined at location {}:{}", lno, f.rel_path);
+				let name: String = util::parse1_only(&state, &mut args)?;
+				if f.name.is_none() {
+					f.name = Some(name.clone());
 				}
-				f.name = Some(util::parse1_only(&state, &mut args)?);
+				cur.object = Some(name);
+				cur.group = None;
+			},
+			"g" => {
+				// A face can be in multiple groups, so just use all of the names as one
+				let names: Vec<&str> = args.collect();
+				cur.group = if names.is_empty() { None } else { Some(names.join(" ")) };
 			},
 			"v" => {
-				let v = util::parse_vec3_only(&state, &mut args)?;
-				f.vertices.push(v);
+				// `v x y z [w]`, or `v x y z r g b [a]` for vertices with a colour. The weight & alpha aren't used.
+				let v: Vec<Flt> = util::parseN(&state, 3, &mut args)?;
+				let rest: Vec<&str> = args.collect();
+				let color = match rest.len() {
+					0 => vec3(1.0, 1.0, 1.0),
+					1 => {
+						let _: Flt = util::parse_token(&state, rest[0])?;
+						vec3(1.0, 1.0, 1.0)
+					},
+					2 => return Err(state.arity_error()),
+					3 | 4 => {
+						if rest.len() == 4 {
+							let _: Flt = util::parse_token(&state, rest[3])?;
+						}
+						vec3(util::parse_token(&state, rest[0])?, util::parse_token(&state, rest[1])?, util::parse_token(&state, rest[2])?)
+					},
+					_ => return Err(state.error_at(ParseErrorKind::BadArity(command.to_string()), rest[4])),
+				};
+				f.vertices.push(vec3(v[0], v[1], v[2]));
+				f.colors.push(color);
 			},
 			"vt" => {
 				let v = util::parseN_only(&state, 2, &mut args)?;
@@ -277,73 +559,106 @@ fn parse_string(f: &mut ObjFile, s: String) -> GameResult<()> {
 				f.normals.push(v.normalize());
 			},
 			"f" => {
-				fn process_index(i: isize, l: usize) -> u32 {
+				/// Parses an index, checking that it refers to one of the `l` elements read so far
+				fn process_index(state: &ParseState, what: &'static str, s: &str, l: usize) -> ParseResult<u32> {
+					let i: isize = util::parse_token(state, s)?;
 					// Negative indices refer to the end of the array
 					// We need to subtract 1 if it is positive, because we count from 0 wheras .obj is from 1.
-					(if i >= 0 { i as usize - 1 } else { l - ((-i) as usize) }) as u32
+					if i == 0 || i.abs() as usize > l {
+						return Err(state.error_at(ParseErrorKind::IndexOutOfRange { what: what, index: i }, s));
+					}
+					Ok((if i > 0 { i as usize - 1 } else { l - ((-i) as usize) }) as u32)
 				}
-				fn process_index_info(state: &ParseState, f: &ObjFile, s: &str) -> GameResult<PreIndexInfo> {
+				fn process_index_info(state: &ParseState, f: &ObjFile, s: &str) -> ParseResult<PreIndexInfo> {
 					let mut iit = s.split("/");
-					let str_vert = iit.next().ok_or_else(|| state.to_error())?;
+					let str_vert = iit.next().unwrap_or(s);
 					let str_uv = iit.next();
 					let str_norm = iit.next();
 					// Ensure that "1/2/3/" fails
-					if iit.next().is_some() { return Err(state.to_error()); }
-					let idx_vert = str_vert.parse().map_err(|_| state.to_error())?;
-					let idx_vert = process_index(idx_vert, f.vertices.len());
+					if iit.next().is_some() { return Err(state.error_at(ParseErrorKind::BadArgument(s.to_string()), s)); }
+					let idx_vert = process_index(state, "vertex", str_vert, f.vertices.len())?;
 					let idx_uv = match str_uv {
 						None | Some("") => None,
-						Some(s) => {
-							let idx = s.parse().map_err(|_| state.to_error())?;
-							Some(process_index(idx, f.uvs.len()))
-						}
+						Some(s) => Some(process_index(state, "uv", s, f.uvs.len())?),
 					};
 					let idx_norm = match str_norm {
 						None | Some("") => None,
-						Some(s) => {
-							let idx = s.parse().map_err(|_| state.to_error())?;
-							Some(process_index(idx, f.normals.len()))
-						}
+						Some(s) => Some(process_index(state, "normal", s, f.normals.len())?),
 					};
 					//trace!("Face index: \"{}\" => v:{} uv:{:?} norm:{:?}", s, idx_vert, idx_uv, idx_norm);
 					Ok(PreIndexInfo::new(idx_vert, idx_uv, idx_norm))
 				}
-				let fs: Vec<String> = util::parseN_only(&state, 3, &mut args)?;
-				let v0 = process_index_info(&state, f, &fs[0])?;
-				let v1 = process_index_info(&state, f, &fs[1])?;
-				let v2 = process_index_info(&state, f, &fs[2])?;
-				f.pre_faces.push(vec3(v0, v1, v2));
+				let fs: Vec<&str> = args.collect();
+				if fs.len() < 3 {
+					return Err(state.arity_error());
+				}
+				let mut vs = Vec::with_capacity(fs.len());
+				for s in fs.iter() {
+					vs.push(process_index_info(&state, f, s)?);
+				}
+				// Split polygons into triangles
+				let ps: Vec<Vec3> = vs.iter().map(|v| f.vertices[v.vert as usize]).collect();
+				let tris = triangulate::triangulate(&ps);
+				for t in tris.iter() {
+					f.push_pre_face(vec3(vs[t[0]], vs[t[1]], vs[t[2]]), &cur, smoothing);
+				}
 			},
 			"usemtl" => {
 				let m: String = util::parse1_only(&state, &mut args)?;
-				f.material = Some(m);
+				material_uses.push((m.clone(), state.error_at(ParseErrorKind::MissingMaterial(m.clone()), line.trim())));
+				cur.material = Some(m);
 			},
 			"s" => {
-				// TODO: Figure out what this command does. For now, just ignore it.
+				// `s off` and `s 0` both turn smoothing off
+				let a = args.next().ok_or_else(|| state.arity_error())?;
+				util::ensure_end(&state, &mut args)?;
+				smoothing = if a == "off" { 0 } else { util::parse_token(&state, a)? };
 			},
 			_ => {
-				return Err(format!("Unrecognized command `{}` at {}:{}", command, f.rel_path, lno));
+				let e = state.error(ParseErrorKind::UnknownCommand(command.to_string()));
+				match mode {
+					ParseMode::Strict => return Err(e),
+					ParseMode::Lenient => f.skip(e),
+				}
 			}
 		}
 	}
+	// Check that all of the materials used exist
+	for (m, e) in material_uses.into_iter() {
+		if !f.materials.contains_key(&m) {
+			return Err(e);
+		}
+	}
 	Ok(())
 }
 
+/// Loads an .mtl file on its own, from a path relative to the executable
+pub fn load_mtl(rel_path: &Path, mode: ParseMode) -> ParseResult<HashMap<String, Material>> {
+	let path = vfs::canonicalize_exe(rel_path);
+	let s = FsResolver.read_mtl(rel_path)
+		.map_err(|e| ParseError::new(ParseErrorKind::Io(e.to_string()), rel_path))?;
+	let mut f = ObjFile::empty(rel_path.to_string_lossy().into_owned(), path.clone());
+	parse_mtl_string(&mut f, &path, rel_path, &s, &mut FsResolver, mode)?;
+	Ok(f.materials)
+}
+
 /// rel_path = the path of the mtl file relative to the exe.
-fn parse_mtl_string(f: &mut ObjFile, path: &Path, rel_path: &Path, s: &str) -> GameResult<()> {
+fn parse_mtl_string<Res>(f: &mut ObjFile, path: &Path, rel_path: &Path, s: &str, resolver: &mut Res, mode: ParseMode) -> ParseResult<()>
+		where Res: Resolver {
 	// Get lines that filter out comments & empty lines
 	let li = s.lines()
 		.map(|l| l.split("#").next().unwrap_or(""));
 
 	let mut current_mat_name = None;
 	let mut current_mat = Material::default();
-	let mut state = ParseState::new(String::new(), 0, path.to_path_buf(), rel_path.to_path_buf());
+	let mut state = ParseState::new(path.to_path_buf(), rel_path.to_path_buf());
 
 	for (lno, line) in li.enumerate().map(|(lno, l)| (lno + 1, l)) {
 		if line == "" { continue; }
 		let mut args = line.split_whitespace().peekable();
 		let command = args.next().unwrap_or("");
-		state.command = command.to_string();
+		state.command = command;
+		state.line = line;
 		state.lno = lno;
 		trace!("Mtl State: {:?}", state);
 		match command {
@@ -362,13 +677,26 @@ fn parse_mtl_string(f: &mut ObjFile, path: &Path, rel_path: &Path, s: &str) -> G
 			"Kd" => { current_mat.Kd = util::parse_vec3_only(&state, &mut args)?; },
 			"Ks" => { current_mat.Ks = util::parse_vec3_only(&state, &mut args)?; },
 			"Ke" => { current_mat.Ke = util::parse_vec3_only(&state, &mut args)?; },
-			"Ni" => { /* TODO: Figure out what this command is */ },
+			"Ni" => { current_mat.Ni = util::parse1_only(&state, &mut args)?; },
 			"d"  => { current_mat.d = util::parse1_only(&state, &mut args)?; },
-			"illum" => { /* TODO: Implement this command */ },
-			"map_Ka" => { current_mat.map_Ka = Some(parse_texture_args(&state, &mut args)?); },
-			"map_Kd" => { current_mat.map_Kd = Some(parse_texture_args(&state, &mut args)?); },
+			"illum" => { current_mat.illum = util::parse1_only(&state, &mut args)?; },
+			"map_Ka" => { current_mat.map_Ka = Some(parse_texture(f, &state, &mut args, resolver, mode)?); },
+			"map_Kd" => { current_mat.map_Kd = Some(parse_texture(f, &state, &mut args, resolver, mode)?); },
+			"map_Ks" => { current_mat.map_Ks = Some(parse_texture(f, &state, &mut args, resolver, mode)?); },
+			"map_Ns" => { current_mat.map_Ns = Some(parse_texture(f, &state, &mut args, resolver, mode)?); },
+			"map_d"  => { current_mat.map_d  = Some(parse_texture(f, &state, &mut args, resolver, mode)?); },
+			"map_Ke" => { current_mat.map_Ke = Some(parse_texture(f, &state, &mut args, resolver, mode)?); },
+			"bump" | "map_Bump" | "map_bump" => { current_mat.bump = Some(parse_texture(f, &state, &mut args, resolver, mode)?); },
+			"disp"  => { current_mat.disp  = Some(parse_texture(f, &state, &mut args, resolver, mode)?); },
+			"decal" => { current_mat.decal = Some(parse_texture(f, &state, &mut args, resolver, mode)?); },
+			"refl"  => { current_mat.refl.push(parse_texture(f, &state, &mut args, resolver, mode)?); },
+			"uv_projection" => { current_mat.uv_projection = projection::parse_uv_projection(&state, &mut args)?; },
 			_ => {
-				return Err(format!("Unrecognized command `{}` at {}:{}", state.command, state.path.display(), state.lno))
+				let e = state.error(ParseErrorKind::UnknownCommand(command.to_string()));
+				match mode {
+					ParseMode::Strict => return Err(e),
+					ParseMode::Lenient => f.skip(e),
+				}
 			}
 		}
 	}
@@ -378,30 +706,131 @@ fn parse_mtl_string(f: &mut ObjFile, path: &Path, rel_path: &Path, s: &str) -> G
 	Ok(())
 }
 
-fn parse_texture_args<'a, I>(state: &ParseState, args: &mut Peekable<I>) -> GameResult<(TextureID, TextureOptions)>
+/// Parses a texture map statement, using `resolver` to get the ID of the texture
+fn parse_texture<'a, I, Res>(f: &mut ObjFile, state: &ParseState, args: &mut Peekable<I>, resolver: &mut Res, mode: ParseMode)
+		-> ParseResult<(TextureID, TextureOptions)>
+		where I: Iterator<Item=&'a str>, Res: Resolver {
+	let (path, opt) = parse_texture_args(f, state, args, mode)?;
+	Ok((resolver.texture_id(Path::new(&path)), opt))
+}
+
+/// Parses the options & path of a texture map statement. In lenient mode, unknown options are skipped along with their
+/// arguments, and added to `f.warnings`.
+fn parse_texture_args<'a, I>(f: &mut ObjFile, state: &ParseState, args: &mut Peekable<I>, mode: ParseMode)
+		-> ParseResult<(TextureID, TextureOptions)>
 		where I: Iterator<Item=&'a str> {
-	let a: String = util::parse1(state, args)?;
+	let a = args.next().ok_or_else(|| state.arity_error())?;
 	if !a.starts_with('-') { // `a` is a texture ID.
 		// Ensure that there are no more args
-		if args.peek().is_some() { return Err(state.to_error()); }
+		util::ensure_end(state, args)?;
 
 		// Parse `a` as a texture ID
-		let id = parse_texture_path(state, &a);
+		let id = parse_texture_path(state, a);
 		Ok((id, TextureOptions::default()))
 	} else { // `a` is a texture option
-		match a.as_str() {
+		let ret = match a {
 			"-s" => { // "-s u [v] [w]" -- uv scale option
 				let u = util::parse1(state, args)?;
 				let v = util::parse1_opt(args).unwrap_or(1.0);
 				let _: Flt = util::parse1_opt(args).unwrap_or(1.0); // Ignore the 3D option
-				let (id, mut opt) = parse_texture_args(state, args)?; // Recurse on other arguments
+				let (id, mut opt) = parse_texture_args(f, state, args, mode)?; // Recurse on other arguments
 				opt.uv_scale = vec2(u, v);
-				Ok((id, opt))
+				(id, opt)
+			},
+			"-o" => { // "-o u [v] [w]" -- uv offset option
+				let u = util::parse1(state, args)?;
+				let v = util::parse1_opt(args).unwrap_or(0.0);
+				let _: Flt = util::parse1_opt(args).unwrap_or(0.0); // Ignore the 3D option
+				let (id, mut opt) = parse_texture_args(f, state, args, mode)?;
+				opt.uv_offset = vec2(u, v);
+				(id, opt)
+			},
+			"-t" => { // "-t u [v] [w]" -- turbulence option
+				let u = util::parse1(state, args)?;
+				let v = util::parse1_opt(args).unwrap_or(0.0);
+				let w = util::parse1_opt(args).unwrap_or(0.0);
+				let (id, mut opt) = parse_texture_args(f, state, args, mode)?;
+				opt.turbulence = vec3(u, v, w);
+				(id, opt)
+			},
+			"-clamp" | "-blendu" | "-blendv" | "-cc" => { // "-clamp on|off" etc. -- boolean options
+				let b = util::parse_on_off(state, args)?;
+				let (id, mut opt) = parse_texture_args(f, state, args, mode)?;
+				match a {
+					"-clamp"  => opt.clamp = b,
+					"-blendu" => opt.blend_u = b,
+					"-blendv" => opt.blend_v = b,
+					_         => opt.color_correction = b,
+				}
+				(id, opt)
+			},
+			"-bm" => { // "-bm mult" -- bump multiplier
+				let bm = util::parse1(state, args)?;
+				let (id, mut opt) = parse_texture_args(f, state, args, mode)?;
+				opt.bump_mult = bm;
+				(id, opt)
+			},
+			"-boost" => { // "-boost value" -- mip-map sharpness
+				let boost = util::parse1(state, args)?;
+				let (id, mut opt) = parse_texture_args(f, state, args, mode)?;
+				opt.boost = boost;
+				(id, opt)
+			},
+			"-mm" => { // "-mm base [gain]" -- texture value range
+				let base = util::parse1(state, args)?;
+				let gain = util::parse1_opt(args).unwrap_or(1.0);
+				let (id, mut opt) = parse_texture_args(f, state, args, mode)?;
+				opt.mm = vec2(base, gain);
+				(id, opt)
+			},
+			"-imfchan" => { // "-imfchan r|g|b|m|l|z" -- channel to use
+				use render::TextureChannel::*;
+				let c = args.next().ok_or_else(|| state.arity_error())?;
+				let c = match c {
+					"r" => R, "g" => G, "b" => B, "m" => M, "l" => L, "z" => Z,
+					_ => return Err(state.error_at(ParseErrorKind::BadArgument(c.to_string()), c)),
+				};
+				let (id, mut opt) = parse_texture_args(f, state, args, mode)?;
+				opt.imfchan = Some(c);
+				(id, opt)
+			},
+			"-texres" => { // "-texres resolution"
+				let res = util::parse1(state, args)?;
+				let (id, mut opt) = parse_texture_args(f, state, args, mode)?;
+				opt.texres = Some(res);
+				(id, opt)
+			},
+			"-type" => { // "-type sphere|cube_top|..." -- reflection map type
+				use render::ReflectionType::*;
+				let t = args.next().ok_or_else(|| state.arity_error())?;
+				let t = match t {
+					"sphere" => Sphere,
+					"cube_top" => CubeTop,
+					"cube_bottom" => CubeBottom,
+					"cube_front" => CubeFront,
+					"cube_back" => CubeBack,
+					"cube_left" => CubeLeft,
+					"cube_right" => CubeRight,
+					_ => return Err(state.error_at(ParseErrorKind::BadArgument(t.to_string()), t)),
+				};
+				let (id, mut opt) = parse_texture_args(f, state, args, mode)?;
+				opt.refl_type = Some(t);
+				(id, opt)
 			},
 			_ => {
-				Err(state.to_error() + &format!(": Unknown texture option `{}`", a))
+				let e = state.error_at(ParseErrorKind::BadArgument(a.to_string()), a);
+				if mode == ParseMode::Strict {
+					return Err(e);
+				}
+				f.skip(e);
+				// The arguments of an unknown option are everything up to the next option, apart from the path at the end
+				let rest: Vec<&'a str> = args.collect();
+				let is_option = |a: &&str| a.starts_with('-') && a.parse::<Flt>().is_err();
+				let n = rest.iter().position(is_option).unwrap_or(rest.len().saturating_sub(1));
+				return parse_texture_args(f, state, &mut rest[n..].iter().cloned().peekable(), mode);
 			}
-		}
+		};
+		Ok(ret)
 	}
 }
 
@@ -421,25 +850,201 @@ fn parse_texture_path(state: &ParseState, id: &str) -> TextureID {
 	}
 }
 
-pub fn load_shader_program(ctx: &Rc<Context>, rel_base: &str) -> GameResult<Program> {
-	// TODO: Handle more shader types
-	let base = vfs::canonicalize_exe(rel_base);
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	use std::io;
+	use std::path::Path;
+
+	use super::{ObjFile, ParseErrorKind, ParseMode, ParseResult};
+
+	const QUADS: &'static str = "
+mtllib quads.mtl
+v 0 0 0
+v 1 0 0
+v 1 0 -1
+v 0 0 -1
+v 2 0 0
+v 2 0 -1
+usemtl red
+f 1 2 3 4
+usemtl blue
+f 2 5 6 3
+";
+
+	const QUADS_MTL: &'static str = "
+newmtl red
+Kd 1 0 0
+illum 1
+newmtl blue
+Kd 0 0 1
+map_Kd -o 0.5 0.25 -clamp on blue.png
+newmtl boxed
+uv_projection box
+";
+
+	fn parse(s: &str) -> ParseResult<ObjFile> {
+		parse_mode(s, ParseMode::Strict)
+	}
+
+	fn parse_mode(s: &str, mode: ParseMode) -> ParseResult<ObjFile> {
+		ObjFile::from_str("res/mesh/test.obj".into(), s, &mut |p: &Path| {
+			if p == Path::new("res/mesh/quads.mtl") {
+				Ok(QUADS_MTL.to_string())
+			} else if p == Path::new("res/mesh/lenient.mtl") {
+				Ok("newmtl transmissive\nTf 1 1 1\n".to_string())
+			} else if p == Path::new("res/mesh/bad_projection.mtl") {
+				Ok("newmtl bad\nuv_projection planar w\n".to_string())
+			} else {
+				Err(io::Error::new(io::ErrorKind::NotFound, "not found"))
+			}
+		}, mode)
+	}
+
+	#[test]
+	fn test_quads() {
+		let f = parse(QUADS).unwrap();
+		assert_eq!(f.faces.len(), 4);
+		assert_eq!(f.ranges.len(), 2);
+		assert_eq!((f.ranges[0].start, f.ranges[0].len), (0, 2));
+		assert_eq!((f.ranges[1].start, f.ranges[1].len), (2, 2));
+		assert_eq!(f.ranges[1].material, Some("blue".to_string()));
+
+		let blue = &f.materials["blue"];
+		assert_eq!(blue.Kd, vec3(0.0, 0.0, 1.0));
+		let (ref id, opt) = *blue.map_Kd.as_ref().unwrap();
+		assert_eq!(Path::new(id), Path::new("res/mesh/blue.png"));
+		assert_eq!(opt.uv_offset, vec2(0.5, 0.25));
+		assert!(opt.clamp);
+	}
+
+	#[test]
+	fn test_lenient() {
+		let src = "mtllib lenient.mtl\nv 0 0 0\nv 1 0 0\nv 1 0 -1\nl 1 2\nvp 0.5\nl 2 3\nusemtl transmissive\nf 1 2 3\n";
+		let e = parse(src).unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::UnknownCommand("Tf".into()));
+
+		let f = parse_mode(src, ParseMode::Lenient).unwrap();
+		assert_eq!(f.faces.len(), 1);
+		// Every statement that was skipped is warned about
+		let warnings: Vec<(ParseErrorKind, usize)> = f.warnings.iter().map(|w| (w.kind.clone(), w.line)).collect();
+		assert_eq!(warnings, vec![
+			(ParseErrorKind::UnknownCommand("Tf".into()), 2),
+			(ParseErrorKind::UnknownCommand("l".into()), 5),
+			(ParseErrorKind::UnknownCommand("vp".into()), 6),
+			(ParseErrorKind::UnknownCommand("l".into()), 7),
+		]);
+		assert_eq!(f.warnings[0].path, Path::new("res/mesh/lenient.mtl"));
+	}
+
+	#[test]
+	fn test_lenient_texture_options() {
+		let mtl = "newmtl a\nmap_Kd -foo 1 -2 -clamp on -bar a.png\n";
+		let parse = |mode| ObjFile::from_str("res/mesh/a.obj".into(), "mtllib a.mtl\nv 0 0 0\nv 1 0 0\nv 1 0 -1\nusemtl a\nf 1 2 3\n",
+			&mut |_: &Path| Ok(mtl.to_string()), mode);
+		let e = parse(ParseMode::Strict).unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::BadArgument("-foo".into()));
+
+		// Unknown options are skipped with their arguments, but the rest of the statement is kept
+		let f = parse(ParseMode::Lenient).unwrap();
+		let (ref id, opt) = *f.materials["a"].map_Kd.as_ref().unwrap();
+		assert_eq!(Path::new(id), Path::new("res/mesh/a.png"));
+		assert!(opt.clamp);
+		let warnings: Vec<ParseErrorKind> = f.warnings.iter().map(|w| w.kind.clone()).collect();
+		assert_eq!(warnings, vec![ParseErrorKind::BadArgument("-foo".into()), ParseErrorKind::BadArgument("-bar".into())]);
+	}
+
+	#[test]
+	fn test_generated_uvs() {
+		// A floor & a wall, without any uvs
+		let src = "mtllib quads.mtl\nv 0 0 0\nv 2 0 0\nv 2 0 -2\nv 0 0 -2\nv 2 2 0\nv 2 2 -2\n";
+		let wall_uvs = |f: &ObjFile| -> Vec<Vec2> {
+			let face = f.faces[2];
+			[face.x, face.y, face.z].iter().map(|ii| f.uvs[ii.uv as usize]).collect()
+		};
+
+		// Planar projection is the default, which squashes the wall into a line
+		let f = parse(&(src.to_string() + "usemtl red\nf 1 2 3 4\nf 2 5 6 3\n")).unwrap();
+		for uv in f.uvs.iter() {
+			assert!(uv.x >= 0.0 && uv.x <= 1.0 && uv.y >= 0.0 && uv.y <= 1.0);
+		}
+		let uvs = wall_uvs(&f);
+		assert!(uvs.iter().all(|uv| uv.x == uvs[0].x));
+
+		let f = parse(&(src.to_string() + "usemtl boxed\nf 1 2 3 4\nf 2 5 6 3\n")).unwrap();
+		let uvs = wall_uvs(&f);
+		assert!(uvs.iter().any(|uv| uv.x != uvs[0].x));
 
-	// Load source of shaders
-	let mut vs_src = String::new();
-	File::open(base.with_extension("vs"))
-		.map_err(|e| format!("Could not open file {}.vs: {}", base.display(), e))?
-		.read_to_string(&mut vs_src)
-		.map_err(|e| format!("Could not read file {}.vs: {}", base.display(), e))?;
+		let e = parse("mtllib bad_projection.mtl\n").unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::BadArgument("w".into()));
+	}
 
-	let mut fs_src = String::new();
-	File::open(base.with_extension("fs"))
-		.map_err(|e| format!("Could not open file {}.fs: {}", base.display(), e))?
-		.read_to_string(&mut fs_src)
-		.map_err(|e| format!("Could not read file {}.fs: {}", base.display(), e))?;
+	#[test]
+	fn test_smoothing() {
+		let src = "v 0 0 0\nv 1 0 0\nv 1 1 -1\nv 0 1 -1\nv 0 1 1\nv 1 1 1\n";
+		// Normals used by the first vertex, which is shared by both quads
+		fn vert0_normals(f: &ObjFile) -> Vec<Vec3> {
+			f.faces.iter()
+				.flat_map(|face| vec![face.x, face.y, face.z])
+				.filter(|ii| ii.vert == 0)
+				.map(|ii| f.normals[ii.norm as usize])
+				.collect()
+		}
 
-	let prog = Program::from_source(ctx, &vs_src, &fs_src, None)
-		.map_err(|e| format!("Could not parse shader {}\n{}", base.display(), e))?;
+		let flat = parse(&(src.to_string() + "s off\nf 1 2 3 4\nf 2 1 5 6\n")).unwrap();
+		let ns = vert0_normals(&flat);
+		assert!(ns.iter().any(|&n| n != ns[0]));
+
+		let smooth = parse(&(src.to_string() + "s 1\nf 1 2 3 4\nf 2 1 5 6\n")).unwrap();
+		let ns = vert0_normals(&smooth);
+		assert!(ns.len() >= 2);
+		for &n in ns.iter() {
+			assert!((n - vec3(0.0, 1.0, 0.0)).magnitude() < 1e-5);
+		}
+
+		// The two sides of a double sided triangle cancel out, so each keeps its own normal
+		let double = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\ns 1\nf 1 2 3\nf 1 3 2\n").unwrap();
+		for (face, &expected) in double.faces.iter().zip([vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0)].iter()) {
+			for ii in [face.x, face.y, face.z].iter() {
+				assert_eq!(double.normals[ii.norm as usize], expected);
+			}
+		}
+	}
 
-	Ok(prog)
+	#[test]
+	fn test_vertex_colors() {
+		let f = parse("v 0 0 0\nv 1 0 0 0.5\nv 1 1 0 1 0.5 0\nf 1 2 3\n").unwrap();
+		assert_eq!(f.vertices[2], vec3(1.0, 1.0, 0.0));
+		assert_eq!(f.colors, vec![vec3(1.0, 1.0, 1.0), vec3(1.0, 1.0, 1.0), vec3(1.0, 0.5, 0.0)]);
+
+		// Alpha is ignored
+		let f = parse("v 0 0 0 0 0.5 1 0.25\nv 1 0 0\nv 1 1 0\nf 1 2 3\n").unwrap();
+		assert_eq!(f.colors[0], vec3(0.0, 0.5, 1.0));
+	}
+
+	#[test]
+	fn test_errors() {
+		let e = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 4\n").unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::IndexOutOfRange { what: "vertex", index: 4 });
+		assert_eq!((e.line, e.column, e.len), (4, 7, 1));
+
+		let e = parse("v 0 0 0\n  vx 1 0\n").unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::UnknownCommand("vx".into()));
+		assert_eq!((e.line, e.column, e.len), (2, 3, 2));
+
+		let e = parse("v 0 0\n").unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::BadArity("v".into()));
+
+		let e = parse("v 0 0 0 1 1\n").unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::BadArity("v".into()));
+		let e = parse("v 0 0 0 1 1 1 1 1\n").unwrap_err();
+		assert_eq!((e.kind, e.column), (ParseErrorKind::BadArity("v".into()), 17));
+		let e = parse("v 0 0 0 1 1 1 x\n").unwrap_err();
+		assert_eq!(e.column, 15);
+
+		let e = parse("mtllib quads.mtl\nusemtl green\n").unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::MissingMaterial("green".into()));
+		assert_eq!(e.line, 2);
+	}
 }
@@ -0,0 +1,446 @@
+use prelude::*;
+
+use std::fs::File;
+use std::io::Read;
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::str::{self, Lines, SplitWhitespace};
+
+use vfs;
+
+use super::{ObjFile, FaceRange, PreIndexInfo, ParseState};
+use super::error::{ParseError, ParseErrorKind, ParseResult};
+use super::triangulate::triangulate;
+use super::util;
+
+impl ObjFile {
+	/// Loads a .ply file (binary or ASCII) from a path relative to the executable.
+	///
+	/// The file is converted into an `ObjFile`, so missing normals & uvs are generated in the same way.
+	/// Vertex colours are read into `colors`.
+	pub fn load_ply(rel_path: String) -> ParseResult<ObjFile> {
+		let path = vfs::canonicalize_exe(&rel_path);
+		let stamp = vfs::file_stamp(&rel_path);
+		let mut data = vec![];
+		File::open(&path)
+			.and_then(|mut file| file.read_to_end(&mut data))
+			.map_err(|e| ParseError::new(ParseErrorKind::Io(e.to_string()), &rel_path))?;
+
+		let mut f = ObjFile::empty(rel_path, path);
+		f.sources[0].1 = stamp;
+		parse_ply(&mut f, &data)?;
+		f.finish()?;
+		Ok(f)
+	}
+
+	/// Parses a .ply file (binary or ASCII) from memory. `rel_path` is used for error messages.
+	pub fn from_ply(rel_path: String, data: &[u8]) -> ParseResult<ObjFile> {
+		let path = PathBuf::from(&rel_path);
+		let mut f = ObjFile::empty(rel_path, path);
+		parse_ply(&mut f, data)?;
+		f.finish()?;
+		Ok(f)
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum PlyType {
+	I8,
+	U8,
+	I16,
+	U16,
+	I32,
+	U32,
+	F32,
+	F64,
+}
+impl PlyType {
+	fn parse(s: &str) -> Option<PlyType> {
+		use self::PlyType::*;
+		match s {
+			"char" | "int8" => Some(I8),
+			"uchar" | "uint8" => Some(U8),
+			"short" | "int16" => Some(I16),
+			"ushort" | "uint16" => Some(U16),
+			"int" | "int32" => Some(I32),
+			"uint" | "uint32" => Some(U32),
+			"float" | "float32" => Some(F32),
+			"double" | "float64" => Some(F64),
+			_ => None,
+		}
+	}
+
+	fn size(self) -> usize {
+		use self::PlyType::*;
+		match self {
+			I8 | U8 => 1,
+			I16 | U16 => 2,
+			I32 | U32 | F32 => 4,
+			F64 => 8,
+		}
+	}
+
+	/// The value that integer colours are divided by to get a value between 0 and 1
+	fn color_max(self) -> f64 {
+		use self::PlyType::*;
+		match self {
+			I8 => 127.0,
+			U8 => 255.0,
+			I16 => 32767.0,
+			U16 => 65535.0,
+			I32 => 2147483647.0,
+			U32 => 4294967295.0,
+			F32 | F64 => 1.0,
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+enum PlyProperty {
+	Scalar(String, PlyType),
+	/// Name, type of the length & type of the items
+	List(String, PlyType, PlyType),
+}
+
+#[derive(Clone, Debug)]
+struct PlyElement {
+	name: String,
+	count: usize,
+	properties: Vec<PlyProperty>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum PlyFormat {
+	Ascii,
+	BinaryLittleEndian,
+	BinaryBigEndian,
+}
+
+/// Reads the values in the body of a file
+enum PlyReader<'a> {
+	Ascii {
+		state: ParseState<'a>,
+		lines: Lines<'a>,
+		tokens: Peekable<SplitWhitespace<'a>>,
+	},
+	Binary {
+		rel_path: PathBuf,
+		data: &'a [u8],
+		pos: usize,
+		big_endian: bool,
+	},
+}
+impl<'a> PlyReader<'a> {
+	fn read(&mut self, ty: PlyType) -> ParseResult<f64> {
+		match self {
+			&mut PlyReader::Ascii { ref mut state, ref mut lines, ref mut tokens } => {
+				// Move on to the next non-empty line if this one has been read
+				while tokens.peek().is_none() {
+					let line = match lines.next() {
+						Some(l) => l.trim(),
+						None => {
+							let mut e = ParseError::new(ParseErrorKind::Invalid("unexpected end of file".into()), &state.rel_path);
+							e.line = state.lno;
+							return Err(e);
+						}
+					};
+					state.lno += 1;
+					state.line = line;
+					state.command = line;
+					*tokens = line.split_whitespace().peekable();
+				}
+				util::parse_token(state, tokens.next().unwrap())
+			},
+			&mut PlyReader::Binary { ref rel_path, data, ref mut pos, big_endian } => {
+				let b = match data.get(*pos..*pos + ty.size()) {
+					Some(b) => b,
+					None => return Err(ParseError::new(ParseErrorKind::Invalid("unexpected end of file".into()), rel_path)),
+				};
+				*pos += ty.size();
+				let mut u = 0u64;
+				for i in 0..b.len() {
+					let byte = if big_endian { b[i] } else { b[b.len() - 1 - i] };
+					u = (u << 8) | byte as u64;
+				}
+				use self::PlyType::*;
+				Ok(match ty {
+					I8 => u as u8 as i8 as f64,
+					U8 => u as u8 as f64,
+					I16 => u as u16 as i16 as f64,
+					U16 => u as u16 as f64,
+					I32 => u as u32 as i32 as f64,
+					U32 => u as u32 as f64,
+					F32 => f32::from_bits(u as u32) as f64,
+					F64 => f64::from_bits(u),
+				})
+			},
+		}
+	}
+
+	fn error(&self, kind: ParseErrorKind) -> ParseError {
+		match self {
+			&PlyReader::Ascii { ref state, .. } => state.error_at(kind, state.line),
+			&PlyReader::Binary { ref rel_path, .. } => ParseError::new(kind, rel_path),
+		}
+	}
+}
+
+/// Parses the header. Returns the format, the elements, and the offset of the body.
+fn parse_header<'a>(state: &mut ParseState<'a>, s: &'a str) -> ParseResult<(PlyFormat, Vec<PlyElement>, usize)> {
+	let mut format = None;
+	let mut elements: Vec<PlyElement> = vec![];
+	let mut offset = 0;
+	for line in s.split('\n') {
+		offset += line.len() + 1;
+		state.lno += 1;
+		let line = line.trim();
+		let mut args = line.split_whitespace().peekable();
+		let command = args.next().unwrap_or("");
+		state.command = command;
+		state.line = line;
+		if state.lno == 1 {
+			if line != "ply" {
+				return Err(state.error(ParseErrorKind::Invalid("not a PLY file".into())));
+			}
+			continue;
+		}
+		match command {
+			"" | "comment" | "obj_info" => {},
+			"format" => {
+				let f = match args.next() {
+					Some("ascii") => PlyFormat::Ascii,
+					Some("binary_little_endian") => PlyFormat::BinaryLittleEndian,
+					Some("binary_big_endian") => PlyFormat::BinaryBigEndian,
+					Some(a) => return Err(state.error_at(ParseErrorKind::BadArgument(a.to_string()), a)),
+					None => return Err(state.arity_error()),
+				};
+				let version: String = util::parse1_only(state, &mut args)?;
+				if version != "1.0" {
+					return Err(state.error_at(ParseErrorKind::BadArgument(version.clone()), line.split_whitespace().last().unwrap()));
+				}
+				format = Some(f);
+			},
+			"element" => {
+				let name = args.next().ok_or_else(|| state.arity_error())?.to_string();
+				let count = util::parse1_only(state, &mut args)?;
+				elements.push(PlyElement { name: name, count: count, properties: vec![] });
+			},
+			"property" => {
+				let ty = |a: &'a str| PlyType::parse(a).ok_or_else(|| state.error_at(ParseErrorKind::BadArgument(a.to_string()), a));
+				let a = args.next().ok_or_else(|| state.arity_error())?;
+				let prop = if a == "list" {
+					let count_ty = ty(args.next().ok_or_else(|| state.arity_error())?)?;
+					let item_ty = ty(args.next().ok_or_else(|| state.arity_error())?)?;
+					PlyProperty::List(util::parse1_only(state, &mut args)?, count_ty, item_ty)
+				} else {
+					PlyProperty::Scalar(util::parse1_only(state, &mut args)?, ty(a)?)
+				};
+				match elements.last_mut() {
+					Some(e) => e.properties.push(prop),
+					None => return Err(state.error(ParseErrorKind::Invalid("property outside of an element".into()))),
+				}
+			},
+			"end_header" => {
+				return match format {
+					Some(f) => Ok((f, elements, offset.min(s.len()))),
+					None => Err(state.error(ParseErrorKind::Invalid("header has no format".into()))),
+				};
+			},
+			_ => return Err(state.error(ParseErrorKind::UnknownCommand(command.to_string()))),
+		}
+	}
+	let mut e = ParseError::new(ParseErrorKind::Invalid("header has no `end_header`".into()), &state.rel_path);
+	e.line = state.lno;
+	Err(e)
+}
+
+fn parse_ply(f: &mut ObjFile, data: &[u8]) -> ParseResult<()> {
+	// The header is always ASCII, but the body may not be UTF-8
+	let header_len = data.windows(10).position(|w| w == b"end_header")
+		.map(|i| i + 10)
+		.unwrap_or(data.len());
+	let header = str::from_utf8(&data[..header_len])
+		.map_err(|_| ParseError::new(ParseErrorKind::Invalid("header is not ASCII".into()), &f.rel_path))?;
+	let rel_path = PathBuf::from(&f.rel_path);
+	let mut state = ParseState::new(f.path.clone(), rel_path.clone());
+	let (format, elements, mut body_start) = parse_header(&mut state, header)?;
+	// Skip the line ending after `end_header`
+	if data.get(body_start) == Some(&b'\r') { body_start += 1; }
+	if data.get(body_start) == Some(&b'\n') { body_start += 1; }
+
+	let mut r = match format {
+		PlyFormat::Ascii => {
+			let s = str::from_utf8(&data[body_start..])
+				.map_err(|_| ParseError::new(ParseErrorKind::Invalid("body is not valid UTF-8".into()), &f.rel_path))?;
+			// Line numbers continue from the header
+			let lno = header.lines().count();
+			let mut state = ParseState::new(f.path.clone(), rel_path.clone());
+			state.lno = lno;
+			PlyReader::Ascii { state: state, lines: s.lines(), tokens: "".split_whitespace().peekable() }
+		},
+		_ => PlyReader::Binary {
+			rel_path: rel_path,
+			data: data,
+			pos: body_start,
+			big_endian: format == PlyFormat::BinaryBigEndian,
+		},
+	};
+
+	let mut normals = vec![];
+	let mut uvs = vec![];
+	let mut polygons: Vec<Vec<Idx>> = vec![];
+	for e in elements.iter() {
+		for _ in 0..e.count {
+			let mut pos = vec3(0.0, 0.0, 0.0);
+			let mut normal = vec3(0.0, 0.0, 0.0);
+			let mut color = vec3(1.0, 1.0, 1.0);
+			let mut uv = vec2(0.0, 0.0);
+			for p in e.properties.iter() {
+				match p {
+					&PlyProperty::Scalar(ref name, ty) => {
+						let x = r.read(ty)?;
+						if e.name != "vertex" {
+							continue;
+						}
+						match &name[..] {
+							"x" => pos.x = x as Flt,
+							"y" => pos.y = x as Flt,
+							"z" => pos.z = x as Flt,
+							"nx" => normal.x = x as Flt,
+							"ny" => normal.y = x as Flt,
+							"nz" => normal.z = x as Flt,
+							"red" | "r" | "diffuse_red" => color.x = (x / ty.color_max()) as Flt,
+							"green" | "g" | "diffuse_green" => color.y = (x / ty.color_max()) as Flt,
+							"blue" | "b" | "diffuse_blue" => color.z = (x / ty.color_max()) as Flt,
+							"s" | "u" | "texture_u" | "texture_s" => uv.x = x as Flt,
+							// Invert the v axis in the same way as .obj files
+							"t" | "v" | "texture_v" | "texture_t" => uv.y = 1.0 - x as Flt,
+							_ => {},
+						}
+					},
+					&PlyProperty::List(ref name, count_ty, item_ty) => {
+						let n = r.read(count_ty)?;
+						if n < 0.0 {
+							return Err(r.error(ParseErrorKind::BadNumber(n.to_string())));
+						}
+						// The count isn't used to allocate space up front, so that a huge count runs out of file instead of memory
+						let mut items = vec![];
+						for _ in 0..n as usize {
+							items.push(r.read(item_ty)?);
+						}
+						if e.name == "face" && (name == "vertex_indices" || name == "vertex_index") {
+							if items.len() < 3 {
+								return Err(r.error(ParseErrorKind::Invalid("face has less than 3 vertices".into())));
+							}
+							polygons.push(items.into_iter().map(|i| i as Idx).collect());
+						}
+					},
+				}
+			}
+			if e.name == "vertex" {
+				f.vertices.push(pos);
+				f.colors.push(color);
+				normals.push(normal);
+				uvs.push(uv);
+			}
+		}
+	}
+
+	// Only use the normals & uvs if the file has them
+	let vertex = elements.iter().find(|e| e.name == "vertex");
+	let has = |names: &[&str]| vertex.map(|e| e.properties.iter().any(|p| match p {
+		&PlyProperty::Scalar(ref n, _) => names.contains(&&n[..]),
+		_ => false,
+	})).unwrap_or(false);
+	let has_normals = has(&["nx"]);
+	let has_uvs = has(&["s", "u", "texture_u", "texture_s"]);
+	if has_normals {
+		f.normals = normals;
+	}
+	if has_uvs {
+		f.uvs = uvs;
+	}
+
+	let cur = FaceRange { object: None, group: None, material: None, start: 0, len: 0 };
+	for poly in polygons {
+		if let Some(&i) = poly.iter().find(|&&i| i as usize >= f.vertices.len()) {
+			return Err(ParseError::new(ParseErrorKind::IndexOutOfRange { what: "vertex", index: i as isize }, &f.rel_path));
+		}
+		let points: Vec<Vec3> = poly.iter().map(|&i| f.vertices[i as usize]).collect();
+		for t in triangulate(&points) {
+			let ii = |i: usize| {
+				let v = poly[i];
+				PreIndexInfo::new(v, if has_uvs { Some(v) } else { None }, if has_normals { Some(v) } else { None })
+			};
+			f.push_pre_face(vec3(ii(t[0]), ii(t[1]), ii(t[2])), &cur, 0);
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	use render::parse::ObjFile;
+
+	const ASCII: &'static str = "ply
+format ascii 1.0
+comment A coloured quad
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+";
+
+	#[test]
+	fn test_ply_ascii() {
+		let f = ObjFile::from_ply("res/mesh/test.ply".into(), ASCII.as_bytes()).unwrap();
+		assert_eq!(f.vertices.len(), 4);
+		assert_eq!(f.colors[1], vec3(0.0, 1.0, 0.0));
+		assert_eq!(f.faces.len(), 2);
+		// Normals are generated
+		assert_eq!(f.normals[f.faces[0].x.norm as usize], vec3(0.0, 0.0, 1.0));
+
+		let e = ObjFile::from_ply("res/mesh/test.ply".into(), ASCII.replace("4 0 1 2 3", "4 0 1 2 x").as_bytes()).unwrap_err();
+		assert_eq!(e.line, 18);
+
+		// Huge list counts are errors
+		let huge = ASCII.replace("list uchar int", "list float int").replace("4 0 1 2 3", "1e30 0 1 2 3");
+		assert!(ObjFile::from_ply("res/mesh/test.ply".into(), huge.as_bytes()).is_err());
+	}
+
+	#[test]
+	fn test_ply_binary() {
+		let mut data = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+			property float nx\nproperty float ny\nproperty float nz\nelement face 1\nproperty list uchar ushort vertex_indices\nend_header\n".to_vec();
+		let vs = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+		for v in vs.iter() {
+			for x in v.iter().chain([0.0f32, 0.0, -1.0].iter()) {
+				let b = x.to_bits();
+				data.extend_from_slice(&[(b >> 24) as u8, (b >> 16) as u8, (b >> 8) as u8, b as u8]);
+			}
+		}
+		data.extend_from_slice(&[3, 0, 0, 0, 1, 0, 2]);
+
+		let f = ObjFile::from_ply("res/mesh/test.ply".into(), &data).unwrap();
+		assert_eq!(f.vertices[1], vec3(1.0, 0.0, 0.0));
+		assert_eq!(f.faces.len(), 1);
+		// Normals are read from the file
+		assert_eq!(f.normals.len(), 3);
+		assert_eq!(f.normals[f.faces[0].y.norm as usize], vec3(0.0, 0.0, -1.0));
+
+		assert!(ObjFile::from_ply("res/mesh/test.ply".into(), &data[..data.len() - 1]).is_err());
+	}
+}
@@ -0,0 +1,199 @@
+use prelude::*;
+
+use std::f32::consts::PI;
+use std::fmt;
+use std::iter::Peekable;
+
+use super::ParseState;
+use super::error::{ParseErrorKind, ParseResult};
+use super::util;
+
+/// An axis of the mesh's space
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Axis {
+	X,
+	Y,
+	Z,
+}
+impl Axis {
+	/// Gets the axis that `v` is closest to pointing along
+	pub fn dominant(v: Vec3) -> Axis {
+		let a = vec3(v.x.abs(), v.y.abs(), v.z.abs());
+		if a.x > a.y && a.x > a.z {
+			Axis::X
+		} else if a.z > a.y {
+			Axis::Z
+		} else {
+			Axis::Y
+		}
+	}
+
+	/// Splits `v` into the 2 components across the axis, then the component along it
+	fn split(self, v: Vec3) -> (Flt, Flt, Flt) {
+		match self {
+			Axis::X => (v.z, v.y, v.x),
+			Axis::Y => (v.x, v.z, v.y),
+			Axis::Z => (v.x, v.y, v.z),
+		}
+	}
+}
+impl fmt::Display for Axis {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match *self { Axis::X => "x", Axis::Y => "y", Axis::Z => "z" })
+	}
+}
+
+/// How uvs are generated for faces that don't have any (`uv_projection` in .mtl files)
+///
+/// Generated uvs are scaled by the largest side of the mesh's bounding box, so textures aren't stretched.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UvProjection {
+	/// Projects onto the plane perpendicular to the axis
+	Planar(Axis),
+	/// Projects each face onto the plane that it is closest to facing, like a cube around the mesh
+	Box,
+	/// Projects outwards from the centre of the mesh, with the poles on the y axis
+	Spherical,
+	/// Projects outwards from the axis, going through the centre of the mesh
+	Cylindrical(Axis),
+}
+impl UvProjection {
+	/// Calculates the uvs of the corners of a triangle. `min` & `max` are the bounds of the mesh.
+	pub fn project(self, tri: [Vec3; 3], min: Vec3, max: Vec3) -> [Vec2; 3] {
+		let size = max - min;
+		let extent = size.x.max(size.y).max(size.z);
+		let extent = if extent > 0.0 { extent } else { 1.0 };
+		let centre = (min + max) * 0.5;
+
+		// Projects onto a plane, flipping u if the plane is being looked at from behind
+		let planar = |p: Vec3, axis: Axis, flip: bool| {
+			let (u, v, _) = axis.split((p - min) / extent);
+			vec2(if flip { 1.0 - u } else { u }, v)
+		};
+		// Angle around the axis, from 0 to 1
+		let around = |p: Vec3, axis: Axis| {
+			let (a, b, _) = axis.split(p - centre);
+			a.atan2(b) / (2.0 * PI) + 0.5
+		};
+
+		let mut uvs = [vec2(0.0, 0.0); 3];
+		match self {
+			UvProjection::Planar(axis) => {
+				for (uv, &p) in uvs.iter_mut().zip(tri.iter()) {
+					*uv = planar(p, axis, false);
+				}
+			},
+			UvProjection::Box => {
+				let normal = (tri[1] - tri[0]).cross(tri[2] - tri[0]);
+				let axis = Axis::dominant(normal);
+				let (_, _, facing) = axis.split(normal);
+				// The y axis is seen from above, so it is the other way around to x & z
+				let flip = if axis == Axis::Y { facing < 0.0 } else { facing > 0.0 };
+				for (uv, &p) in uvs.iter_mut().zip(tri.iter()) {
+					*uv = planar(p, axis, flip);
+				}
+			},
+			UvProjection::Spherical => {
+				for (uv, &p) in uvs.iter_mut().zip(tri.iter()) {
+					let d = p - centre;
+					let len = d.magnitude();
+					let v = if len > 0.0 { (d.y / len).max(-1.0).min(1.0).acos() / PI } else { 0.5 };
+					*uv = vec2(around(p, Axis::Y), v);
+				}
+				fix_seam(&mut uvs);
+			},
+			UvProjection::Cylindrical(axis) => {
+				for (uv, &p) in uvs.iter_mut().zip(tri.iter()) {
+					let (_, _, h) = axis.split((p - min) / extent);
+					*uv = vec2(around(p, axis), 1.0 - h);
+				}
+				fix_seam(&mut uvs);
+			},
+		}
+		uvs
+	}
+}
+impl Default for UvProjection {
+	fn default() -> UvProjection {
+		UvProjection::Planar(Axis::Y)
+	}
+}
+impl fmt::Display for UvProjection {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			UvProjection::Planar(axis) => write!(f, "planar {}", axis),
+			UvProjection::Box => f.write_str("box"),
+			UvProjection::Spherical => f.write_str("spherical"),
+			UvProjection::Cylindrical(axis) => write!(f, "cylindrical {}", axis),
+		}
+	}
+}
+
+/// Stops triangles that cross the seam of a wrapped projection from stretching across the whole texture
+fn fix_seam(uvs: &mut [Vec2; 3]) {
+	let min = uvs.iter().fold(1.0 as Flt, |m, uv| m.min(uv.x));
+	let max = uvs.iter().fold(0.0 as Flt, |m, uv| m.max(uv.x));
+	if max - min > 0.5 {
+		for uv in uvs.iter_mut() {
+			if uv.x < 0.5 {
+				uv.x += 1.0;
+			}
+		}
+	}
+}
+
+/// Parses the arguments of a `uv_projection` statement, e.g. `planar x` or `box`.
+///
+/// The axis is optional, and defaults to y.
+pub fn parse_uv_projection<'a, I>(state: &ParseState, args: &mut Peekable<I>) -> ParseResult<UvProjection>
+		where I: Iterator<Item=&'a str> {
+	fn parse_axis<'a, I>(state: &ParseState, args: &mut Peekable<I>) -> ParseResult<Axis>
+			where I: Iterator<Item=&'a str> {
+		match args.next() {
+			Some("x") => Ok(Axis::X),
+			Some("y") | None => Ok(Axis::Y),
+			Some("z") => Ok(Axis::Z),
+			Some(a) => Err(state.error_at(ParseErrorKind::BadArgument(a.to_string()), a)),
+		}
+	}
+	let a = args.next().ok_or_else(|| state.arity_error())?;
+	let ret = match a {
+		"planar" => UvProjection::Planar(parse_axis(state, args)?),
+		"box" => UvProjection::Box,
+		"spherical" => UvProjection::Spherical,
+		"cylindrical" => UvProjection::Cylindrical(parse_axis(state, args)?),
+		_ => return Err(state.error_at(ParseErrorKind::BadArgument(a.to_string()), a)),
+	};
+	util::ensure_end(state, args)?;
+	Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	use super::{Axis, UvProjection};
+
+	#[test]
+	fn test_project() {
+		let (min, max) = (vec3(-1.0, 0.0, -1.0), vec3(1.0, 2.0, 1.0));
+		// A wall facing +x, which a planar y projection would smear into a line
+		let wall = [vec3(1.0, 0.0, -1.0), vec3(1.0, 2.0, -1.0), vec3(1.0, 0.0, 1.0)];
+
+		let uvs = UvProjection::Planar(Axis::Y).project(wall, min, max);
+		assert_eq!(uvs[0], uvs[1]);
+		for uv in uvs.iter() {
+			assert!(uv.x >= 0.0 && uv.y >= 0.0);
+		}
+
+		let uvs = UvProjection::Box.project(wall, min, max);
+		assert_eq!(uvs, [vec2(1.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 0.0)]);
+
+		// Triangles crossing the seam wrap around rather than covering the whole texture
+		let tri = [vec3(-0.1, 1.0, -1.0), vec3(0.1, 1.0, -1.0), vec3(0.0, 2.0, -1.0)];
+		let uvs = UvProjection::Cylindrical(Axis::Y).project(tri, min, max);
+		for uv in uvs.iter() {
+			assert!((uv.x - 1.0).abs() < 0.1);
+		}
+	}
+}
@@ -0,0 +1,64 @@
+#[allow(unused_imports)]
+use prelude::*;
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use render::TextureID;
+use vfs::{self, FileStamp};
+
+/// Resolves the files that a mesh file refers to (`mtllib`, texture maps & glTF buffers).
+///
+/// All paths given are relative to the executable, in the same way as `ObjFile::rel_path`.
+///
+/// Closures of the form `FnMut(&Path) -> io::Result<String>` can be used as resolvers that
+/// only load material libraries.
+pub trait Resolver {
+	/// Reads the contents of the material library at `rel_path`
+	fn read_mtl(&mut self, rel_path: &Path) -> io::Result<String>;
+
+	/// Reads the contents of a binary file at `rel_path`, e.g. an external glTF buffer
+	fn read_bytes(&mut self, rel_path: &Path) -> io::Result<Vec<u8>> {
+		Err(io::Error::new(io::ErrorKind::NotFound, format!("file not found: {}", rel_path.display())))
+	}
+
+	/// Gets the ID of the texture at `rel_path`
+	fn texture_id(&mut self, rel_path: &Path) -> TextureID {
+		rel_path.to_string_lossy().into_owned()
+	}
+
+	/// Gets the size & modification time of the file at `rel_path`. This is called just before the file is read.
+	///
+	/// Returns None if the file isn't on disk, in which case meshes that use it aren't cached.
+	fn stamp(&mut self, _rel_path: &Path) -> Option<FileStamp> {
+		None
+	}
+}
+
+/// Resolves files by reading them from the directory the executable is in
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FsResolver;
+impl Resolver for FsResolver {
+	fn read_mtl(&mut self, rel_path: &Path) -> io::Result<String> {
+		let mut s = String::new();
+		File::open(vfs::canonicalize_exe(rel_path))?.read_to_string(&mut s)?;
+		Ok(s)
+	}
+
+	fn read_bytes(&mut self, rel_path: &Path) -> io::Result<Vec<u8>> {
+		let mut v = vec![];
+		File::open(vfs::canonicalize_exe(rel_path))?.read_to_end(&mut v)?;
+		Ok(v)
+	}
+
+	fn stamp(&mut self, rel_path: &Path) -> Option<FileStamp> {
+		vfs::file_stamp(rel_path)
+	}
+}
+
+impl<F> Resolver for F where F: FnMut(&Path) -> io::Result<String> {
+	fn read_mtl(&mut self, rel_path: &Path) -> io::Result<String> {
+		self(rel_path)
+	}
+}
@@ -0,0 +1,327 @@
+#[allow(unused_imports)]
+use prelude::*;
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use glium::Program;
+use glium::backend::Context;
+use glium::program::{ComputeShader, ProgramCreationError, ProgramCreationInput};
+
+use render::SHADER_DIR;
+use vfs;
+
+use super::util;
+
+/// Preprocesses the shaders of a program.
+///
+/// `#include "file"` is replaced with the contents of the file, relative to `res/shader`, and `#line`
+/// directives are added so that GLSL errors point at the right line. GLSL only allows source strings to be
+/// numbered, so each file is numbered by its index in `files`. As the numbers are shared by every shader in
+/// the program, `remap_log` can turn them back into file names no matter which shader the error is in.
+pub struct Preprocessor {
+	/// `#define`s added to the start of every shader
+	defines: Vec<(String, String)>,
+	/// Files read, relative to the executable
+	pub files: Vec<PathBuf>,
+	/// Files currently being included, to detect cycles
+	stack: Vec<PathBuf>,
+}
+impl Preprocessor {
+	pub fn new(defines: &[(&str, &str)]) -> Preprocessor {
+		Preprocessor {
+			defines: defines.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect(),
+			files: vec![],
+			stack: vec![],
+		}
+	}
+
+	/// Preprocesses the shader at `rel_path` (relative to the executable), using `read` to read files.
+	pub fn process<F>(&mut self, rel_path: &Path, read: &mut F) -> GameResult<String>
+			where F: FnMut(&Path) -> io::Result<String> {
+		let mut out = String::new();
+		self.include(&mut out, rel_path, None, read)?;
+		Ok(out)
+	}
+
+	/// Index of a file in `files`, adding it if it isn't there
+	fn file_index(&mut self, rel_path: &Path) -> usize {
+		match self.files.iter().position(|p| p == rel_path) {
+			Some(i) => i,
+			None => {
+				self.files.push(rel_path.to_path_buf());
+				self.files.len() - 1
+			}
+		}
+	}
+
+	/// Adds the contents of a file to `out`. `from` is where the file is included from, or None for the shader itself.
+	fn include<F>(&mut self, out: &mut String, rel_path: &Path, from: Option<(&Path, usize)>, read: &mut F) -> GameResult<()>
+			where F: FnMut(&Path) -> io::Result<String> {
+		let location = match from {
+			Some((path, lno)) => format!(" (included from {}:{})", path.display(), lno),
+			None => String::new(),
+		};
+		if self.stack.iter().any(|p| p == rel_path) {
+			let chain: Vec<String> = self.stack.iter().chain(Some(&rel_path.to_path_buf()))
+				.map(|p| p.display().to_string())
+				.collect();
+			return Err(format!("Include cycle{}: {}", location, chain.join(" -> ")));
+		}
+		let src = read(rel_path)
+			.map_err(|e| format!("Could not read shader file {}{}: {}", rel_path.display(), location, e))?;
+		let idx = self.file_index(rel_path);
+
+		self.stack.push(rel_path.to_path_buf());
+		let mut lines = src.lines().enumerate().map(|(i, l)| (i + 1, l)).peekable();
+		if from.is_none() {
+			// `#version` has to come before anything else, so the defines go just after it
+			let mut first = 1;
+			if let Some(&(_, line)) = lines.peek() {
+				if line.trim().starts_with("#version") {
+					out.push_str(line);
+					out.push('\n');
+					lines.next();
+					first = 2;
+				}
+			}
+			for &(ref k, ref v) in self.defines.iter() {
+				out.push_str(&format!("#define {} {}\n", k, v));
+			}
+			out.push_str(&format!("#line {} {}\n", first, idx));
+		} else {
+			out.push_str(&format!("#line 1 {}\n", idx));
+		}
+
+		for (lno, line) in lines {
+			let trimmed = line.trim();
+			if trimmed.starts_with("#include") {
+				let arg = trimmed["#include".len()..].trim();
+				if arg.len() < 2 || !arg.starts_with('"') || !arg.ends_with('"') {
+					self.stack.pop();
+					return Err(format!("Invalid include at {}:{}: expected `#include \"file\"`", rel_path.display(), lno));
+				}
+				let inc = util::remove_parents(&Path::new(SHADER_DIR).join(&arg[1..arg.len() - 1]));
+				let res = self.include(out, &inc, Some((rel_path, lno)), read);
+				if res.is_err() {
+					self.stack.pop();
+					return res;
+				}
+				// Go back to the line after the include
+				out.push_str(&format!("#line {} {}\n", lno + 1, idx));
+			} else {
+				out.push_str(line);
+				out.push('\n');
+			}
+		}
+		self.stack.pop();
+		Ok(())
+	}
+
+	/// Replaces the source string numbers in a GLSL compiler log with the names of the files.
+	///
+	/// Handles the `0:12` (Mesa, AMD & Intel) and `0(12)` (Nvidia) formats.
+	pub fn remap_log(&self, log: &str) -> String {
+		log.lines().map(|l| self.remap_log_line(l)).collect::<Vec<_>>().join("\n")
+	}
+
+	fn remap_log_line(&self, line: &str) -> String {
+		fn digits(s: &str) -> usize {
+			s.bytes().take_while(|&b| b >= b'0' && b <= b'9').count()
+		}
+		let prefix_len = ["ERROR: ", "WARNING: "].iter()
+			.find(|p| line.starts_with(*p))
+			.map(|p| p.len())
+			.unwrap_or(0);
+		let rest = &line[prefix_len..];
+		let n = digits(rest);
+		let file = match rest[..n].parse::<usize>().ok().and_then(|i| self.files.get(i)) {
+			Some(file) => file,
+			None => return line.to_string(),
+		};
+		let rest = &rest[n..];
+		let close = match rest.chars().next() {
+			Some(':') => None,
+			Some('(') => Some(')'),
+			_ => return line.to_string(),
+		};
+		let rest = &rest[1..];
+		let n = digits(rest);
+		if n == 0 {
+			return line.to_string();
+		}
+		let (lno, mut rest) = rest.split_at(n);
+		if let Some(c) = close {
+			if !rest.starts_with(c) {
+				return line.to_string();
+			}
+			rest = &rest[1..];
+		}
+		format!("{}{}:{}{}", &line[..prefix_len], file.display(), lno, rest)
+	}
+}
+
+/// Reads a file relative to the executable
+fn read_file(rel_path: &Path) -> io::Result<String> {
+	let mut s = String::new();
+	File::open(vfs::canonicalize_exe(rel_path))?.read_to_string(&mut s)?;
+	Ok(s)
+}
+
+/// Preprocesses a shader that the program may not have, returning None if `read` can't find the file
+fn process_optional<F>(pp: &mut Preprocessor, rel_path: &Path, missing: &mut Vec<PathBuf>, read: &mut F) -> GameResult<Option<String>>
+		where F: FnMut(&Path) -> io::Result<String> {
+	match read(rel_path) {
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+			missing.push(rel_path.to_path_buf());
+			Ok(None)
+		},
+		// Other errors are reported by the preprocessor
+		_ => pp.process(rel_path, read).map(Some),
+	}
+}
+
+/// Preprocessed shaders of a program
+struct ProgramSources {
+	vs: String,
+	tcs: Option<String>,
+	tes: Option<String>,
+	gs: Option<String>,
+	fs: String,
+	pp: Preprocessor,
+	/// Every file that the program was built from, then the optional shaders that don't exist
+	files: Vec<PathBuf>,
+}
+
+/// Preprocesses the shaders of the program at `rel_base`, using `read` to read files. See `load_shader_program`.
+fn preprocess_program<F>(rel_base: &str, defines: &[(&str, &str)], read: &mut F) -> GameResult<ProgramSources>
+		where F: FnMut(&Path) -> io::Result<String> {
+	let base = Path::new(rel_base);
+	let mut pp = Preprocessor::new(defines);
+	let mut missing = vec![];
+	let vs = pp.process(&base.with_extension("vs"), read)?;
+	let tcs = process_optional(&mut pp, &base.with_extension("tcs"), &mut missing, read)?;
+	let tes = process_optional(&mut pp, &base.with_extension("tes"), &mut missing, read)?;
+	let gs = process_optional(&mut pp, &base.with_extension("gs"), &mut missing, read)?;
+	let fs = pp.process(&base.with_extension("fs"), read)?;
+	if tcs.is_some() && tes.is_none() {
+		return Err(format!("Could not parse shader {}\nTessellation control shader has no evaluation shader ({})",
+			rel_base, base.with_extension("tes").display()));
+	}
+
+	let mut files = pp.files.clone();
+	files.extend(missing);
+	Ok(ProgramSources { vs: vs, tcs: tcs, tes: tes, gs: gs, fs: fs, pp: pp, files: files })
+}
+
+/// Turns an error from glium into a message with the file names of the shaders in it
+fn program_error(pp: &Preprocessor, rel_path: &str, e: ProgramCreationError) -> String {
+	let e = match e {
+		ProgramCreationError::CompilationError(log) => pp.remap_log(&log),
+		ProgramCreationError::LinkingError(log) => pp.remap_log(&log),
+		e => e.to_string(),
+	};
+	format!("Could not parse shader {}\n{}", rel_path, e)
+}
+
+/// Loads a shader program from `<rel_base>.vs` & `<rel_base>.fs`, adding `defines` to the start of each shader.
+///
+/// The geometry (`.gs`) and tessellation control & evaluation (`.tcs` & `.tes`) shaders are used if they exist.
+///
+/// Also returns every file that the program was built from, including the ones that were `#include`d. The
+/// optional shaders that don't exist are at the end, so that adding one can be noticed when reloading.
+pub fn load_shader_program(ctx: &Rc<Context>, rel_base: &str, defines: &[(&str, &str)]) -> GameResult<(Program, Vec<PathBuf>)> {
+	let src = preprocess_program(rel_base, defines, &mut read_file)?;
+	let input = ProgramCreationInput::SourceCode {
+		vertex_shader: &src.vs,
+		tessellation_control_shader: src.tcs.as_ref().map(|s| &s[..]),
+		tessellation_evaluation_shader: src.tes.as_ref().map(|s| &s[..]),
+		geometry_shader: src.gs.as_ref().map(|s| &s[..]),
+		fragment_shader: &src.fs,
+		transform_feedback_varyings: None,
+		outputs_srgb: false,
+		uses_point_size: false,
+	};
+	let prog = Program::new(ctx, input)
+		.map_err(|e| program_error(&src.pp, rel_base, e))?;
+	Ok((prog, src.files))
+}
+
+/// Loads a compute shader from `<rel_base>.comp`, adding `defines` to the start of it.
+///
+/// Also returns every file that the shader was built from, including the ones that were `#include`d. Unlike the
+/// programs in `Shader`s, compute shaders aren't hot reloaded; the caller has to check the files for changes.
+pub fn load_compute_shader(ctx: &Rc<Context>, rel_base: &str, defines: &[(&str, &str)]) -> GameResult<(ComputeShader, Vec<PathBuf>)> {
+	if !ComputeShader::is_supported(ctx) {
+		return Err(format!("Could not load compute shader {}: compute shaders are not supported", rel_base));
+	}
+	let mut pp = Preprocessor::new(defines);
+	let src = pp.process(&Path::new(rel_base).with_extension("comp"), &mut read_file)?;
+	let shader = ComputeShader::from_source(ctx, &src)
+		.map_err(|e| program_error(&pp, rel_base, e))?;
+	Ok((shader, pp.files))
+}
+
+#[cfg(test)]
+mod test {
+	use std::io;
+	use std::path::Path;
+
+	use super::{preprocess_program, Preprocessor};
+
+	fn read(p: &Path) -> io::Result<String> {
+		let s = match &*p.to_string_lossy() {
+			"res/shader/main.fs" => "#version 440\n#include \"lib/light.glsl\"\nvoid main() {}\n",
+			"res/shader/lib/light.glsl" => "// Lighting\n#include \"color.glsl\"\nfloat light() { return 1.0; }\n",
+			"res/shader/color.glsl" => "vec3 color() { return vec3(1.0); }\n",
+			"res/shader/a.glsl" => "#include \"b.glsl\"\n",
+			"res/shader/b.glsl" => "#include \"a.glsl\"\n",
+			"res/shader/main.vs" | "res/shader/tess.vs" | "res/shader/tess.tcs" | "res/shader/tess.fs" => "void main() {}\n",
+			_ => return Err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+		};
+		Ok(s.to_string())
+	}
+
+	#[test]
+	fn test_preprocess() {
+		let mut pp = Preprocessor::new(&[("INSTANCED", "1")]);
+		let src = pp.process(Path::new("res/shader/main.fs"), &mut read).unwrap();
+		assert_eq!(src, "#version 440\n#define INSTANCED 1\n#line 2 0\n\
+			#line 1 1\n// Lighting\n\
+			#line 1 2\nvec3 color() { return vec3(1.0); }\n\
+			#line 3 1\nfloat light() { return 1.0; }\n\
+			#line 3 0\nvoid main() {}\n");
+		assert_eq!(pp.files, vec![Path::new("res/shader/main.fs"), Path::new("res/shader/lib/light.glsl"), Path::new("res/shader/color.glsl")]);
+
+		assert_eq!(pp.remap_log("0:3(5): error: `x' undeclared\nERROR: 1:2: bad\n2(1) : error C0000: syntax error\n7:1: unknown file"),
+			"res/shader/main.fs:3(5): error: `x' undeclared\nERROR: res/shader/lib/light.glsl:2: bad\n\
+			res/shader/color.glsl:1 : error C0000: syntax error\n7:1: unknown file");
+	}
+
+	#[test]
+	fn test_include_errors() {
+		let mut pp = Preprocessor::new(&[]);
+		let e = pp.process(Path::new("res/shader/a.glsl"), &mut read).unwrap_err();
+		assert!(e.contains("res/shader/a.glsl -> res/shader/b.glsl -> res/shader/a.glsl"), "{}", e);
+
+		let mut read_missing = |p: &Path| if p == Path::new("res/shader/x.fs") { Ok("#include \"missing.glsl\"\n".to_string()) } else { read(p) };
+		let e = Preprocessor::new(&[]).process(Path::new("res/shader/x.fs"), &mut read_missing).unwrap_err();
+		assert!(e.contains("res/shader/missing.glsl (included from res/shader/x.fs:1)"), "{}", e);
+	}
+
+	#[test]
+	fn test_preprocess_program() {
+		// The optional shaders that don't exist come after the files that were read, so that adding one is noticed
+		let src = preprocess_program("res/shader/main", &[], &mut read).unwrap();
+		assert!(src.tcs.is_none() && src.tes.is_none() && src.gs.is_none());
+		let files: Vec<&Path> = src.files.iter().map(|p| p.as_path()).collect();
+		assert_eq!(files, vec![Path::new("res/shader/main.vs"), Path::new("res/shader/main.fs"),
+			Path::new("res/shader/lib/light.glsl"), Path::new("res/shader/color.glsl"),
+			Path::new("res/shader/main.tcs"), Path::new("res/shader/main.tes"), Path::new("res/shader/main.gs")]);
+
+		let e = preprocess_program("res/shader/tess", &[], &mut read).err().unwrap();
+		assert!(e.contains("Tessellation control shader has no evaluation shader (res/shader/tess.tes)"), "{}", e);
+	}
+}
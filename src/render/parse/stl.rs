@@ -0,0 +1,235 @@
+use prelude::*;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::str;
+
+use vfs;
+
+use super::{ObjFile, FaceRange, PreIndexInfo, ParseState};
+use super::error::{ParseError, ParseErrorKind, ParseResult};
+use super::triangulate::triangulate;
+use super::util;
+
+impl ObjFile {
+	/// Loads an .stl file (binary or ASCII) from a path relative to the executable.
+	///
+	/// The file is converted into an `ObjFile`, so missing normals & uvs are generated in the same way.
+	pub fn load_stl(rel_path: String) -> ParseResult<ObjFile> {
+		let path = vfs::canonicalize_exe(&rel_path);
+		let stamp = vfs::file_stamp(&rel_path);
+		let mut data = vec![];
+		File::open(&path)
+			.and_then(|mut file| file.read_to_end(&mut data))
+			.map_err(|e| ParseError::new(ParseErrorKind::Io(e.to_string()), &rel_path))?;
+
+		let mut f = ObjFile::empty(rel_path, path);
+		f.sources[0].1 = stamp;
+		parse_stl(&mut f, &data)?;
+		f.finish()?;
+		Ok(f)
+	}
+
+	/// Parses an .stl file (binary or ASCII) from memory. `rel_path` is used for error messages.
+	pub fn from_stl(rel_path: String, data: &[u8]) -> ParseResult<ObjFile> {
+		let path = PathBuf::from(&rel_path);
+		let mut f = ObjFile::empty(rel_path, path);
+		parse_stl(&mut f, data)?;
+		f.finish()?;
+		Ok(f)
+	}
+}
+
+fn read_f32(data: &[u8], offset: usize) -> Flt {
+	let b = &data[offset..offset + 4];
+	f32::from_bits(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+}
+
+fn read_vec3(data: &[u8], offset: usize) -> Vec3 {
+	vec3(read_f32(data, offset), read_f32(data, offset + 4), read_f32(data, offset + 8))
+}
+
+/// Adds a facet. `normal` is ignored if it is zero, so that it is generated instead.
+fn push_facet(f: &mut ObjFile, cur: &FaceRange, normal: Vec3, vertices: &[Vec3]) {
+	let start = f.vertices.len() as Idx;
+	f.vertices.extend_from_slice(vertices);
+	f.colors.extend(vertices.iter().map(|_| vec3(1.0, 1.0, 1.0)));
+	let norm = if normal.magnitude2() != 0.0 {
+		f.normals.push(normal.normalize());
+		Some((f.normals.len() - 1) as Idx)
+	} else {
+		None
+	};
+	for t in triangulate(vertices) {
+		let ii = |i: usize| PreIndexInfo::new(start + i as Idx, None, norm);
+		f.push_pre_face(vec3(ii(t[0]), ii(t[1]), ii(t[2])), cur, 0);
+	}
+}
+
+fn parse_stl(f: &mut ObjFile, data: &[u8]) -> ParseResult<()> {
+	// Binary files have an 80 byte header, then the number of triangles and 50 bytes per triangle.
+	// ASCII files start with `solid`, but so do some binary files, so check the size of the file first.
+	let binary_len = if data.len() >= 84 {
+		let n = data[80] as usize | (data[81] as usize) << 8 | (data[82] as usize) << 16 | (data[83] as usize) << 24;
+		n.checked_mul(50).and_then(|x| x.checked_add(84))
+	} else {
+		None
+	};
+	let is_ascii = binary_len != Some(data.len()) && !data.contains(&0) && str::from_utf8(data)
+		.map(|s| s.split_whitespace().next() == Some("solid"))
+		.unwrap_or(false);
+
+	if is_ascii {
+		parse_ascii(f, str::from_utf8(data).unwrap())
+	} else {
+		match binary_len {
+			Some(len) if len <= data.len() => {},
+			_ => return Err(ParseError::new(ParseErrorKind::Invalid("binary STL file is cut off".into()), &f.rel_path)),
+		}
+		let cur = FaceRange { object: None, group: None, material: None, start: 0, len: 0 };
+		let mut offset = 84;
+		while offset + 50 <= data.len() {
+			let normal = read_vec3(data, offset);
+			let vertices = [read_vec3(data, offset + 12), read_vec3(data, offset + 24), read_vec3(data, offset + 36)];
+			push_facet(f, &cur, normal, &vertices);
+			offset += 50; // Skip the attribute byte count
+		}
+		Ok(())
+	}
+}
+
+fn parse_ascii(f: &mut ObjFile, s: &str) -> ParseResult<()> {
+	let mut state = ParseState::new(f.path.clone(), PathBuf::from(&f.rel_path));
+	let mut cur = FaceRange { object: None, group: None, material: None, start: 0, len: 0 };
+	// Normal & vertices of the facet currently being read
+	let mut facet: Option<(Vec3, Vec<Vec3>)> = None;
+
+	for (lno, line) in s.lines().enumerate() {
+		let lno = lno + 1;
+		let line = line.trim();
+		if line == "" { continue; }
+		let mut args = line.split_whitespace().peekable();
+		let command = args.next().unwrap_or("");
+		state.command = command;
+		state.line = line;
+		state.lno = lno;
+		match command {
+			"solid" => {
+				let name: Vec<&str> = args.collect();
+				if !name.is_empty() {
+					cur.object = Some(name.join(" "));
+					if f.name.is_none() {
+						f.name = cur.object.clone();
+					}
+				}
+			},
+			"facet" => {
+				if facet.is_some() {
+					return Err(state.error(ParseErrorKind::Invalid("facet inside of another facet".into())));
+				}
+				match args.next() {
+					Some("normal") => {},
+					Some(a) => return Err(state.error_at(ParseErrorKind::BadArgument(a.to_string()), a)),
+					None => return Err(state.arity_error()),
+				}
+				facet = Some((util::parse_vec3_only(&state, &mut args)?, vec![]));
+			},
+			"outer" => {
+				match args.next() {
+					Some("loop") => util::ensure_end(&state, &mut args)?,
+					Some(a) => return Err(state.error_at(ParseErrorKind::BadArgument(a.to_string()), a)),
+					None => return Err(state.arity_error()),
+				}
+			},
+			"vertex" => {
+				let v = util::parse_vec3_only(&state, &mut args)?;
+				match facet {
+					Some((_, ref mut vertices)) => vertices.push(v),
+					None => return Err(state.error(ParseErrorKind::Invalid("vertex outside of a facet".into()))),
+				}
+			},
+			"endloop" => {},
+			"endfacet" => {
+				match facet.take() {
+					Some((normal, ref vertices)) if vertices.len() >= 3 => push_facet(f, &cur, normal, vertices),
+					Some(_) => return Err(state.error(ParseErrorKind::Invalid("facet has less than 3 vertices".into()))),
+					None => return Err(state.error(ParseErrorKind::Invalid("`endfacet` outside of a facet".into()))),
+				}
+			},
+			"endsolid" => {
+				cur.object = None;
+			},
+			_ => {
+				return Err(state.error(ParseErrorKind::UnknownCommand(command.to_string())));
+			}
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	use render::parse::{ObjFile, ParseErrorKind};
+
+	const ASCII: &'static str = "solid pyramid
+facet normal 0 0 -1
+	outer loop
+		vertex 0 0 0
+		vertex 0 1 0
+		vertex 1 1 0
+		vertex 1 0 0
+	endloop
+endfacet
+facet normal 0 0 0
+	outer loop
+		vertex 0 0 0
+		vertex 1 0 0
+		vertex 0.5 0.5 1
+	endloop
+endfacet
+endsolid pyramid
+";
+
+	#[test]
+	fn test_stl_ascii() {
+		let f = ObjFile::from_stl("res/mesh/test.stl".into(), ASCII.as_bytes()).unwrap();
+		assert_eq!(f.name, Some("pyramid".into()));
+		assert_eq!(f.faces.len(), 3);
+		// The first facet keeps its normal, the second has one generated
+		assert_eq!(f.normals[f.faces[0].x.norm as usize], vec3(0.0, 0.0, -1.0));
+		let n = f.normals[f.faces[2].x.norm as usize];
+		assert!((n - vec3(0.0, -1.0, 0.5).normalize()).magnitude() < 1e-5);
+
+		let e = ObjFile::from_stl("res/mesh/test.stl".into(), b"solid x\nvertex 0 0 0\n").unwrap_err();
+		assert_eq!(e.line, 2);
+	}
+
+	#[test]
+	fn test_stl_binary() {
+		fn push_vec3(data: &mut Vec<u8>, v: [f32; 3]) {
+			for x in v.iter() {
+				let b = x.to_bits();
+				data.extend_from_slice(&[b as u8, (b >> 8) as u8, (b >> 16) as u8, (b >> 24) as u8]);
+			}
+		}
+		// Binary files can start with "solid" too
+		let mut data = b"solid but actually binary".to_vec();
+		data.resize(80, 0);
+		data.extend_from_slice(&[1, 0, 0, 0]);
+		push_vec3(&mut data, [0.0, 0.0, 1.0]);
+		push_vec3(&mut data, [0.0, 0.0, 0.0]);
+		push_vec3(&mut data, [1.0, 0.0, 0.0]);
+		push_vec3(&mut data, [0.0, 1.0, 0.0]);
+		data.extend_from_slice(&[0, 0]);
+
+		let f = ObjFile::from_stl("res/mesh/test.stl".into(), &data).unwrap();
+		assert_eq!(f.vertices, vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0)]);
+		assert_eq!(f.faces.len(), 1);
+
+		let e = ObjFile::from_stl("res/mesh/test.stl".into(), &data[..100]).unwrap_err();
+		assert_eq!(e.kind, ParseErrorKind::Invalid("binary STL file is cut off".into()));
+	}
+}
@@ -0,0 +1,210 @@
+use prelude::*;
+
+/// Triangulates a polygon using ear clipping.
+///
+/// Returns triangles as indices into `points`, keeping the winding order of the polygon.
+/// The polygon is assumed to be roughly planar and non self-intersecting.
+///
+/// Clipping an ear only changes whether its neighbours are ears, and only reflex vertices can be inside of an ear, so
+/// this takes O(n * r) time for a polygon with n vertices, r of which are reflex.
+pub fn triangulate(points: &[Vec3]) -> Vec<[usize; 3]> {
+	let n = points.len();
+	if n < 3 {
+		return vec![];
+	} else if n == 3 {
+		return vec![[0, 1, 2]];
+	}
+
+	// Project onto the plane the polygon lies in, by dropping the largest component of the normal
+	let normal = newell_normal(points);
+	let (ax, ay) = if normal.x.abs() >= normal.y.abs() && normal.x.abs() >= normal.z.abs() {
+		(1, 2)
+	} else if normal.y.abs() >= normal.z.abs() {
+		(2, 0)
+	} else {
+		(0, 1)
+	};
+	let pts: Vec<Vec2> = points.iter().map(|p| vec2(p[ax], p[ay])).collect();
+
+	// Orientation of the projected polygon
+	let mut area = 0.0;
+	for i in 0..n {
+		let a = pts[i];
+		let b = pts[(i + 1) % n];
+		area += a.x * b.y - b.x * a.y;
+	}
+	let sign = if area < 0.0 { -1.0 } else { 1.0 };
+
+	// The remaining vertices, as a circular linked list
+	let mut poly = Polygon {
+		pts: pts,
+		prev: (0..n).map(|i| (i + n - 1) % n).collect(),
+		next: (0..n).map(|i| (i + 1) % n).collect(),
+		is_reflex: vec![false; n],
+		reflex: vec![],
+		sign: sign,
+	};
+	for i in 0..n {
+		poly.update_reflex(i);
+	}
+	let mut ear: Vec<bool> = (0..n).map(|i| poly.is_ear(i)).collect();
+
+	let mut ret = Vec::with_capacity(n - 2);
+	let mut remaining = n;
+	let mut cur = 0;
+	// Vertices looked at since the last ear was clipped
+	let mut checked = 0;
+	while remaining > 3 {
+		// If there are no ears, the polygon is degenerate, so clip the vertex anyway
+		if !ear[cur] && checked < remaining {
+			cur = poly.next[cur];
+			checked += 1;
+			continue;
+		}
+		let (ip, inx) = (poly.prev[cur], poly.next[cur]);
+		ret.push([ip, cur, inx]);
+		poly.next[ip] = inx;
+		poly.prev[inx] = ip;
+		poly.is_reflex[cur] = false;
+		poly.update_reflex(ip);
+		poly.update_reflex(inx);
+		{
+			let is_reflex = &poly.is_reflex;
+			poly.reflex.retain(|&i| is_reflex[i]);
+		}
+		ear[ip] = poly.is_ear(ip);
+		ear[inx] = poly.is_ear(inx);
+		remaining -= 1;
+		cur = inx;
+		checked = 0;
+	}
+	ret.push([poly.prev[cur], cur, poly.next[cur]]);
+	ret
+}
+
+/// A polygon that is being clipped
+struct Polygon {
+	/// Vertices, projected onto the plane of the polygon
+	pts: Vec<Vec2>,
+	/// Previous & next vertices that haven't been clipped yet
+	prev: Vec<usize>,
+	next: Vec<usize>,
+	is_reflex: Vec<bool>,
+	/// Reflex vertices that haven't been clipped, including some that have stopped being reflex
+	reflex: Vec<usize>,
+	/// Orientation of the polygon
+	sign: Flt,
+}
+impl Polygon {
+	/// Checks if a vertex is reflex (or flat). Vertices only stop being reflex as ears are clipped, never start.
+	fn update_reflex(&mut self, i: usize) {
+		let was_reflex = self.is_reflex[i];
+		self.is_reflex[i] = cross2(self.pts[self.prev[i]], self.pts[i], self.pts[self.next[i]]) * self.sign <= 0.0;
+		if self.is_reflex[i] && !was_reflex {
+			self.reflex.push(i);
+		}
+	}
+
+	fn is_ear(&self, i: usize) -> bool {
+		// Reflex (or flat) vertices cannot be ears
+		if self.is_reflex[i] {
+			return false;
+		}
+		let (ip, inx) = (self.prev[i], self.next[i]);
+		let (a, b, c) = (self.pts[ip], self.pts[i], self.pts[inx]);
+		// No other vertex may lie inside the ear. If a convex vertex does, a reflex one does too.
+		for &r in self.reflex.iter() {
+			if r == ip || r == inx || !self.is_reflex[r] {
+				continue;
+			}
+			let p = self.pts[r];
+			if cross2(a, b, p) * self.sign >= 0.0
+			&& cross2(b, c, p) * self.sign >= 0.0
+			&& cross2(c, a, p) * self.sign >= 0.0 {
+				return false;
+			}
+		}
+		true
+	}
+}
+
+/// Calculates the (unnormalized) normal of a polygon using Newell's method
+fn newell_normal(points: &[Vec3]) -> Vec3 {
+	let mut n = vec3(0.0, 0.0, 0.0);
+	for i in 0..points.len() {
+		let a = points[i];
+		let b = points[(i + 1) % points.len()];
+		n.x += (a.y - b.y) * (a.z + b.z);
+		n.y += (a.z - b.z) * (a.x + b.x);
+		n.z += (a.x - b.x) * (a.y + b.y);
+	}
+	n
+}
+
+fn cross2(o: Vec2, a: Vec2, b: Vec2) -> Flt {
+	(a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	#[test]
+	fn test_triangulate_quad() {
+		let ps = [vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(1.0, 0.0, -1.0), vec3(0.0, 0.0, -1.0)];
+		let ts = super::triangulate(&ps);
+		assert_eq!(ts.len(), 2);
+	}
+
+	#[test]
+	fn test_triangulate_concave() {
+		// An L shape in the xy plane. Fanning from vertex 0 would produce a triangle outside of it.
+		// 5-4
+		// | |
+		// | 3-2
+		// |   |
+		// 0---1
+		let ps = [
+			vec3(0.0, 0.0, 0.0), vec3(2.0, 0.0, 0.0), vec3(2.0, 1.0, 0.0),
+			vec3(1.0, 1.0, 0.0), vec3(1.0, 2.0, 0.0), vec3(0.0, 2.0, 0.0),
+		];
+		let ts = super::triangulate(&ps);
+		assert_eq!(ts.len(), 4);
+		let mut area = 0.0;
+		for t in ts.iter() {
+			let (a, b, c) = (ps[t[0]], ps[t[1]], ps[t[2]]);
+			let z = (b - a).cross(c - a).z;
+			// Winding must be preserved (counter-clockwise)
+			assert!(z > 0.0);
+			area += z / 2.0;
+		}
+		assert!((area - 3.0).abs() < 1e-5);
+	}
+
+	#[test]
+	fn test_triangulate_large() {
+		// A star with thousands of vertices, half of them reflex, must be quick to triangulate
+		let n = 4000;
+		let ps: Vec<Vec3> = (0..n).map(|i| {
+			let a = i as Flt / n as Flt * 2.0 * ::std::f32::consts::PI;
+			let r = if i % 2 == 0 { 1.0 } else { 0.9 };
+			vec3(a.cos() * r, a.sin() * r, 0.0)
+		}).collect();
+		let ts = super::triangulate(&ps);
+		assert_eq!(ts.len(), n - 2);
+		let mut area = 0.0;
+		for t in ts.iter() {
+			let (a, b, c) = (ps[t[0]], ps[t[1]], ps[t[2]]);
+			let z = (b - a).cross(c - a).z;
+			assert!(z > 0.0);
+			area += z / 2.0;
+		}
+		let mut expected = 0.0;
+		for i in 0..n {
+			let (a, b) = (ps[i], ps[(i + 1) % n]);
+			expected += (a.x * b.y - b.x * a.y) / 2.0;
+		}
+		assert!((area - expected).abs() < 1e-3, "{} != {}", area, expected);
+	}
+}
@@ -5,32 +5,58 @@ use std::str::FromStr;
 use std::iter::Peekable;
 
 use super::ParseState;
+use super::error::{ParseResult, ParseErrorKind};
 
 // The `*_only` variants of the `parse*` functions only parse the specified amount,
 // and error when there are more arguments
 
-pub fn parse_vec3_only<'a, I>(st: &ParseState, it: &mut Peekable<I>) -> GameResult<Vec3>
+pub fn parse_vec3_only<'a, I>(st: &ParseState, it: &mut Peekable<I>) -> ParseResult<Vec3>
 		where I: Iterator<Item=&'a str> {
 	let v = parseN_only(st, 3, it)?;
 	let v = vec3(v[0], v[1], v[2]);
 	Ok(v)
 }
 
+/// Parses a token. If invalid, returns Err.
+pub fn parse_token<F: FromStr>(st: &ParseState, a: &str) -> ParseResult<F> {
+	a.parse().map_err(|_| st.error_at(ParseErrorKind::BadNumber(a.to_string()), a))
+}
+
+/// Parses an `on` or `off` item from the iterator. If invalid, returns Err.
+pub fn parse_on_off<'a, I>(st: &ParseState, it: &mut Peekable<I>) -> ParseResult<bool>
+		where I: Iterator<Item=&'a str> {
+	match it.next() {
+		Some("on") => Ok(true),
+		Some("off") => Ok(false),
+		Some(a) => Err(st.error_at(ParseErrorKind::BadArgument(a.to_string()), a)),
+		None => Err(st.arity_error()),
+	}
+}
+
 /// Parses one item from the iterator. If invalid, returns Err.
-pub fn parse1<'a, F: FromStr, I>(st: &ParseState, it: &mut Peekable<I>) -> GameResult<F>
+pub fn parse1<'a, F: FromStr, I>(st: &ParseState, it: &mut Peekable<I>) -> ParseResult<F>
 		where I: Iterator<Item=&'a str> {
-	let a = it.next().ok_or_else(|| st.to_error())?;
-	a.parse().map_err(|_| st.to_error())
+	let a = it.next().ok_or_else(|| st.arity_error())?;
+	parse_token(st, a)
 }
 
 /// Parses one item from the iterator. If invalid, returns Err.
 /// 
 /// If there is another item after this one, this function returns Err.
-pub fn parse1_only<'a, F: FromStr, I>(st: &ParseState, it: &mut Peekable<I>) -> GameResult<F>
+pub fn parse1_only<'a, F: FromStr, I>(st: &ParseState, it: &mut Peekable<I>) -> ParseResult<F>
 		where I: Iterator<Item=&'a str> {
-	let a = it.next().ok_or_else(|| st.to_error())?;
-	if it.peek().is_some() { return Err(st.to_error()); }
-	a.parse().map_err(|_| st.to_error())
+	let a = it.next().ok_or_else(|| st.arity_error())?;
+	ensure_end(st, it)?;
+	parse_token(st, a)
+}
+
+/// Returns Err if there are any items left in the iterator.
+pub fn ensure_end<'a, I>(st: &ParseState, it: &mut Peekable<I>) -> ParseResult<()>
+		where I: Iterator<Item=&'a str> {
+	match it.peek() {
+		Some(a) => Err(st.error_at(ParseErrorKind::BadArity(st.command.to_string()), a)),
+		None => Ok(()),
+	}
 }
 
 /// Parses one item from the iterator. If invalid, returns None, and doesn't move the iterator forward.
@@ -49,8 +75,7 @@ pub fn parse1_opt<'a, F: FromStr, I>(it: &mut Peekable<I>) -> Option<F>
 }
 
 /// Parses N items from the iterator. If any are invalid, returns Err.
-#[allow(dead_code)]
-pub fn parseN<'a, F: FromStr, I>(st: &ParseState, n: usize, it: &mut Peekable<I>) -> GameResult<Vec<F>>
+pub fn parseN<'a, F: FromStr, I>(st: &ParseState, n: usize, it: &mut Peekable<I>) -> ParseResult<Vec<F>>
 		where I: Iterator<Item=&'a str> {
 	let mut ret = Vec::with_capacity(n);
 	for _ in 0..n {
@@ -62,7 +87,7 @@ pub fn parseN<'a, F: FromStr, I>(st: &ParseState, n: usize, it: &mut Peekable<I>
 /// Parses N items from the iterator. If any are invalid, returns Err.
 /// 
 /// If there is another item after the last one, this function returns Err.
-pub fn parseN_only<'a, F: FromStr, I>(st: &ParseState, n: usize, it: &mut Peekable<I>) -> GameResult<Vec<F>>
+pub fn parseN_only<'a, F: FromStr, I>(st: &ParseState, n: usize, it: &mut Peekable<I>) -> ParseResult<Vec<F>>
 		where I: Iterator<Item=&'a str> {
 	let mut ret = Vec::with_capacity(n);
 	for _ in 0..n-1 {
@@ -93,6 +118,26 @@ pub fn remove_parents(p: &Path) -> PathBuf {
 	ret
 }
 
+/// Gets the path of `to` relative to the directory `from_dir`
+///
+/// Both paths should be relative to the same directory.
+pub fn relative_path(from_dir: &Path, to: &Path) -> PathBuf {
+	let from_dir = remove_parents(from_dir);
+	let to = remove_parents(to);
+	let from_cs: Vec<_> = from_dir.components().collect();
+	let to_cs: Vec<_> = to.components().collect();
+	let common = from_cs.iter().zip(to_cs.iter()).take_while(|&(a, b)| a == b).count();
+
+	let mut ret = PathBuf::new();
+	for _ in common..from_cs.len() {
+		ret.push("..");
+	}
+	for c in to_cs[common..].iter() {
+		ret.push(c.as_os_str());
+	}
+	ret
+}
+
 #[cfg(test)]
 mod test {
 	#[test]
@@ -111,4 +156,19 @@ mod test {
 		trp!("../thing/../other/", "../other/");
 		trp!("../../../thing/thing2/../o", "../../../thing/o");
 	}
-}
\ No newline at end of file
+	
+	#[test]
+	fn test_relative_path() {
+		use std::path::Path;
+		macro_rules! trp {
+			($from:expr, $to:expr, $expect:expr) => ({
+				let ret = super::relative_path(Path::new($from), Path::new($to));
+				assert_eq!(ret, Path::new($expect));
+			})
+		}
+		
+		trp!("res/mesh", "res/mesh/earth.png", "earth.png");
+		trp!("res/mesh", "res/tex/earth.png", "../tex/earth.png");
+		trp!("res/mesh/../mesh/", "res/tex/sub/../earth.png", "../tex/earth.png");
+	}
+}
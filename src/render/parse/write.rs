@@ -0,0 +1,296 @@
+#[allow(unused_imports)]
+use prelude::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use render::{Material, TextureID, TextureOptions};
+use vfs;
+
+use super::ObjFile;
+use super::util;
+
+/// Saves an .obj file and its materials to `rel_path` (relative to the executable).
+///
+/// The materials are saved next to it, in a file with the same name and a .mtl extension.
+pub fn save_obj(f: &ObjFile, rel_path: &str) -> io::Result<()> {
+	let rel_path = Path::new(rel_path);
+	let mtl_rel_path = rel_path.with_extension("mtl");
+	let mtl_name = mtl_rel_path.file_name()
+		.map(|s| s.to_string_lossy().into_owned())
+		.unwrap_or(String::new());
+
+	let mut w = io::BufWriter::new(File::create(vfs::canonicalize_exe(rel_path))?);
+	write_obj(&mut w, f, Some(&mtl_name))?;
+	let mut w = io::BufWriter::new(File::create(vfs::canonicalize_exe(&mtl_rel_path))?);
+	write_mtl(&mut w, &f.materials, &mtl_rel_path)
+}
+
+/// Writes the contents of an .obj file.
+///
+/// The faces that are written are the calculated faces, so every face has a uv and normal.
+/// If `mtllib` is given, a `mtllib` statement is written so that the materials can be found.
+pub fn write_obj<W: Write>(w: &mut W, f: &ObjFile, mtllib: Option<&str>) -> io::Result<()> {
+	writeln!(w, "# Exported from {}", f.rel_path)?;
+	if let Some(mtllib) = mtllib {
+		writeln!(w, "mtllib {}", mtllib)?;
+	}
+
+	// Colours are only written if there are any, as not every program can read them
+	let white = vec3(1.0, 1.0, 1.0);
+	let has_colors = f.colors.iter().any(|&c| c != white);
+	for (v, c) in f.vertices.iter().zip(f.colors.iter()) {
+		if has_colors {
+			writeln!(w, "v {} {} {} {} {} {}", v.x, v.y, v.z, c.x, c.y, c.z)?;
+		} else {
+			writeln!(w, "v {} {} {}", v.x, v.y, v.z)?;
+		}
+	}
+	for uv in f.uvs.iter() {
+		// Undo the inversion of the v axis done when parsing
+		writeln!(w, "vt {} {}", uv.x, 1.0 - uv.y)?;
+	}
+	for n in f.normals.iter() {
+		writeln!(w, "vn {} {} {}", n.x, n.y, n.z)?;
+	}
+
+	let mut object = None;
+	let mut group = None;
+	let mut material = None;
+	let mut smoothing = 0;
+	for r in f.ranges.iter() {
+		if r.object != object {
+			object = r.object.clone();
+			group = None;
+			if let Some(ref o) = object {
+				writeln!(w, "o {}", o)?;
+			}
+		}
+		if r.group != group {
+			group = r.group.clone();
+			match group {
+				Some(ref g) => writeln!(w, "g {}", g)?,
+				None => writeln!(w, "g")?,
+			}
+		}
+		if r.material != material {
+			material = r.material.clone();
+			if let Some(ref m) = material {
+				writeln!(w, "usemtl {}", m)?;
+			}
+		}
+		for i in r.start..r.start + r.len {
+			let s = f.smoothing_groups.get(i).cloned().unwrap_or(0);
+			if s != smoothing {
+				smoothing = s;
+				if s == 0 {
+					writeln!(w, "s off")?;
+				} else {
+					writeln!(w, "s {}", s)?;
+				}
+			}
+			let face = f.faces[i];
+			write!(w, "f")?;
+			for ii in [face.x, face.y, face.z].iter() {
+				write!(w, " {}/{}/{}", ii.vert + 1, ii.uv + 1, ii.norm + 1)?;
+			}
+			writeln!(w)?;
+		}
+	}
+	Ok(())
+}
+
+/// Writes the contents of an .mtl file.
+///
+/// `rel_path` is the path of the .mtl file relative to the executable, and is used to write
+/// texture paths relative to the .mtl file.
+pub fn write_mtl<W: Write>(w: &mut W, materials: &HashMap<String, Material>, rel_path: &Path) -> io::Result<()> {
+	let dir = rel_path.parent().unwrap_or(Path::new(""));
+	let def = Material::default();
+
+	// Sort the materials so that the output is always the same
+	let mut names: Vec<&String> = materials.keys().collect();
+	names.sort();
+	for name in names {
+		let m = &materials[name];
+		writeln!(w, "newmtl {}", name)?;
+		writeln!(w, "Ns {}", m.Ns)?;
+		writeln!(w, "Ka {} {} {}", m.Ka.x, m.Ka.y, m.Ka.z)?;
+		writeln!(w, "Kd {} {} {}", m.Kd.x, m.Kd.y, m.Kd.z)?;
+		writeln!(w, "Ks {} {} {}", m.Ks.x, m.Ks.y, m.Ks.z)?;
+		writeln!(w, "Ke {} {} {}", m.Ke.x, m.Ke.y, m.Ke.z)?;
+		if m.Ni != def.Ni {
+			writeln!(w, "Ni {}", m.Ni)?;
+		}
+		writeln!(w, "d {}", m.d)?;
+		writeln!(w, "illum {}", m.illum)?;
+		if m.uv_projection != def.uv_projection {
+			writeln!(w, "uv_projection {}", m.uv_projection)?;
+		}
+
+		let maps = [
+			("map_Ka", &m.map_Ka), ("map_Kd", &m.map_Kd), ("map_Ks", &m.map_Ks), ("map_Ns", &m.map_Ns),
+			("map_d", &m.map_d), ("map_Ke", &m.map_Ke), ("bump", &m.bump), ("disp", &m.disp), ("decal", &m.decal),
+		];
+		for &(command, map) in maps.iter() {
+			if let &Some((ref id, opt)) = map {
+				write_texture(w, command, dir, id, opt)?;
+			}
+		}
+		for &(ref id, opt) in m.refl.iter() {
+			write_texture(w, "refl", dir, id, opt)?;
+		}
+		writeln!(w)?;
+	}
+	Ok(())
+}
+
+fn write_texture<W: Write>(w: &mut W, command: &str, dir: &Path, id: &TextureID, opt: TextureOptions) -> io::Result<()> {
+	let def = TextureOptions::default();
+	let on_off = |b| if b { "on" } else { "off" };
+
+	write!(w, "{}", command)?;
+	if let Some(t) = opt.refl_type {
+		use render::ReflectionType::*;
+		let t = match t {
+			Sphere => "sphere",
+			CubeTop => "cube_top",
+			CubeBottom => "cube_bottom",
+			CubeFront => "cube_front",
+			CubeBack => "cube_back",
+			CubeLeft => "cube_left",
+			CubeRight => "cube_right",
+		};
+		write!(w, " -type {}", t)?;
+	}
+	if opt.blend_u != def.blend_u { write!(w, " -blendu {}", on_off(opt.blend_u))?; }
+	if opt.blend_v != def.blend_v { write!(w, " -blendv {}", on_off(opt.blend_v))?; }
+	if opt.color_correction != def.color_correction { write!(w, " -cc {}", on_off(opt.color_correction))?; }
+	if opt.clamp != def.clamp { write!(w, " -clamp {}", on_off(opt.clamp))?; }
+	if opt.bump_mult != def.bump_mult { write!(w, " -bm {}", opt.bump_mult)?; }
+	if opt.boost != def.boost { write!(w, " -boost {}", opt.boost)?; }
+	if opt.mm != def.mm { write!(w, " -mm {} {}", opt.mm.x, opt.mm.y)?; }
+	if opt.uv_offset != def.uv_offset { write!(w, " -o {} {}", opt.uv_offset.x, opt.uv_offset.y)?; }
+	if opt.uv_scale != def.uv_scale { write!(w, " -s {} {}", opt.uv_scale.x, opt.uv_scale.y)?; }
+	if opt.turbulence != def.turbulence {
+		write!(w, " -t {} {} {}", opt.turbulence.x, opt.turbulence.y, opt.turbulence.z)?;
+	}
+	if let Some(res) = opt.texres {
+		write!(w, " -texres {}", res)?;
+	}
+	if let Some(c) = opt.imfchan {
+		use render::TextureChannel::*;
+		let c = match c { R => "r", G => "g", B => "b", M => "m", L => "l", Z => "z" };
+		write!(w, " -imfchan {}", c)?;
+	}
+
+	// Write the texture path relative to the .mtl file
+	let path = Path::new(id);
+	let path = if path.is_absolute() { path.to_path_buf() } else { util::relative_path(dir, path) };
+	writeln!(w, " {}", path.display())
+}
+
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	use std::io;
+	use std::path::Path;
+
+	use render::parse::{Axis, ObjFile, ParseMode, UvProjection};
+
+	const OBJ: &'static str = "
+mtllib cube.mtl
+o Cube
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0.5 0.5 -1 1 0.5 0.25
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+usemtl stone
+s 1
+f 1/1 2/2 3/3 4/4
+s off
+g tip
+usemtl glass
+f 1 2 5
+f 2 3 5
+";
+
+	const MTL: &'static str = "
+newmtl stone
+Ka 0.25 0.25 0.25
+Kd 0.5 0.5 0.5
+map_Kd -s 2 2 -o 0.5 0 -clamp on ../tex/stone.png
+bump -bm 0.5 -imfchan l stone_bump.png
+newmtl glass
+Kd 0.75 1 1
+d 0.5
+Ni 1.5
+illum 0
+uv_projection cylindrical z
+";
+
+	fn parse(obj: &str, mtl: &str) -> ObjFile {
+		let mtl = mtl.to_string();
+		ObjFile::from_str("res/mesh/cube.obj".into(), obj, &mut |p: &Path| {
+			if p == Path::new("res/mesh/cube.mtl") {
+				Ok(mtl.clone())
+			} else {
+				Err(io::Error::new(io::ErrorKind::NotFound, "not found"))
+			}
+		}, ParseMode::Strict).unwrap()
+	}
+
+	fn write(f: &ObjFile) -> (String, String) {
+		let mut obj = vec![];
+		super::write_obj(&mut obj, f, Some("cube.mtl")).unwrap();
+		let mut mtl = vec![];
+		super::write_mtl(&mut mtl, &f.materials, Path::new("res/mesh/cube.mtl")).unwrap();
+		(String::from_utf8(obj).unwrap(), String::from_utf8(mtl).unwrap())
+	}
+
+	#[test]
+	fn test_round_trip() {
+		let f1 = parse(OBJ, MTL);
+		let (obj1, mtl1) = write(&f1);
+		let f2 = parse(&obj1, &mtl1);
+		let (_, mtl2) = write(&f2);
+
+		assert_eq!(f1.vertices, f2.vertices);
+		assert_eq!(f1.colors, f2.colors);
+		assert_eq!(f1.uvs, f2.uvs);
+		assert_eq!(f1.normals.len(), f2.normals.len());
+		for (a, b) in f1.normals.iter().zip(f2.normals.iter()) {
+			// Normals are renormalized when parsing, so they may differ slightly
+			assert!((a - b).magnitude() < 1e-5);
+		}
+		assert_eq!(f1.smoothing_groups, f2.smoothing_groups);
+		assert_eq!(f1.faces.len(), f2.faces.len());
+		for (a, b) in f1.faces.iter().zip(f2.faces.iter()) {
+			for &(a, b) in [(a.x, b.x), (a.y, b.y), (a.z, b.z)].iter() {
+				assert_eq!((a.vert, a.uv, a.norm), (b.vert, b.uv, b.norm));
+			}
+		}
+		assert_eq!(f1.ranges.len(), f2.ranges.len());
+		for (a, b) in f1.ranges.iter().zip(f2.ranges.iter()) {
+			assert_eq!((&a.object, &a.group, &a.material, a.start, a.len), (&b.object, &b.group, &b.material, b.start, b.len));
+		}
+		let stone = &f2.materials["stone"];
+		let (ref id, opt) = *stone.map_Kd.as_ref().unwrap();
+		assert_eq!(Path::new(id), Path::new("res/tex/stone.png"));
+		assert!(opt.clamp);
+		assert_eq!(stone.bump.as_ref().unwrap().1.bump_mult, 0.5);
+		assert_eq!(f2.materials["glass"].uv_projection, UvProjection::Cylindrical(Axis::Z));
+		assert_eq!(f2.materials["glass"].Ni, 1.5);
+
+		// Writing the materials again should produce exactly the same output
+		assert_eq!(mtl1, mtl2);
+	}
+}
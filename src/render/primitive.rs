@@ -0,0 +1,408 @@
+//! Meshes generated from parameters rather than loaded from files, for greyboxing levels.
+//!
+//! Primitives are loaded through the `MeshBank` like any other mesh, with IDs such as `proc:box?w=2&h=1`. The
+//! parameters that aren't given use their defaults. Every primitive is centred on the origin, with y up.
+use prelude::*;
+
+use std::f32::consts::PI;
+
+use super::{MeshData, Vertex};
+use super::parse::UvProjection;
+
+/// Start of the IDs of generated meshes
+pub const PROC_PREFIX: &'static str = "proc:";
+/// Most triangles that a primitive can have, so that a typo in an ID can't make loading it hang or run out of memory
+pub const MAX_TRIANGLES: usize = 1 << 20;
+/// Most segments, rings etc. of a primitive
+const MAX_COUNT: usize = 1024;
+
+/// A shape that can be generated
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Primitive {
+	/// `proc:box?w=1&h=1&d=1`
+	Box { w: Flt, h: Flt, d: Flt },
+	/// `proc:sphere?r=0.5&segments=32&rings=16`
+	UvSphere { r: Flt, segments: usize, rings: usize },
+	/// `proc:icosphere?r=0.5&subdivisions=2`
+	Icosphere { r: Flt, subdivisions: usize },
+	/// `proc:plane?w=1&d=1&sx=1&sz=1`, facing up, with `sx` by `sz` quads
+	Plane { w: Flt, d: Flt, sx: usize, sz: usize },
+	/// `proc:cylinder?r=0.5&h=1&segments=32`
+	Cylinder { r: Flt, h: Flt, segments: usize },
+	/// `proc:cone?r=0.5&h=1&segments=32`, with the point at the top
+	Cone { r: Flt, h: Flt, segments: usize },
+	/// `proc:capsule?r=0.5&h=2&segments=32&rings=8`, where `h` is the height including the ends
+	Capsule { r: Flt, h: Flt, segments: usize, rings: usize },
+	/// `proc:torus?r=0.5&t=0.125&segments=32&sides=16`, lying flat, where `t` is the radius of the tube
+	Torus { r: Flt, t: Flt, segments: usize, sides: usize },
+}
+impl Primitive {
+	/// Checks if a mesh ID is of a generated mesh
+	pub fn is_primitive_id(id: &str) -> bool {
+		id.starts_with(PROC_PREFIX)
+	}
+
+	/// Parses an ID such as `proc:box?w=2&h=1`
+	pub fn from_id(id: &str) -> GameResult<Primitive> {
+		if !Primitive::is_primitive_id(id) {
+			return Err(format!("Invalid primitive ({}): IDs of primitives start with `{}`", id, PROC_PREFIX));
+		}
+		let rest = &id[PROC_PREFIX.len()..];
+		let (name, query) = match rest.find('?') {
+			Some(i) => (&rest[..i], &rest[i + 1..]),
+			None => (rest, ""),
+		};
+		let mut params = Params { id: id, params: vec![] };
+		for param in query.split('&').filter(|p| !p.is_empty()) {
+			match param.find('=') {
+				Some(i) => params.params.push((&param[..i], &param[i + 1..], false)),
+				None => return Err(format!("Invalid primitive ({}): expected `name=value`, found `{}`", id, param)),
+			}
+		}
+
+		let ret = match name {
+			"box" => Primitive::Box {
+				w: params.size("w", 1.0)?,
+				h: params.size("h", 1.0)?,
+				d: params.size("d", 1.0)?,
+			},
+			"sphere" => Primitive::UvSphere {
+				r: params.size("r", 0.5)?,
+				segments: params.count("segments", 32, 3, MAX_COUNT)?,
+				rings: params.count("rings", 16, 2, MAX_COUNT)?,
+			},
+			"icosphere" => Primitive::Icosphere {
+				r: params.size("r", 0.5)?,
+				subdivisions: params.count("subdivisions", 2, 0, 7)?,
+			},
+			"plane" => Primitive::Plane {
+				w: params.size("w", 1.0)?,
+				d: params.size("d", 1.0)?,
+				sx: params.count("sx", 1, 1, MAX_COUNT)?,
+				sz: params.count("sz", 1, 1, MAX_COUNT)?,
+			},
+			"cylinder" => Primitive::Cylinder {
+				r: params.size("r", 0.5)?,
+				h: params.size("h", 1.0)?,
+				segments: params.count("segments", 32, 3, MAX_COUNT)?,
+			},
+			"cone" => Primitive::Cone {
+				r: params.size("r", 0.5)?,
+				h: params.size("h", 1.0)?,
+				segments: params.count("segments", 32, 3, MAX_COUNT)?,
+			},
+			"capsule" => {
+				let r = params.size("r", 0.5)?;
+				let h = params.size("h", 2.0)?;
+				if h < r * 2.0 {
+					return Err(format!("Invalid primitive ({}): a capsule can't be shorter than its width", id));
+				}
+				Primitive::Capsule {
+					r: r,
+					h: h,
+					segments: params.count("segments", 32, 3, MAX_COUNT)?,
+					rings: params.count("rings", 8, 1, MAX_COUNT)?,
+				}
+			},
+			"torus" => Primitive::Torus {
+				r: params.size("r", 0.5)?,
+				t: params.size("t", 0.125)?,
+				segments: params.count("segments", 32, 3, MAX_COUNT)?,
+				sides: params.count("sides", 16, 3, MAX_COUNT)?,
+			},
+			_ => return Err(format!("Invalid primitive ({}): unknown shape `{}`", id, name)),
+		};
+		params.ensure_used()?;
+		if ret.triangle_count() > MAX_TRIANGLES {
+			return Err(format!("Invalid primitive ({}): it would have more than {} triangles", id, MAX_TRIANGLES));
+		}
+		Ok(ret)
+	}
+
+	/// Roughly how many triangles the mesh of the primitive has
+	pub fn triangle_count(&self) -> usize {
+		match *self {
+			Primitive::Box { .. } => 12,
+			Primitive::UvSphere { segments, rings, .. } => 2 * segments * rings,
+			Primitive::Icosphere { subdivisions, .. } => 20 << (2 * subdivisions),
+			Primitive::Plane { sx, sz, .. } => 2 * sx * sz,
+			Primitive::Cylinder { segments, .. } => 4 * segments,
+			Primitive::Cone { segments, .. } => 2 * segments,
+			Primitive::Capsule { segments, rings, .. } => 2 * segments * (2 * rings + 1),
+			Primitive::Torus { segments, sides, .. } => 2 * segments * sides,
+		}
+	}
+
+	/// Generates the mesh of the primitive, with a single submesh using the default material
+	pub fn generate(&self) -> MeshData {
+		let mut b = Builder { corners: vec![] };
+		match *self {
+			Primitive::Box { w, h, d } => {
+				let half = vec3(w, h, d) * 0.5;
+				// Each face as its normal, and the directions that u & v go in
+				let faces = [
+					(vec3( 1.0, 0.0, 0.0), vec3( 0.0, 0.0, -1.0), vec3(0.0, -1.0, 0.0)),
+					(vec3(-1.0, 0.0, 0.0), vec3( 0.0, 0.0,  1.0), vec3(0.0, -1.0, 0.0)),
+					(vec3( 0.0, 1.0, 0.0), vec3( 1.0, 0.0,  0.0), vec3(0.0,  0.0, 1.0)),
+					(vec3( 0.0,-1.0, 0.0), vec3( 1.0, 0.0,  0.0), vec3(0.0,  0.0, -1.0)),
+					(vec3( 0.0, 0.0, 1.0), vec3( 1.0, 0.0,  0.0), vec3(0.0, -1.0, 0.0)),
+					(vec3( 0.0, 0.0,-1.0), vec3(-1.0, 0.0,  0.0), vec3(0.0, -1.0, 0.0)),
+				];
+				for &(n, du, dv) in faces.iter() {
+					let (n, du, dv) = (n.mul_element_wise(half), du.mul_element_wise(half) * 2.0, dv.mul_element_wise(half) * 2.0);
+					let normal = n.normalize();
+					b.surface(1, &steps(1), |u, v| (n + du * (u - 0.5) + dv * (v - 0.5), normal));
+				}
+			},
+			Primitive::UvSphere { r, segments, rings } => {
+				b.surface(segments, &steps(rings), |u, v| {
+					let n = around(u, v * PI);
+					(n * r, n)
+				});
+			},
+			Primitive::Icosphere { r, subdivisions } => {
+				for tri in icosphere(subdivisions).iter() {
+					let ps = [tri[0] * r, tri[1] * r, tri[2] * r];
+					let uvs = UvProjection::Spherical.project(ps, vec3(-r, -r, -r), vec3(r, r, r));
+					b.tri([(ps[0], tri[0], uvs[0]), (ps[1], tri[1], uvs[1]), (ps[2], tri[2], uvs[2])]);
+				}
+			},
+			Primitive::Plane { w, d, sx, sz } => {
+				b.surface(sx, &steps(sz), |u, v| (vec3((u - 0.5) * w, 0.0, (v - 0.5) * d), vec3(0.0, 1.0, 0.0)));
+			},
+			Primitive::Cylinder { r, h, segments } => {
+				b.surface(segments, &steps(1), |u, v| {
+					let n = around(u, PI / 2.0);
+					(n * r + vec3(0.0, h * (0.5 - v), 0.0), n)
+				});
+				b.disk(segments, r, h / 2.0, true);
+				b.disk(segments, r, -h / 2.0, false);
+			},
+			Primitive::Cone { r, h, segments } => {
+				// The slope of the side tilts the normals upwards
+				let len = (r * r + h * h).sqrt();
+				b.surface(segments, &steps(1), |u, v| {
+					let out = around(u, PI / 2.0);
+					(out * (r * v) + vec3(0.0, h * (0.5 - v), 0.0), out * (h / len) + vec3(0.0, r / len, 0.0))
+				});
+				b.disk(segments, r, -h / 2.0, false);
+			},
+			Primitive::Capsule { r, h, segments, rings } => {
+				// v is spread along the outline of the capsule, so that the texture isn't stretched
+				let straight = h - r * 2.0;
+				let end = (PI * r / 2.0) / (PI * r + straight);
+				let mut vs: Vec<Flt> = steps(rings).iter().map(|&v| v * end).collect();
+				vs.extend(steps(rings).iter().map(|&v| 1.0 - end + v * end));
+				b.surface(segments, &vs, |u, v| {
+					if v <= end {
+						let n = around(u, v / end * PI / 2.0);
+						(n * r + vec3(0.0, straight / 2.0, 0.0), n)
+					} else {
+						let n = around(u, PI / 2.0 + (v - (1.0 - end)) / end * PI / 2.0);
+						(n * r - vec3(0.0, straight / 2.0, 0.0), n)
+					}
+				});
+			},
+			Primitive::Torus { r, t, segments, sides } => {
+				b.surface(segments, &steps(sides), |u, v| {
+					let out = around(u, PI / 2.0);
+					let angle = v * PI * 2.0;
+					let n = out * angle.cos() + vec3(0.0, angle.sin(), 0.0);
+					(out * r + n * t, n)
+				});
+			},
+		}
+		MeshData::from_corners(b.corners, None)
+	}
+}
+
+/// The parameters of a primitive's ID, and whether they have been used
+struct Params<'a> {
+	id: &'a str,
+	params: Vec<(&'a str, &'a str, bool)>,
+}
+impl<'a> Params<'a> {
+	fn get(&mut self, name: &str) -> Option<&'a str> {
+		let p = self.params.iter_mut().find(|p| p.0 == name);
+		p.map(|p| {
+			p.2 = true;
+			p.1
+		})
+	}
+
+	/// A length, which must be more than 0
+	fn size(&mut self, name: &str, default: Flt) -> GameResult<Flt> {
+		let id = self.id;
+		match self.get(name) {
+			Some(s) => match s.parse::<Flt>() {
+				Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+				_ => Err(format!("Invalid primitive ({}): `{}` must be a number above 0, found `{}`", id, name, s)),
+			},
+			None => Ok(default),
+		}
+	}
+
+	/// A number of segments, which must be from `min` to `max`
+	fn count(&mut self, name: &str, default: usize, min: usize, max: usize) -> GameResult<usize> {
+		let id = self.id;
+		match self.get(name) {
+			Some(s) => match s.parse::<usize>() {
+				Ok(x) if x >= min && x <= max => Ok(x),
+				_ => Err(format!("Invalid primitive ({}): `{}` must be a whole number from {} to {}, found `{}`", id, name, min, max, s)),
+			},
+			None => Ok(default),
+		}
+	}
+
+	fn ensure_used(&self) -> GameResult<()> {
+		match self.params.iter().find(|p| !p.2) {
+			Some(p) => Err(format!("Invalid primitive ({}): unknown parameter `{}`", self.id, p.0)),
+			None => Ok(()),
+		}
+	}
+}
+
+/// Splits 0 to 1 into `n` steps, including both ends
+fn steps(n: usize) -> Vec<Flt> {
+	(0..n + 1).map(|i| i as Flt / n as Flt).collect()
+}
+
+/// Unit vector at `u` of the way around the y axis, `angle` down from the top
+fn around(u: Flt, angle: Flt) -> Vec3 {
+	let phi = u * PI * 2.0;
+	vec3(angle.sin() * phi.cos(), angle.cos(), angle.sin() * phi.sin())
+}
+
+/// Triangles of a unit sphere made by subdividing an icosahedron
+fn icosphere(subdivisions: usize) -> Vec<[Vec3; 3]> {
+	let g = (1.0 + (5.0 as Flt).sqrt()) / 2.0;
+	let ps: Vec<Vec3> = [
+		(-1.0, g, 0.0), (1.0, g, 0.0), (-1.0, -g, 0.0), (1.0, -g, 0.0),
+		(0.0, -1.0, g), (0.0, 1.0, g), (0.0, -1.0, -g), (0.0, 1.0, -g),
+		(g, 0.0, -1.0), (g, 0.0, 1.0), (-g, 0.0, -1.0), (-g, 0.0, 1.0),
+	].iter().map(|&(x, y, z)| vec3(x, y, z).normalize()).collect();
+	let faces = [
+		[0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+		[1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+		[3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+		[4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+	];
+	let mut tris: Vec<[Vec3; 3]> = faces.iter().map(|f| [ps[f[0]], ps[f[1]], ps[f[2]]]).collect();
+	for _ in 0..subdivisions {
+		let mut next = Vec::with_capacity(tris.len() * 4);
+		for t in tris.iter() {
+			let mid = |a: Vec3, b: Vec3| ((a + b) * 0.5).normalize();
+			let (a, b, c) = (mid(t[0], t[1]), mid(t[1], t[2]), mid(t[2], t[0]));
+			next.push([t[0], a, c]);
+			next.push([t[1], b, a]);
+			next.push([t[2], c, b]);
+			next.push([a, b, c]);
+		}
+		tris = next;
+	}
+	tris
+}
+
+/// Builds the corners of a primitive's triangles
+struct Builder {
+	corners: Vec<Vertex>,
+}
+impl Builder {
+	/// Adds a triangle of (position, normal, uv)s. Triangles are turned to face the way their normals do, and
+	/// triangles without any area (such as at the poles of a sphere) are skipped.
+	fn tri(&mut self, mut t: [(Vec3, Vec3, Vec2); 3]) {
+		let n = (t[1].0 - t[0].0).cross(t[2].0 - t[0].0);
+		if n.magnitude2() <= 1e-12 {
+			return;
+		}
+		if n.dot(t[0].1 + t[1].1 + t[2].1) < 0.0 {
+			t.swap(1, 2);
+		}
+		for &(p, n, uv) in t.iter() {
+			self.corners.push(Vertex::new(array3(p), array2(uv), array3(n), [1.0; 3], [0.0; 4]));
+		}
+	}
+
+	/// Adds a surface made of a grid of quads. `f` gives the position & normal at a uv, where u goes from 0 to 1 in
+	/// `segments` steps, and v goes through `vs`.
+	fn surface<F>(&mut self, segments: usize, vs: &[Flt], f: F)
+			where F: Fn(Flt, Flt) -> (Vec3, Vec3) {
+		let us = steps(segments);
+		let point = |u: Flt, v: Flt| {
+			let (p, n) = f(u, v);
+			(p, n, vec2(u, v))
+		};
+		for v in vs.windows(2) {
+			for u in us.windows(2) {
+				let (a, b, c, d) = (point(u[0], v[0]), point(u[1], v[0]), point(u[1], v[1]), point(u[0], v[1]));
+				self.tri([a, b, c]);
+				self.tri([a, c, d]);
+			}
+		}
+	}
+
+	/// Adds a flat disk facing straight up or down, at height `y`
+	fn disk(&mut self, segments: usize, r: Flt, y: Flt, up: bool) {
+		let n = vec3(0.0, if up { 1.0 } else { -1.0 }, 0.0);
+		let centre = (vec3(0.0, y, 0.0), n, vec2(0.5, 0.5));
+		let us = steps(segments);
+		for u in us.windows(2) {
+			let edge = |u: Flt| {
+				let dir = around(u, PI / 2.0);
+				(dir * r + vec3(0.0, y, 0.0), n, vec2(0.5 + dir.x * 0.5, 0.5 + dir.z * 0.5))
+			};
+			self.tri([centre, edge(u[0]), edge(u[1])]);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	use super::Primitive;
+
+	#[test]
+	fn test_from_id() {
+		assert_eq!(Primitive::from_id("proc:box?w=2&h=1").unwrap(), Primitive::Box { w: 2.0, h: 1.0, d: 1.0 });
+		assert_eq!(Primitive::from_id("proc:icosphere").unwrap(), Primitive::Icosphere { r: 0.5, subdivisions: 2 });
+		assert!(Primitive::from_id("proc:icosphere?subdivisions=7").is_ok());
+		for id in ["proc:box?w=0", "proc:box?x=1", "proc:box?w", "proc:sphere?segments=2", "proc:pyramid",
+				"proc:capsule?r=1&h=1", "res/mesh/box.obj", "proc:icosphere?subdivisions=20",
+				"proc:sphere?segments=1024&rings=1024", "proc:plane?sx=1024&sz=1024"].iter() {
+			assert!(Primitive::from_id(id).is_err(), "{}", id);
+		}
+	}
+
+	#[test]
+	fn test_generate() {
+		let ids = ["proc:box?w=2&h=1&d=3", "proc:sphere", "proc:icosphere?subdivisions=1", "proc:plane?sx=2&sz=3",
+			"proc:cylinder", "proc:cone", "proc:capsule", "proc:torus"];
+		for id in ids.iter() {
+			let data = Primitive::from_id(id).unwrap().generate();
+			assert!(data.indices.len() > 0 && data.indices.len() % 3 == 0, "{}", id);
+			assert_eq!(data.submeshes[0].range, 0..data.indices.len());
+			for t in data.indices.chunks(3) {
+				let v = |i: u32| &data.vertices[i as usize];
+				let p = |i: u32| Vec3::from(v(i).pos());
+				// Triangles face the same way as their normals, and the normals are unit length
+				let n = (p(t[1]) - p(t[0])).cross(p(t[2]) - p(t[0]));
+				assert!(n.dot(Vec3::from(v(t[0]).normal())) > 0.0, "{}: {:?}", id, t);
+				assert!((Vec3::from(v(t[0]).normal()).magnitude() - 1.0).abs() < 1e-4, "{}", id);
+				// u can go past 1 where triangles wrap around the seam of a sphere
+				for &i in t.iter() {
+					let uv = v(i).uv();
+					assert!(uv[0] >= 0.0 && uv[0] < 2.0 && uv[1] >= 0.0 && uv[1] <= 1.0 + 1e-4, "{}: {:?}", id, uv);
+				}
+			}
+		}
+
+		// The box has 24 vertices, as the corners are split between faces
+		let data = Primitive::from_id("proc:box?w=2&h=1&d=3").unwrap().generate();
+		assert_eq!((data.vertices.len(), data.indices.len()), (24, 36));
+		for v in data.vertices.iter() {
+			let p = v.pos();
+			assert_eq!([p[0].abs(), p[1].abs(), p[2].abs()], [1.0, 0.5, 1.5]);
+		}
+	}
+}
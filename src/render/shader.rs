@@ -0,0 +1,59 @@
+use prelude::*;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use glium::Program;
+use glium::backend::Context;
+
+use vfs;
+use super::parse;
+
+/// A shader program, which remembers the files it was built from so that it can be reloaded when they change
+pub struct Shader {
+	program: Program,
+	/// Path of the shaders without an extension, relative to the executable, e.g. "res/shader/phong"
+	rel_base: String,
+	defines: Vec<(String, String)>,
+	/// Every file that the program was built from, including `#include`d files, and when they were modified
+	sources: Vec<(PathBuf, Option<SystemTime>)>,
+}
+impl Shader {
+	/// Loads the program at `rel_base`, with `#define`s added to the start of each shader
+	pub fn new(ctx: &Rc<Context>, rel_base: &str, defines: &[(&str, &str)]) -> GameResult<Shader> {
+		let (program, files) = parse::load_shader_program(ctx, rel_base, defines)?;
+		Ok(Shader {
+			program: program,
+			rel_base: rel_base.to_string(),
+			defines: defines.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect(),
+			sources: files.into_iter().map(|p| { let t = modified(&p); (p, t) }).collect(),
+		})
+	}
+
+	pub fn program(&self) -> &Program {
+		&self.program
+	}
+
+	pub fn rel_base(&self) -> &str {
+		&self.rel_base
+	}
+
+	/// Checks if any of the files that the program was built from have changed since it was loaded
+	pub fn is_outdated(&self) -> bool {
+		self.sources.iter().any(|&(ref p, t)| modified(p) != t)
+	}
+
+	/// Reloads the program. If that fails, the old program is kept.
+	pub fn reload(&mut self, ctx: &Rc<Context>) -> GameResult<()> {
+		let defines: Vec<(&str, &str)> = self.defines.iter().map(|&(ref k, ref v)| (&k[..], &v[..])).collect();
+		*self = Shader::new(ctx, &self.rel_base, &defines)?;
+		Ok(())
+	}
+}
+
+/// When a file relative to the executable was last modified
+fn modified(rel_path: &Path) -> Option<SystemTime> {
+	fs::metadata(vfs::canonicalize_exe(rel_path)).and_then(|m| m.modified()).ok()
+}
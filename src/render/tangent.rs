@@ -0,0 +1,128 @@
+use prelude::*;
+
+use std::collections::HashMap;
+
+/// Generates tangents for a list of triangles, in a way that is compatible with MikkTSpace.
+///
+/// `positions`, `normals` & `uvs` hold the 3 corners of each triangle in turn. Returns the tangent of each
+/// corner, with the handedness of the bitangent in `w`, so that `bitangent = cross(normal, tangent.xyz) * tangent.w`.
+///
+/// As with MikkTSpace, the tangent of each face is weighted by the angle of the face at the corner and
+/// orthogonalized against the corner's normal. Corners that share a position, normal, uv & handedness get
+/// the same tangent, so that they can still be merged into one vertex.
+pub fn generate_tangents(positions: &[Vec3], normals: &[Vec3], uvs: &[Vec2]) -> Vec<Vec4> {
+	let n = positions.len() / 3 * 3;
+	// Tangent & handedness of each corner, before they are averaged
+	let mut corners: Vec<(Vec3, bool)> = Vec::with_capacity(n);
+	for t in 0..n / 3 {
+		let i = t * 3;
+		let p = [positions[i], positions[i + 1], positions[i + 2]];
+		// Uvs are flipped back so that v points up, as that is the space normal maps are made in
+		let uv = [uv_up(uvs[i]), uv_up(uvs[i + 1]), uv_up(uvs[i + 2])];
+		let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+		let (d1, d2) = (uv[1] - uv[0], uv[2] - uv[0]);
+		let r = d1.x * d2.y - d2.x * d1.y;
+		let (tangent, bitangent) = if r.abs() > 1e-12 {
+			((e1 * d2.y - e2 * d1.y) / r, (e2 * d1.x - e1 * d2.x) / r)
+		} else {
+			(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0)) // Degenerate uvs don't contribute
+		};
+
+		for c in 0..3 {
+			let normal = normals[i + c];
+			let a = p[(c + 1) % 3] - p[c];
+			let b = p[(c + 2) % 3] - p[c];
+			let angle = if a.magnitude2() == 0.0 || b.magnitude2() == 0.0 {
+				0.0
+			} else {
+				(a.dot(b) / (a.magnitude() * b.magnitude())).max(-1.0).min(1.0).acos()
+			};
+			let t = orthogonalize(tangent, normal);
+			let right_handed = normal.cross(tangent).dot(bitangent) >= 0.0;
+			corners.push((t * angle, right_handed));
+		}
+	}
+
+	// Sum the tangents of the corners that will become the same vertex
+	let mut sums: HashMap<([u32; 3], [u32; 3], [u32; 2], bool), Vec3> = HashMap::new();
+	let key = |i: usize, right_handed: bool| {
+		let (p, n, uv) = (positions[i], normals[i], uvs[i]);
+		([p.x.to_bits(), p.y.to_bits(), p.z.to_bits()], [n.x.to_bits(), n.y.to_bits(), n.z.to_bits()],
+			[uv.x.to_bits(), uv.y.to_bits()], right_handed)
+	};
+	for (i, &(t, right_handed)) in corners.iter().enumerate() {
+		*sums.entry(key(i, right_handed)).or_insert(zero()) += t;
+	}
+
+	corners.iter().enumerate().map(|(i, &(_, right_handed))| {
+		let normal = normals[i];
+		let mut t = orthogonalize(sums[&key(i, right_handed)], normal);
+		if t.magnitude2() == 0.0 {
+			t = any_perpendicular(normal);
+		}
+		t.extend(if right_handed { 1.0 } else { -1.0 })
+	}).collect()
+}
+
+fn uv_up(uv: Vec2) -> Vec2 {
+	vec2(uv.x, 1.0 - uv.y)
+}
+
+/// Makes `t` perpendicular to `n`, and normalizes it. Returns zero if that isn't possible.
+fn orthogonalize(t: Vec3, n: Vec3) -> Vec3 {
+	let t = t - n * n.dot(t);
+	if t.magnitude2() > 1e-20 {
+		t.normalize()
+	} else {
+		vec3(0.0, 0.0, 0.0)
+	}
+}
+
+/// Gets a unit vector that is perpendicular to `n`
+fn any_perpendicular(n: Vec3) -> Vec3 {
+	let axis = if n.x.abs() < 0.9 { vec3(1.0, 0.0, 0.0) } else { vec3(0.0, 1.0, 0.0) };
+	let t = orthogonalize(axis, n);
+	if t.magnitude2() == 0.0 { axis } else { t }
+}
+
+#[cfg(test)]
+mod test {
+	use prelude::*;
+
+	/// Two triangles making up a unit quad in the xy plane. `u_sign` mirrors the uvs horizontally.
+	fn quad(u_sign: Flt) -> (Vec<Vec3>, Vec<Vec3>, Vec<Vec2>) {
+		let ps = [vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(1.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)];
+		let corners = [0, 1, 2, 0, 2, 3];
+		let positions: Vec<Vec3> = corners.iter().map(|&i| ps[i]).collect();
+		let normals = vec![vec3(0.0, 0.0, 1.0); 6];
+		// v is flipped, in the same way as when .obj files are parsed
+		let uvs = positions.iter().map(|p| vec2(p.x * u_sign, 1.0 - p.y)).collect();
+		(positions, normals, uvs)
+	}
+
+	#[test]
+	fn test_generate_tangents() {
+		let (ps, ns, uvs) = quad(1.0);
+		let ts = super::generate_tangents(&ps, &ns, &uvs);
+		for t in ts.iter() {
+			assert!((t - vec4(1.0, 0.0, 0.0, 1.0)).magnitude() < 1e-5);
+		}
+
+		// Mirrored uvs flip the tangent & the handedness
+		let (ps, ns, uvs) = quad(-1.0);
+		let ts = super::generate_tangents(&ps, &ns, &uvs);
+		for t in ts.iter() {
+			assert!((t - vec4(-1.0, 0.0, 0.0, -1.0)).magnitude() < 1e-5);
+		}
+	}
+
+	#[test]
+	fn test_shared_corners() {
+		// Corners with the same attributes get exactly the same tangent, even though their faces differ
+		let (mut ps, ns, uvs) = quad(1.0);
+		ps[5].z = 0.5;
+		let ts = super::generate_tangents(&ps, &ns, &uvs);
+		assert_eq!(ts[0], ts[3]);
+		assert_eq!(ts[2], ts[4]);
+	}
+}
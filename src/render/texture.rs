@@ -2,8 +2,11 @@ use prelude::*;
 
 use std::borrow::Cow;
 use std::fs::File;
+use std::io::Read;
 use std::rc::Rc;
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use glium::backend::Context;
 use glium::texture::{ClientFormat, RawImage2d, Texture2d};
@@ -11,28 +14,105 @@ use glium::texture::{ClientFormat, RawImage2d, Texture2d};
 use game::duration_to_millis;
 use vfs;
 use png::{self, Parameter};
+use super::asset_cache::{AssetCache, CacheStats, RetryPolicy};
+use super::loader::{Loader, LoadProgress};
 use super::normalize_id;
 
 pub type TextureID = String;
 
 pub const TEX_DIR: &'static str = "res/tex/";
+/// Default GPU memory budget of the textures in a `TextureBank`, in bytes
+pub const DEFAULT_TEXTURE_BUDGET: usize = 512 * 1024 * 1024;
 
+/// Channel of a texture used to create a scalar or bump texture (`-imfchan`)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureChannel {
+	R,
+	G,
+	B,
+	/// Matte channel
+	M,
+	/// Luminance channel
+	L,
+	/// Z-depth channel
+	Z,
+}
+
+/// Type of a reflection map (`-type`)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReflectionType {
+	Sphere,
+	CubeTop,
+	CubeBottom,
+	CubeFront,
+	CubeBack,
+	CubeLeft,
+	CubeRight,
+}
+
+/// Options for a texture map in an .mtl file
 #[derive(Debug, Copy, Clone)]
 pub struct TextureOptions {
+	/// UV scale (`-s`)
 	pub uv_scale: Vec2,
+	/// UV offset (`-o`)
+	pub uv_offset: Vec2,
+	/// Turbulence (`-t`) (TODO)
+	pub turbulence: Vec3,
+	/// If true, UVs are clamped to [0, 1] rather than repeating the texture (`-clamp`)
+	pub clamp: bool,
+	/// Horizontal texture blending (`-blendu`) (TODO)
+	pub blend_u: bool,
+	/// Vertical texture blending (`-blendv`) (TODO)
+	pub blend_v: bool,
+	/// Color correction (`-cc`) (TODO)
+	pub color_correction: bool,
+	/// Bump multiplier (`-bm`)
+	pub bump_mult: Flt,
+	/// Mip-map sharpness boost (`-boost`) (TODO)
+	pub boost: Flt,
+	/// Base value & gain of the texture values (`-mm`) (TODO)
+	pub mm: Vec2,
+	/// Channel used for scalar & bump textures (`-imfchan`) (TODO)
+	pub imfchan: Option<TextureChannel>,
+	/// Resolution of the texture to create (`-texres`) (TODO)
+	pub texres: Option<u32>,
+	/// Type of reflection map (`-type`). Only used for `refl` maps.
+	pub refl_type: Option<ReflectionType>,
 }
 impl Default for TextureOptions {
 	fn default() -> TextureOptions {
 		TextureOptions {
 			uv_scale: vec2(1.0, 1.0),
+			uv_offset: vec2(0.0, 0.0),
+			turbulence: vec3(0.0, 0.0, 0.0),
+			clamp: false,
+			blend_u: true,
+			blend_v: true,
+			color_correction: false,
+			bump_mult: 1.0,
+			boost: 0.0,
+			mm: vec2(0.0, 1.0),
+			imfchan: None,
+			texres: None,
+			refl_type: None,
 		}
 	}
 }
 
 pub struct TextureBank {
 	ctx: Rc<Context>,
-	cache: HashMap<TextureID, GameResult<Rc<Texture2d>>>,
+	cache: AssetCache<Texture2d>,
+	/// Loads a texture from the embedded .png file that is given, or from a file if there isn't one
+	loader: Loader<Option<Arc<Vec<u8>>>, RawImage2d<'static, u8>>,
+	/// Textures being loaded in the background, and the ticket of the latest request for each
+	pending: HashMap<TextureID, u64>,
+	/// .png files embedded in other files (e.g. glTF meshes), which textures with these IDs are loaded from
+	embedded: HashMap<TextureID, Arc<Vec<u8>>>,
+	progress: LoadProgress,
 	default_texture: Rc<Texture2d>,
+	/// Used in place of textures that failed to load
+	error_texture: Rc<Texture2d>,
 }
 
 impl TextureBank {
@@ -44,19 +124,43 @@ impl TextureBank {
 			height: 1,
 			format: ClientFormat::F32F32F32F32,
 		}).map_err(|e| format!("Unable to create TexureBank: Unable to create default texture: {}", e))?;
+		let et = Texture2d::new(&ctx, error_image())
+			.map_err(|e| format!("Unable to create TexureBank: Unable to create error texture: {}", e))?;
 		
 		let mut tb = TextureBank {
 			ctx: ctx,
-			cache: HashMap::new(),
+			cache: AssetCache::new(DEFAULT_TEXTURE_BUDGET),
+			loader: Loader::new("texture", |id, png| match png {
+				Some(png) => decode_png(&id.to_string(), &png[..]),
+				None => load_png(&id.to_string()),
+			}),
+			pending: HashMap::new(),
+			embedded: HashMap::new(),
+			progress: LoadProgress::default(),
 			default_texture: Rc::new(dt),
+			error_texture: Rc::new(et),
 		};
-		tb.load_textures();
+		tb.request_textures();
 		Ok(tb)
 	}
 	
-	/// Clears the texture cache
+	/// Clears the texture cache. Textures that are being loaded are forgotten about.
 	pub fn clear_cache(&mut self) {
 		self.cache.clear();
+		self.pending.clear();
+		self.progress = LoadProgress::default();
+	}
+	
+	/// Loads every texture that has been loaded before again, along with any new ones in the TEX_DIR directory.
+	/// 
+	/// The old textures are used until the new ones have loaded.
+	pub fn reload(&mut self) {
+		self.pending.clear();
+		self.progress = LoadProgress::default();
+		for id in self.cache.ids() {
+			self.request(id);
+		}
+		self.request_textures();
 	}
 	
 	/// Returns the default texture (one opaque white pixel)
@@ -64,51 +168,138 @@ impl TextureBank {
 		self.default_texture.clone()
 	}
 	
+	/// Returns the error texture (a magenta & black checkerboard)
+	pub fn error_texture(&self) -> Rc<Texture2d> {
+		self.error_texture.clone()
+	}
+	
+	/// How many of the textures that have been requested have loaded
+	pub fn progress(&self) -> LoadProgress {
+		self.progress
+	}
+	
+	/// Sets how much GPU memory the textures may take up, in bytes. Textures that haven't been used in the last
+	/// few frames are dropped, least recently used first, to keep within it.
+	pub fn set_budget(&mut self, bytes: usize) {
+		self.cache.set_budget(bytes);
+	}
+	
+	/// Sets how textures that failed to load are retried
+	pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+		self.cache.set_retry_policy(retry);
+	}
+	
+	/// Gets the hits, misses, evictions & memory use of the texture cache
+	pub fn stats(&self) -> CacheStats {
+		self.cache.stats()
+	}
+	
 	/// Load the texture from a file, or an error texture if that doesn't work.
 	/// 
-	/// The error texture is a pink and black checkerboard (TODO: For now it is the same as default_texture).
+	/// The error texture is a magenta and black checkerboard. While the texture is loading, the default texture is
+	/// used, the same as `get_texture_or_default`.
 	pub fn get_texture_or_error(&mut self, id: TextureID) -> Rc<Texture2d> {
-		self.get_texture_or_default(id)
+		let id = normalize_id(id);
+		match self.cache.get(&id) {
+			Some(Ok(tex)) => return tex,
+			Some(Err(_)) => return self.error_texture(),
+			None => {},
+		}
+		self.request(id);
+		self.default_texture()
 	}
 	
 	/// Load the texture from a file, or the default if that doesn't work
 	/// 
-	/// The default texture is a white pixel. 
+	/// The default texture is a white pixel, which is also used while the texture is loading. Textures that haven't
+	/// been requested yet (or were evicted, or are due to be retried) are loaded in the background.
 	pub fn get_texture_or_default(&mut self, id: TextureID) -> Rc<Texture2d> {
-		self.get_texture(id.clone())
-			.unwrap_or(self.default_texture())
+		let id = normalize_id(id);
+		match self.cache.get(&id) {
+			Some(Ok(tex)) => return tex,
+			Some(Err(_)) => return self.default_texture(),
+			None => {},
+		}
+		self.request(id);
+		self.default_texture()
 	}
 	
-	/// Gets a teture from the TextureBank
+	/// Gets a teture from the TextureBank. If it hasn't been loaded yet, this waits for it to load.
 	pub fn get_texture(&mut self, id: TextureID) -> GameResult<Rc<Texture2d>> {
 		// Normalize id first
 		let id = normalize_id(id);
 		// If cache doesn't exist, loads it from a file.
-		if self.cache.get(&id).is_none() {
-			use std::time::Instant;
-			let t_start = Instant::now();
-			let res = match tex_from_file(&self.ctx, &id).map(|t| Rc::new(t)) {
-				Ok(t) => {
-					info!("Loaded texture: {} ({}ms)", &id, duration_to_millis(t_start.elapsed()));
-					Ok(t)
-				},
-				Err(e) => {
-					warn!("Could not load texture ({}): {}", &id, &e);
-					Err(e)
-				}
-			};
-			self.cache.insert(id.clone(), res);
+		if let Some(res) = self.cache.get(&id) {
+			return res;
 		}
-		self.cache.get(&id).unwrap().clone()
+		let t_start = Instant::now();
+		let res = match self.embedded.get(&id) {
+			Some(png) => decode_png(&id, &png[..]),
+			None => load_png(&id),
+		}.and_then(|raw| upload_png(&self.ctx, &id, raw)).map(|t| Rc::new(t));
+		self.insert(id, res.clone(), t_start.elapsed());
+		res
 	}
 	
-	/// Loads a texture into the TextureBank
+	/// Loads a texture into the TextureBank, waiting for it to load
 	pub fn load_texture(&mut self, id: TextureID) -> GameResult<()> {
 		self.get_texture(id).map(|_| ())
 	}
 	
-	/// Loads all of the textures in the TEX_DIR directory
-	pub fn load_textures(&mut self) {
+	/// Starts loading a texture in the background, if it isn't already being loaded.
+	/// 
+	/// Textures that are already loaded are loaded again.
+	pub fn request(&mut self, id: TextureID) {
+		let id = normalize_id(id);
+		if self.pending.contains_key(&id) {
+			return;
+		}
+		let ticket = self.loader.request(id.clone(), self.embedded.get(&id).cloned());
+		self.pending.insert(id, ticket);
+		self.progress.requested += 1;
+	}
+	
+	/// Adds a .png file that is embedded in another file, and starts loading it in the background as the texture `id`.
+	/// 
+	/// The texture is loaded from the same data whenever it is loaded again, e.g. after being evicted.
+	pub fn add_png(&mut self, id: TextureID, png: Vec<u8>) {
+		let id = normalize_id(id);
+		self.embedded.insert(id.clone(), Arc::new(png));
+		// Replace the texture even if it is loading already, as the data may have changed
+		self.pending.remove(&id);
+		self.request(id);
+	}
+	
+	/// Uploads the textures that have finished loading in the background to OpenGL
+	pub fn upload_loaded(&mut self) {
+		self.cache.next_frame();
+		for (id, ticket, res, elapsed) in self.loader.finished() {
+			// Skip textures that have been requested again since, or forgotten about
+			if self.pending.get(&id) != Some(&ticket) {
+				continue;
+			}
+			self.pending.remove(&id);
+			self.progress.loaded += 1;
+			let t_start = Instant::now();
+			let res = res.and_then(|raw| upload_png(&self.ctx, &id, raw)).map(|t| Rc::new(t));
+			self.insert(id, res, elapsed + t_start.elapsed());
+		}
+		// Textures that have stopped being drawn may need to be dropped
+		self.cache.evict();
+	}
+	
+	/// Adds a texture that has loaded (or failed to) to the cache
+	fn insert(&mut self, id: TextureID, res: GameResult<Rc<Texture2d>>, elapsed: Duration) {
+		match res {
+			Ok(_) => info!("Loaded texture: {} ({}ms)", &id, duration_to_millis(elapsed)),
+			Err(ref e) => warn!("Could not load texture ({}): {}", &id, e),
+		}
+		let size = res.as_ref().map(|tex| texture_size(tex)).unwrap_or(0);
+		self.cache.insert(id, res, size);
+	}
+	
+	/// Starts loading all of the textures in the TEX_DIR directory in the background
+	pub fn request_textures(&mut self) {
 		use std::fs;
 		use vfs;
 		
@@ -130,7 +321,7 @@ impl TextureBank {
 					if !id.ends_with(".png") {
 						continue;
 					}
-					self.load_texture(id.clone()).ok();
+					self.request(id);
 				},
 				_ => {} // Ignore files that return an error when iterating over them
 			}
@@ -139,29 +330,65 @@ impl TextureBank {
 
 }
 
-fn tex_from_file(ctx: &Rc<Context>, id: &TextureID) -> GameResult<Texture2d> {
-	let path = vfs::canonicalize_exe(id);
+/// Generates the image of the error texture: an 8x8 magenta & black checkerboard, with one pixel per square
+fn error_image() -> RawImage2d<'static, u8> {
+	const SIZE: u32 = 8;
+	let mut data = Vec::with_capacity((SIZE * SIZE * 3) as usize);
+	for y in 0..SIZE {
+		for x in 0..SIZE {
+			let c = if (x + y) % 2 == 0 { [255, 0, 255] } else { [0, 0, 0] };
+			data.extend_from_slice(&c);
+		}
+	}
+	RawImage2d {
+		data: data.into(),
+		width: SIZE,
+		height: SIZE,
+		format: ClientFormat::U8U8U8,
+	}
+}
+
+/// Estimates how much GPU memory a texture takes up, in bytes. Drivers usually pad RGB textures to 4 bytes per pixel.
+fn texture_size(tex: &Texture2d) -> usize {
+	let (w, h) = (tex.get_width() as usize, tex.get_height().unwrap_or(1) as usize);
+	(0..tex.get_mipmap_levels()).map(|level| (w >> level).max(1) * (h >> level).max(1) * 4).sum()
+}
+
+/// Uploads a decoded .png file to the GPU
+fn upload_png(ctx: &Rc<Context>, id: &TextureID, raw: RawImage2d<'static, u8>) -> GameResult<Texture2d> {
+	Texture2d::new(ctx, raw)
+		.map_err(|e| format!("Invalid png file ({}): {}", e, vfs::canonicalize_exe(id).display()))
+}
+
+/// Reads & decodes a .png file, without uploading it to the GPU
+pub fn load_png(id: &TextureID) -> GameResult<RawImage2d<'static, u8>> {
+	let path = vfs::canonicalize_exe(normalize_id(id.clone()));
 	let f = File::open(&path)
 		.map_err(|e| format!("Invalid png file ({}): {}", e, path.display()))?;
-	
-	let mut decoder = png::Decoder::new(f);
+	read_png(f, &path.display().to_string())
+}
+
+/// Decodes a .png file that is embedded in another file, without uploading it to the GPU
+pub fn decode_png(id: &TextureID, png: &[u8]) -> GameResult<RawImage2d<'static, u8>> {
+	read_png(png, id)
+}
+
+/// Decodes a .png file. `name` is used for error messages.
+fn read_png<R: Read>(r: R, name: &str) -> GameResult<RawImage2d<'static, u8>> {
+	let mut decoder = png::Decoder::new(r);
 	// Alpha stripped due to png crate limitations
 	(png::TRANSFORM_EXPAND | png::TRANSFORM_STRIP_ALPHA).set_param(&mut decoder);
 	let (info, mut reader) = decoder.read_info()
-		.map_err(|e| format!("Invalid png file ({}): {}", e, path.display()))?;
+		.map_err(|e| format!("Invalid png file ({}): {}", e, name))?;
 	
 	let mut buf = vec![0; info.buffer_size()];
 	reader.next_frame(&mut buf)
-		.map_err(|e| format!("Invalid png file ({}): {}", e, path.display()))?;
+		.map_err(|e| format!("Invalid png file ({}): {}", e, name))?;
 	
-	let raw = RawImage2d {
+	Ok(RawImage2d {
 		data: buf.into(),
 		width: info.width,
 		height: info.height,
 		format: ClientFormat::U8U8U8, // 3 U8s because Alpha is stripped due to png crate limitations
-	};
-	
-	let tex = Texture2d::new(ctx, raw)
-		.map_err(|e| format!("Invalid png file ({}): {}", e, path.display()))?;
-	Ok(tex)
+	})
 }
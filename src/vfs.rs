@@ -1,7 +1,26 @@
 #[allow(unused_imports)]
 use prelude::*;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::env::current_exe;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Size & modification time of a file, to tell if it has changed since it was read
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FileStamp {
+	pub len: u64,
+	/// Modification time, since the Unix epoch
+	pub modified: Duration,
+}
+
+/// Gets the stamp of a file relative to the executable, or None if it can't be found.
+///
+/// This should be taken just before the file is read, so that changes made while it is being read are noticed.
+pub fn file_stamp<P>(rel_path: P) -> Option<FileStamp> where P: AsRef<Path> {
+	let meta = fs::metadata(canonicalize_exe(rel_path)).ok()?;
+	let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+	Some(FileStamp { len: meta.len(), modified: modified })
+}
 
 fn exe_dir() -> PathBuf {
 	current_exe().ok()
@@ -74,5 +74,6 @@ impl World {
 		}
 		r.unset_portals(f);
 		self.render_iter(r, f);
+		r.flush(f);
 	}
 }